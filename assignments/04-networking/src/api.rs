@@ -0,0 +1,1144 @@
+use std::str::FromStr;
+
+use crate::{
+    auth::{self, Role},
+    error::AspirinEatsError,
+    events,
+    food::{Order, OrderRequest, OrderStatus, OrderStatusUpdate},
+    http::{encode_query, etag_version, order_etag, HttpRequest, HttpResponse, Method},
+    openapi,
+    router::{Params, Router},
+    store::{OrderQuery, OrderSort, OrderStore, SavedResponse, SortDirection},
+};
+
+/// Largest page of orders that can be requested with `limit`
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Header a client can send with `POST /orders` so that retrying the request can't place the
+/// order twice
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Longest `Idempotency-Key` accepted
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Build the router for the Aspirin Eats API, serving orders from any kind of store
+pub fn router<S: OrderStore + 'static>() -> Router<S> {
+    Router::new()
+        .route(Method::Get, "/", welcome)
+        .route(Method::Get, "/menu", get_menu)
+        .route(Method::Get, "/menu/{version:int}", get_menu_version)
+        .route(Method::Get, "/orders", list_orders)
+        .route(Method::Post, "/orders", create_order)
+        .route(Method::Delete, "/orders", reset_orders)
+        .route(Method::Get, "/orders/{id:int}", get_order)
+        .route(Method::Patch, "/orders/{id:int}", update_order_status)
+        .route(Method::Delete, "/orders/{id:int}", remove_order)
+        .route(Method::Get, "/orders/{id:int}/events", order_events)
+        .route(Method::Get, "/orders/{id:int}/history", order_history)
+        .route(Method::Post, "/admin/purge", purge_orders)
+        .route(Method::Get, "/openapi.json", get_openapi)
+}
+
+fn welcome<S: OrderStore>(
+    _db: &S,
+    _request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    Ok(HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!"))
+}
+
+/// Get the OpenAPI document describing the API
+fn get_openapi<S: OrderStore>(
+    _db: &S,
+    _request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    HttpResponse::json(200, "OK", &openapi::spec())
+}
+
+fn get_menu<S: OrderStore>(
+    db: &S,
+    _request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    HttpResponse::json(200, "OK", &db.current_price_list()?)
+}
+
+fn get_menu_version<S: OrderStore>(
+    db: &S,
+    _request: &HttpRequest,
+    params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let prices = db
+        .get_price_list(params.parse("version")?)?
+        .ok_or(AspirinEatsError::NotFound)?;
+    HttpResponse::json(200, "OK", &prices)
+}
+
+/// List orders, optionally filtered with `customer`, `status`, `min_total` and `max_total`,
+/// sorted with `sort` (`id` or `total`, prefixed with `-` for descending) and paginated with
+/// `limit` and `offset`. The number of matching orders is sent in `X-Total-Count`, and a `Link`
+/// header points at the next page if there is one. Customers only ever see their own orders
+fn list_orders<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    let mut query = parse_order_query(request)?;
+    if let Role::Customer(customer) = role {
+        if query
+            .customer
+            .as_ref()
+            .is_some_and(|other| *other != customer)
+        {
+            return Err(AspirinEatsError::Forbidden);
+        }
+        query.customer = Some(customer);
+    }
+    let page = db.query_orders(&query)?;
+
+    let mut response = HttpResponse::json(200, "OK", &page.orders)?
+        .with_header("X-Total-Count", &page.total_count.to_string());
+    if let Some(next_offset) = page.next_offset {
+        let link = format!(
+            "</orders?{}>; rel=\"next\"",
+            order_query_string(&query, next_offset)
+        );
+        response = response.with_header("Link", &link);
+    }
+    Ok(response)
+}
+
+/// Build an OrderQuery from the query string of a request
+fn parse_order_query(request: &HttpRequest) -> Result<OrderQuery, AspirinEatsError> {
+    fn parse<T: FromStr>(request: &HttpRequest, name: &str) -> Result<Option<T>, AspirinEatsError> {
+        request
+            .query_param(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| AspirinEatsError::InvalidQuery(name.to_string()))
+            })
+            .transpose()
+    }
+
+    let status = request
+        .query_param("status")
+        .map(|status| {
+            serde_json::from_value::<OrderStatus>(status.into())
+                .map_err(|_| AspirinEatsError::InvalidQuery("status".to_string()))
+        })
+        .transpose()?;
+    let (sort, direction) = match request.query_param("sort").unwrap_or("id") {
+        "id" => (OrderSort::Id, SortDirection::Ascending),
+        "-id" => (OrderSort::Id, SortDirection::Descending),
+        "total" => (OrderSort::Total, SortDirection::Ascending),
+        "-total" => (OrderSort::Total, SortDirection::Descending),
+        _ => return Err(AspirinEatsError::InvalidQuery("sort".to_string())),
+    };
+    let limit = parse::<usize>(request, "limit")?;
+    if matches!(limit, Some(limit) if limit == 0 || limit > MAX_PAGE_SIZE) {
+        return Err(AspirinEatsError::InvalidQuery("limit".to_string()));
+    }
+
+    Ok(OrderQuery {
+        customer: request.query_param("customer").map(str::to_string),
+        status,
+        min_total: parse(request, "min_total")?,
+        max_total: parse(request, "max_total")?,
+        sort,
+        direction,
+        limit,
+        offset: parse(request, "offset")?.unwrap_or(0),
+    })
+}
+
+/// Build the query string that requests the given page of an OrderQuery
+pub(crate) fn order_query_string(query: &OrderQuery, offset: usize) -> String {
+    let mut params = Vec::new();
+    if let Some(customer) = &query.customer {
+        params.push(("customer", customer.clone()));
+    }
+    if let Some(status) = &query.status {
+        params.push(("status", format!("{:?}", status)));
+    }
+    if let Some(min_total) = query.min_total {
+        params.push(("min_total", min_total.to_string()));
+    }
+    if let Some(max_total) = query.max_total {
+        params.push(("max_total", max_total.to_string()));
+    }
+    let sort = match (query.sort, query.direction) {
+        (OrderSort::Id, SortDirection::Ascending) => "id",
+        (OrderSort::Id, SortDirection::Descending) => "-id",
+        (OrderSort::Total, SortDirection::Ascending) => "total",
+        (OrderSort::Total, SortDirection::Descending) => "-total",
+    };
+    params.push(("sort", sort.to_string()));
+    if let Some(limit) = query.limit {
+        params.push(("limit", limit.to_string()));
+    }
+    params.push(("offset", offset.to_string()));
+
+    encode_query(params.iter().map(|(key, value)| (*key, value.as_str())))
+}
+
+/// Get an order. Another customer's order is reported as not found, so customers can't find out
+/// which order IDs exist
+fn get_order<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    let order = owned_order(db, &role, params.parse("id")?)?;
+    // clients polling an order, and caches in front of the server, check back every time, but
+    // only get the order again once it has changed
+    let tag = order_etag(&order);
+    if is_none_match(request, &tag) {
+        return Ok(HttpResponse::new(304, "Not Modified", "")
+            .with_header("ETag", &tag)
+            .with_header("Cache-Control", "no-cache"));
+    }
+    Ok(HttpResponse::from(order).with_header("Cache-Control", "no-cache"))
+}
+
+/// Whether a request's `If-None-Match` header lists `tag`, or is `*`. Weak ETags match too, since
+/// the client only wants to know whether it already has the order
+fn is_none_match(request: &HttpRequest, tag: &str) -> bool {
+    request
+        .headers
+        .get_all("If-None-Match")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == tag)
+}
+
+/// Stream an order's status as server-sent events, starting with its current state. The stream
+/// ends once the order is completed, cancelled or deleted
+fn order_events<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    let id = params.parse("id")?;
+    let order = owned_order(db, &role, id)?;
+    let updates = (!order.status.is_final()).then(|| db.subscribe(id));
+    Ok(HttpResponse::new(200, "OK", "")
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        .with_stream(events::order_event_stream(order, updates)))
+}
+
+/// Get everything that has happened to an order, oldest first. Customers can see the history of
+/// their own orders, while staff can also see the history of orders that have been deleted
+fn order_history<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    let id = params.parse("id")?;
+    if let Role::Customer(_) = role {
+        owned_order(db, &role, id)?;
+    }
+    // orders placed before the history was kept exist without any events
+    let history = db.order_history(id)?;
+    if history.is_empty() && db.get_order(id)?.is_none() {
+        return Err(AspirinEatsError::NotFound);
+    }
+    HttpResponse::json(200, "OK", &history)
+}
+
+/// Load an order the sender of a request is allowed to see, failing with `NotFound` otherwise
+fn owned_order<S: OrderStore>(db: &S, role: &Role, id: i64) -> Result<Order, AspirinEatsError> {
+    db.get_order(id)?
+        .filter(|order| role.owns(order))
+        .ok_or(AspirinEatsError::NotFound)
+}
+
+/// Place an order. Customers can only place orders in their own name. If the request has an
+/// `Idempotency-Key` that was already used for the same request, the order isn't placed again and
+/// the original response is sent back with `Idempotency-Replayed: true`
+fn create_order<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let idempotency_key = idempotency_key(request)?;
+    // each customer has their own keys, while staff share theirs
+    let (scope, request_hash) = (role.to_string(), auth::sha256_hex(body.as_bytes()));
+    if let Some(key) = idempotency_key {
+        if let Some(response) = find_replay(db, &scope, key, &request_hash)? {
+            return Ok(response);
+        }
+    }
+
+    let order_request = OrderRequest::from_str(body)?;
+    if matches!(&role, Role::Customer(customer) if *customer != order_request.customer) {
+        return Err(AspirinEatsError::Forbidden);
+    }
+    let order = Order::from_request(order_request, &db.current_price_list()?)?;
+    let created = |id: i64| {
+        let mut order = order.clone();
+        order.id = Some(id);
+        Ok(HttpResponse::json(201, "Created", &order)?
+            .with_header("Location", &format!("/orders/{}", id))
+            .with_header("ETag", &order_etag(&order)))
+    };
+    let Some(key) = idempotency_key else {
+        let id = db.add_order(order.clone(), &role.to_string())?;
+        return created(id);
+    };
+
+    // only successes are saved, so a request that failed can be fixed and retried with its key
+    let save = |id: i64| {
+        let response = created(id)?;
+        Ok(SavedResponse {
+            request_hash: request_hash.clone(),
+            status_code: response.status_code(),
+            body: response.body().to_string(),
+            location: response.headers().get("Location").map(str::to_string),
+            etag: response.headers().get("ETag").map(str::to_string),
+        })
+    };
+    match db.add_order_idempotently(order.clone(), &role.to_string(), &scope, key, &save) {
+        Ok(id) => created(id),
+        // the same request was placed at the same time, and got there first
+        Err(AspirinEatsError::IdempotencyKeyReused) => find_replay(db, &scope, key, &request_hash)?
+            .ok_or(AspirinEatsError::IdempotencyKeyReused),
+        Err(err) => Err(err),
+    }
+}
+
+/// The saved response to send again for a request made with an `Idempotency-Key`, if there is
+/// one. Fails with `IdempotencyKeyReused` if the key was used for a different request
+fn find_replay<S: OrderStore>(
+    db: &S,
+    scope: &str,
+    key: &str,
+    request_hash: &str,
+) -> Result<Option<HttpResponse>, AspirinEatsError> {
+    match db.find_idempotent_response(scope, key)? {
+        Some(saved) if saved.request_hash != request_hash => {
+            Err(AspirinEatsError::IdempotencyKeyReused)
+        }
+        saved => Ok(saved.map(replay)),
+    }
+}
+
+/// The `Idempotency-Key` a request was sent with, if any
+fn idempotency_key(request: &HttpRequest) -> Result<Option<&str>, AspirinEatsError> {
+    let Some(key) = request.headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = key.trim();
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(AspirinEatsError::InvalidHeader(
+            IDEMPOTENCY_KEY_HEADER.to_string(),
+        ));
+    }
+    Ok(Some(key))
+}
+
+/// Send a saved response again
+fn replay(saved: SavedResponse) -> HttpResponse {
+    let status_text = match saved.status_code {
+        201 => "Created",
+        _ => "OK",
+    };
+    let mut response = HttpResponse::new(saved.status_code, status_text, &saved.body)
+        .with_header("Content-Type", "application/json")
+        .with_header("Idempotency-Replayed", "true");
+    if let Some(location) = saved.location {
+        response = response.with_header("Location", &location);
+    }
+    if let Some(etag) = saved.etag {
+        response = response.with_header("ETag", &etag);
+    }
+    response
+}
+
+/// Check an order against a request's `If-Match` header, which lists the ETags of the versions
+/// the client expects the order to be at, or is `*` to match any version. Fails with
+/// `PreconditionFailed` if the order is at another version, or `PreconditionRequired` if
+/// `required` is set and the request has no `If-Match`
+fn check_if_match(
+    request: &HttpRequest,
+    order: &Order,
+    required: bool,
+) -> Result<(), AspirinEatsError> {
+    let mut tags = request
+        .headers
+        .get_all("If-Match")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .peekable();
+    if tags.peek().is_none() {
+        return if required {
+            Err(AspirinEatsError::PreconditionRequired)
+        } else {
+            Ok(())
+        };
+    }
+    // weak ETags never match, since If-Match needs the exact same version. The estimate is left
+    // out, as it isn't something a change the client makes can conflict with
+    if tags.any(|tag| tag == "*" || etag_version(tag) == Some(order.version)) {
+        Ok(())
+    } else {
+        Err(AspirinEatsError::PreconditionFailed)
+    }
+}
+
+/// Change an order's status. Staff can make any allowed transition, while customers can only
+/// cancel their own orders. The request must have an `If-Match` header, so that a change made
+/// since the client last fetched the order isn't overwritten
+fn update_order_status<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    let id = params.parse("id")?;
+    let order = owned_order(db, &role, id)?;
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let update = OrderStatusUpdate::from_str(body)?;
+    if update.status != OrderStatus::Cancelled {
+        role.require_staff()?;
+    }
+    check_if_match(request, &order, true)?;
+    Ok(db
+        .update_status(id, update.status, Some(order.version), &role.to_string())?
+        .into())
+}
+
+fn reset_orders<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    role.require_staff()?;
+    db.reset_orders(&role.to_string())?;
+    Ok(HttpResponse::new(200, "OK", "All orders deleted"))
+}
+
+/// Permanently remove every deleted order. Only admins can do this, and each order purged gets a
+/// final event in its history saying who purged it
+fn purge_orders<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    _params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    role.require_admin()?;
+    let purged = db.purge_deleted_orders(&role.to_string())?;
+    Ok(HttpResponse::new(
+        200,
+        "OK",
+        &format!("{} deleted orders purged", purged),
+    ))
+}
+
+fn remove_order<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+    params: &Params,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let role = auth::authenticate(db, request)?;
+    role.require_staff()?;
+    let id = params.parse("id")?;
+    let order = owned_order(db, &role, id)?;
+    check_if_match(request, &order, false)?;
+    db.remove_order(id, &role.to_string())?;
+    Ok(HttpResponse::new(
+        200,
+        "OK",
+        &format!("Order {} deleted", id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::*;
+    use crate::{
+        food::{Estimate, MenuItem, OrderStatus},
+        http::StreamChunk,
+        kitchen::{Kitchen, ManualClock, DELIVERY_TIME},
+        menu::PriceList,
+        money::Money,
+        store::{MemoryStore, OrderEvent, OrderEventKind},
+    };
+
+    const ORDER_REQUEST: &str = r#"{"customer":"Amit","food":["Fries","Drink"]}"#;
+
+    const STAFF_TOKEN: &str = "staff-token";
+    const AMIT_TOKEN: &str = "amit-token";
+    const BEA_TOKEN: &str = "bea-token";
+    const ADMIN_TOKEN: &str = "admin-token";
+
+    /// An empty store with staff and admin keys, and keys for the customers Amit and Bea
+    fn test_store() -> MemoryStore {
+        let db = MemoryStore::new();
+        db.add_api_key(STAFF_TOKEN, &Role::Staff).unwrap();
+        db.add_api_key(ADMIN_TOKEN, &Role::Admin).unwrap();
+        db.add_api_key(AMIT_TOKEN, &Role::Customer("Amit".to_string()))
+            .unwrap();
+        db.add_api_key(BEA_TOKEN, &Role::Customer("Bea".to_string()))
+            .unwrap();
+        db
+    }
+
+    /// Send a request as staff
+    fn send(db: &MemoryStore, request: &str) -> HttpResponse {
+        send_as(db, Some(STAFF_TOKEN), request)
+    }
+
+    /// Send a request with the given bearer token, or with no credentials at all
+    fn send_as(db: &MemoryStore, token: Option<&str>, request: &str) -> HttpResponse {
+        let request = match token {
+            Some(token) => {
+                let (request_line, rest) = request.split_once("\r\n").unwrap();
+                format!(
+                    "{}\r\nAuthorization: Bearer {}\r\n{}",
+                    request_line, token, rest
+                )
+            }
+            None => request.to_string(),
+        };
+        router().handle(db, &HttpRequest::from_str(&request).unwrap())
+    }
+
+    #[test]
+    fn test_welcome() {
+        let db = test_store();
+        let response = send(&db, "GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), "Welcome to Aspirin Eats!");
+    }
+
+    #[test]
+    fn test_menu() {
+        let db = test_store();
+        let response = send(&db, "GET /menu HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(
+            PriceList::from_str(response.body()).unwrap(),
+            PriceList::default()
+        );
+
+        let mut menu: serde_json::Value = serde_json::to_value(PriceList::default()).unwrap();
+        menu["version"] = 2.into();
+        menu["drink"] = "2.50".into();
+        db.install_price_list(&PriceList::from_str(&menu.to_string()).unwrap())
+            .unwrap();
+
+        // new orders use the new prices, and the old price list is still available
+        let response = send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.price_list, 2);
+        assert_eq!(order.total, Money::from_cents(750));
+
+        let response = send(&db, "GET /menu/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            PriceList::from_str(response.body()).unwrap(),
+            PriceList::default()
+        );
+        assert_eq!(send(&db, "GET /menu/3 HTTP/1.1\r\n\r\n").status_code(), 404);
+    }
+
+    #[test]
+    fn test_promo_codes() {
+        let db = test_store();
+        let mut menu: serde_json::Value = serde_json::to_value(PriceList::default()).unwrap();
+        menu["version"] = 2.into();
+        menu["promo_codes"] = serde_json::json!({"TAKE2": {"Amount": 2}});
+        menu["tax_basis_points"] = 500.into();
+        db.install_price_list(&PriceList::from_str(&menu.to_string()).unwrap())
+            .unwrap();
+
+        let response = send(
+            &db,
+            "POST /orders HTTP/1.1\r\n\r\n\
+            {\"customer\": \"Amit\", \"food\": [\"Fries\"], \"promo_code\": \"TAKE2\"}",
+        );
+        assert_eq!(response.status_code(), 201);
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.breakdown.subtotal, Money::from_dollars(5));
+        assert_eq!(order.breakdown.discounts[0].amount, Money::from_dollars(2));
+        assert_eq!(order.breakdown.tax, Money::from_cents(15));
+        assert_eq!(order.total, Money::from_cents(315));
+        let response = send(&db, "GET /orders/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(Order::from_str(response.body()).unwrap(), order);
+
+        let response = send(
+            &db,
+            "POST /orders HTTP/1.1\r\n\r\n\
+            {\"customer\": \"Amit\", \"food\": [\"Fries\"], \"promo_code\": \"TAKE3\"}",
+        );
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(response.body(), "Unknown promo code: TAKE3");
+    }
+
+    #[test]
+    fn test_create_and_get_order() {
+        let db = test_store();
+        let response = send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(response.headers().get("Location"), Some("/orders/1"));
+
+        let response = send(&db, "GET /orders/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status_code(), 200);
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.id, Some(1));
+        assert_eq!(order.food, vec![MenuItem::Fries, MenuItem::Drink]);
+        assert_eq!(order.status, OrderStatus::Pending);
+
+        let response = send(&db, "GET /orders HTTP/1.1\r\n\r\n");
+        let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(orders, vec![order]);
+    }
+
+    #[test]
+    fn test_list_orders_query() {
+        let db = test_store();
+        for customer in ["Amit", "Bea", "Amit", "Amit"] {
+            send(
+                &db,
+                &format!(
+                    "POST /orders HTTP/1.1\r\n\r\n{{\"customer\":\"{}\",\"food\":[\"Fries\"]}}",
+                    customer
+                ),
+            );
+        }
+
+        let response = send(
+            &db,
+            "GET /orders?customer=Amit&sort=-id&limit=2 HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(response.status_code(), 200);
+        let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+        let ids: Vec<_> = orders.iter().map(|order| order.id.unwrap()).collect();
+        assert_eq!(ids, vec![4, 3]);
+        assert_eq!(response.headers().get("X-Total-Count"), Some("3"));
+        let link = response.headers().get("Link").unwrap();
+        assert_eq!(
+            link,
+            "</orders?customer=Amit&sort=-id&limit=2&offset=2>; rel=\"next\""
+        );
+
+        let next = link.trim_start_matches('<').split('>').next().unwrap();
+        let response = send(&db, &format!("GET {} HTTP/1.1\r\n\r\n", next));
+        let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+        let ids: Vec<_> = orders.iter().map(|order| order.id.unwrap()).collect();
+        assert_eq!(ids, vec![1]);
+        assert_eq!(response.headers().get("Link"), None);
+
+        let response = send(
+            &db,
+            "GET /orders?status=Pending&max_total=4 HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(response.body(), "[]");
+        assert_eq!(response.headers().get("X-Total-Count"), Some("0"));
+
+        for query in [
+            "status=Lost",
+            "sort=name",
+            "limit=0",
+            "limit=1000",
+            "offset=-1",
+            "min_total=x",
+        ] {
+            let response = send(&db, &format!("GET /orders?{} HTTP/1.1\r\n\r\n", query));
+            assert_eq!(response.status_code(), 400, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_update_order_status() {
+        let db = test_store();
+        send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+
+        let patch = |status: &str| {
+            send(
+                &db,
+                &format!(
+                    "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *\r\n\r\n{{\"status\":\"{}\"}}",
+                    status
+                ),
+            )
+        };
+        let response = patch("Preparing");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            Order::from_str(response.body()).unwrap().status,
+            OrderStatus::Preparing
+        );
+
+        assert_eq!(patch("Pending").status_code(), 409);
+        assert_eq!(patch("Cancelled").status_code(), 200);
+        assert_eq!(patch("Preparing").status_code(), 409);
+        assert_eq!(patch("Lost").status_code(), 400);
+        assert_eq!(
+            send(
+                &db,
+                "PATCH /orders/9 HTTP/1.1\r\n\r\n{\"status\":\"Preparing\"}"
+            )
+            .status_code(),
+            404
+        );
+    }
+
+    #[test]
+    fn test_delete_orders() {
+        let db = test_store();
+        for _ in 0..2 {
+            send(
+                &db,
+                &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+            );
+        }
+
+        assert_eq!(
+            send(&db, "DELETE /orders/1 HTTP/1.1\r\n\r\n").status_code(),
+            200
+        );
+        assert_eq!(
+            send(&db, "DELETE /orders/1 HTTP/1.1\r\n\r\n").status_code(),
+            404
+        );
+        assert_eq!(
+            send(&db, "DELETE /orders HTTP/1.1\r\n\r\n").status_code(),
+            200
+        );
+        assert_eq!(
+            send(&db, "GET /orders/2 HTTP/1.1\r\n\r\n").status_code(),
+            404
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let db = test_store();
+        assert_eq!(send(&db, "GET /drinks HTTP/1.1\r\n\r\n").status_code(), 404);
+        assert_eq!(
+            send(&db, "GET /orders/abc HTTP/1.1\r\n\r\n").status_code(),
+            404
+        );
+        let response = send(&db, "PUT /orders HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status_code(), 405);
+        assert_eq!(response.headers().get("Allow"), Some("GET, POST, DELETE"));
+        assert_eq!(
+            send(&db, "POST /orders/1 HTTP/1.1\r\n\r\n").status_code(),
+            405
+        );
+        assert_eq!(
+            send(&db, "POST /orders HTTP/1.1\r\n\r\n").status_code(),
+            400
+        );
+        assert_eq!(
+            send(&db, "POST /orders HTTP/1.1\r\n\r\n{\"customer\":1}").status_code(),
+            400
+        );
+    }
+
+    #[test]
+    fn test_authorization() {
+        let db = test_store();
+        let (amit, bea) = (Some(AMIT_TOKEN), Some(BEA_TOKEN));
+        let order = |customer: &str| {
+            format!(
+                "POST /orders HTTP/1.1\r\n\r\n{{\"customer\":\"{}\",\"food\":[\"Fries\"]}}",
+                customer
+            )
+        };
+
+        // the menu is public, but orders need a known token
+        assert_eq!(
+            send_as(&db, None, "GET /menu HTTP/1.1\r\n\r\n").status_code(),
+            200
+        );
+        let response = send_as(&db, None, "GET /orders HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(response.headers().get("WWW-Authenticate"), Some("Bearer"));
+        assert_eq!(
+            send_as(&db, Some("guess"), "GET /orders HTTP/1.1\r\n\r\n").status_code(),
+            401
+        );
+
+        // customers can only order for themselves
+        assert_eq!(send_as(&db, amit, &order("Amit")).status_code(), 201);
+        assert_eq!(send_as(&db, bea, &order("Bea")).status_code(), 201);
+        assert_eq!(send_as(&db, amit, &order("Bea")).status_code(), 403);
+        assert_eq!(send(&db, &order("Cy")).status_code(), 201);
+
+        // and only see their own orders
+        let list = |token, target: &str| {
+            let response = send_as(&db, token, &format!("GET {} HTTP/1.1\r\n\r\n", target));
+            let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+            orders
+                .iter()
+                .map(|order| order.id.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(list(amit, "/orders"), vec![1]);
+        assert_eq!(list(bea, "/orders?customer=Bea"), vec![2]);
+        assert_eq!(list(Some(STAFF_TOKEN), "/orders"), vec![1, 2, 3]);
+        assert_eq!(
+            send_as(&db, amit, "GET /orders?customer=Bea HTTP/1.1\r\n\r\n").status_code(),
+            403
+        );
+        assert_eq!(
+            send_as(&db, amit, "GET /orders/1 HTTP/1.1\r\n\r\n").status_code(),
+            200
+        );
+        assert_eq!(
+            send_as(&db, amit, "GET /orders/2 HTTP/1.1\r\n\r\n").status_code(),
+            404
+        );
+
+        // customers can cancel their own orders, but only staff can move them along
+        let patch = |token, id: i64, status: &str| {
+            let request = format!(
+                "PATCH /orders/{} HTTP/1.1\r\nIf-Match: *\r\n\r\n{{\"status\":\"{}\"}}",
+                id, status
+            );
+            send_as(&db, token, &request).status_code()
+        };
+        assert_eq!(patch(amit, 1, "Preparing"), 403);
+        assert_eq!(patch(amit, 2, "Cancelled"), 404);
+        assert_eq!(patch(amit, 1, "Cancelled"), 200);
+        assert_eq!(patch(Some(STAFF_TOKEN), 2, "Preparing"), 200);
+
+        // and only staff can delete orders
+        for request in ["DELETE /orders/1", "DELETE /orders"] {
+            let request = format!("{} HTTP/1.1\r\n\r\n", request);
+            assert_eq!(send_as(&db, amit, &request).status_code(), 403);
+        }
+        assert_eq!(
+            send(&db, "DELETE /orders HTTP/1.1\r\n\r\n").status_code(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_order_events() {
+        let db = test_store();
+        send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+        assert_eq!(
+            send_as(
+                &db,
+                Some(BEA_TOKEN),
+                "GET /orders/1/events HTTP/1.1\r\n\r\n"
+            )
+            .status_code(),
+            404
+        );
+
+        let response = send_as(
+            &db,
+            Some(AMIT_TOKEN),
+            "GET /orders/1/events HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(response.status_code(), 200);
+        assert!(response.is_event_stream());
+        let stream = response.stream().unwrap();
+        let timeout = Duration::from_millis(10);
+        let next_status = || match stream.next(timeout) {
+            StreamChunk::Data(data) => {
+                let event = String::from_utf8(data).unwrap();
+                let data = event.strip_prefix("event: status\ndata: ").unwrap();
+                Some(Order::from_str(data.trim_end()).unwrap().status)
+            }
+            _ => None,
+        };
+        assert_eq!(next_status(), Some(OrderStatus::Pending));
+        assert_eq!(next_status(), None);
+
+        send(
+            &db,
+            "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *\r\n\r\n{\"status\":\"Preparing\"}",
+        );
+        assert_eq!(next_status(), Some(OrderStatus::Preparing));
+        send(
+            &db,
+            "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *\r\n\r\n{\"status\":\"Cancelled\"}",
+        );
+        assert_eq!(next_status(), Some(OrderStatus::Cancelled));
+        assert_eq!(stream.next(timeout), StreamChunk::End);
+    }
+
+    #[test]
+    fn test_orders_can_be_fetched_conditionally() {
+        let db = test_store();
+        send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+        let get = |if_none_match: &str| {
+            send(
+                &db,
+                &format!("GET /orders/1 HTTP/1.1\r\n{}\r\n\r\n", if_none_match),
+            )
+        };
+
+        let response = get("X-None: 1");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.headers().get("Cache-Control"), Some("no-cache"));
+        for matching in ["\"1\"", "W/\"1\"", "\"9\", \"1\"", "*"] {
+            let response = get(&format!("If-None-Match: {}", matching));
+            assert_eq!(response.status_code(), 304);
+            assert_eq!(response.headers().get("ETag"), Some("\"1\""));
+            assert_eq!(response.body(), "");
+        }
+
+        // once the order changes, the client gets the new version
+        send(
+            &db,
+            "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *\r\n\r\n{\"status\":\"Preparing\"}",
+        );
+        let response = get("If-None-Match: \"1\"");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.headers().get("ETag"), Some("\"2\""));
+    }
+
+    #[test]
+    fn test_updates_need_the_current_version() {
+        let db = test_store();
+        let response = send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+        assert_eq!(response.headers().get("ETag"), Some("\"1\""));
+        let patch = |if_match: &str, status: &str| {
+            send(
+                &db,
+                &format!(
+                    "PATCH /orders/1 HTTP/1.1\r\n{}\r\n\r\n{{\"status\":\"{}\"}}",
+                    if_match, status
+                ),
+            )
+        };
+
+        assert_eq!(patch("X-None: 1", "Preparing").status_code(), 428);
+        let response = patch("If-Match: \"1\"", "Preparing");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.headers().get("ETag"), Some("\"2\""));
+        assert_eq!(Order::from_str(response.body()).unwrap().version, 2);
+
+        // a second client that fetched version 1 can't overwrite the change
+        assert_eq!(patch("If-Match: \"1\"", "Cancelled").status_code(), 412);
+        assert_eq!(patch("If-Match: W/\"2\"", "Cancelled").status_code(), 412);
+        assert_eq!(
+            patch("If-Match: \"1\", \"2\"", "Cancelled").status_code(),
+            200
+        );
+        let response = send(&db, "GET /orders/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(response.headers().get("ETag"), Some("\"3\""));
+
+        // deleting checks If-Match too, if it is given
+        assert_eq!(
+            send(&db, "DELETE /orders/1 HTTP/1.1\r\nIf-Match: \"2\"\r\n\r\n").status_code(),
+            412
+        );
+        assert_eq!(
+            send(&db, "DELETE /orders/1 HTTP/1.1\r\nIf-Match: \"3\"\r\n\r\n").status_code(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let db = test_store();
+        let post = |token: &str, key: &str, body: &str| {
+            send_as(
+                &db,
+                Some(token),
+                &format!(
+                    "POST /orders HTTP/1.1\r\nIdempotency-Key: {}\r\n\r\n{}",
+                    key, body
+                ),
+            )
+        };
+
+        let first = post(AMIT_TOKEN, "abc", ORDER_REQUEST);
+        assert_eq!(first.status_code(), 201);
+        assert_eq!(first.headers().get("Idempotency-Replayed"), None);
+        let retry = post(AMIT_TOKEN, "abc", ORDER_REQUEST);
+        assert_eq!(retry.status_code(), 201);
+        assert_eq!(retry.headers().get("Idempotency-Replayed"), Some("true"));
+        assert_eq!(retry.body(), first.body());
+        assert_eq!(retry.headers().get("Location"), Some("/orders/1"));
+        assert_eq!(retry.headers().get("ETag"), first.headers().get("ETag"));
+        assert!(retry.headers().get("ETag").is_some());
+
+        // the same key can't be used for a different order
+        let other = r#"{"customer":"Amit","food":["Drink"]}"#;
+        assert_eq!(post(AMIT_TOKEN, "abc", other).status_code(), 422);
+
+        // keys are separate for each customer, and failed requests aren't saved
+        let bea = r#"{"customer":"Bea","food":["Drink"]}"#;
+        assert_eq!(post(BEA_TOKEN, "abc", "{}").status_code(), 400);
+        assert_eq!(post(BEA_TOKEN, "abc", bea).status_code(), 201);
+        assert_eq!(post(AMIT_TOKEN, "def", ORDER_REQUEST).status_code(), 201);
+        assert_eq!(post(AMIT_TOKEN, " ", ORDER_REQUEST).status_code(), 400);
+
+        let response = send(&db, "GET /orders HTTP/1.1\r\n\r\n");
+        assert_eq!(response.headers().get("X-Total-Count"), Some("3"));
+    }
+
+    #[test]
+    fn test_order_history() {
+        let db = test_store();
+        let (amit, bea) = (Some(AMIT_TOKEN), Some(BEA_TOKEN));
+        for _ in 0..2 {
+            send_as(
+                &db,
+                amit,
+                &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+            );
+        }
+        send(
+            &db,
+            "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *\r\n\r\n{\"status\":\"Preparing\"}",
+        );
+        send_as(
+            &db,
+            amit,
+            "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *\r\n\r\n{\"status\":\"Cancelled\"}",
+        );
+        send(&db, "DELETE /orders/2 HTTP/1.1\r\n\r\n");
+
+        let history = |token, id: i64| {
+            let request = format!("GET /orders/{}/history HTTP/1.1\r\n\r\n", id);
+            let response = send_as(&db, token, &request);
+            match response.status_code() {
+                200 => Ok(serde_json::from_str::<Vec<OrderEvent>>(response.body())
+                    .unwrap()
+                    .into_iter()
+                    .map(|event| (event.kind, event.actor))
+                    .collect::<Vec<_>>()),
+                status => Err(status),
+            }
+        };
+        let event = |kind, actor: &str| (kind, actor.to_string());
+        assert_eq!(
+            history(amit, 1),
+            Ok(vec![
+                event(OrderEventKind::Created, "customer:Amit"),
+                event(OrderEventKind::StatusChanged, "staff"),
+                event(OrderEventKind::Cancelled, "customer:Amit"),
+            ])
+        );
+        assert_eq!(history(bea, 1), Err(404));
+        assert_eq!(history(amit, 9), Err(404));
+        assert_eq!(history(Some(STAFF_TOKEN), 9), Err(404));
+
+        // once deleted, only staff can see what happened to an order
+        assert_eq!(history(amit, 2), Err(404));
+        let deleted = vec![
+            event(OrderEventKind::Created, "customer:Amit"),
+            event(OrderEventKind::Deleted, "staff"),
+        ];
+        assert_eq!(history(Some(STAFF_TOKEN), 2), Ok(deleted.clone()));
+
+        // and only admins can purge deleted orders, which is recorded too
+        let purge = "POST /admin/purge HTTP/1.1\r\n\r\n";
+        assert_eq!(send_as(&db, amit, purge).status_code(), 403);
+        assert_eq!(send(&db, purge).status_code(), 403);
+        let response = send_as(&db, Some(ADMIN_TOKEN), purge);
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), "1 deleted orders purged");
+        assert_eq!(
+            history(Some(STAFF_TOKEN), 2),
+            Ok([deleted, vec![event(OrderEventKind::Purged, "admin")]].concat())
+        );
+        assert_eq!(
+            send(&db, "GET /orders/1 HTTP/1.1\r\n\r\n").status_code(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_estimates_keep_the_etag_from_placing_an_order_usable() {
+        let db = test_store();
+        let post = format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST);
+        send(&db, &post);
+        let placed = send(&db, &post);
+        let tag = placed.headers().get("ETag").unwrap().to_string();
+
+        // the first order keeps the kitchen busy, so the second just gets an estimate
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        Kitchen::new(Arc::new(ManualClock::new(start))).tick(&db);
+        let response = send(&db, "GET /orders/2 HTTP/1.1\r\n\r\n");
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert!(order.estimate.is_some());
+
+        // a copy without the estimate isn't current, but a change based on it is still allowed
+        assert_ne!(response.headers().get("ETag"), Some(tag.as_str()));
+        let get = format!("GET /orders/2 HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", tag);
+        assert_eq!(send(&db, &get).status_code(), 200);
+        let patch = format!(
+            "PATCH /orders/2 HTTP/1.1\r\nIf-Match: {}\r\n\r\n{{\"status\":\"Cancelled\"}}",
+            tag
+        );
+        assert_eq!(send(&db, &patch).status_code(), 200);
+    }
+
+    #[test]
+    fn test_estimates() {
+        let db = test_store();
+        send(
+            &db,
+            &format!("POST /orders HTTP/1.1\r\n\r\n{}", ORDER_REQUEST),
+        );
+        let response = send(&db, "GET /orders/1 HTTP/1.1\r\n\r\n");
+        assert!(!response.body().contains("estimate"));
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        Kitchen::new(Arc::new(ManualClock::new(start))).tick(&db);
+        let response = send(&db, "GET /orders/1 HTTP/1.1\r\n\r\n");
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.status, OrderStatus::Preparing);
+        let prep_time = MenuItem::Fries.prep_time() + MenuItem::Drink.prep_time();
+        assert_eq!(
+            order.estimate,
+            Some(Estimate {
+                ready_at: 1_000_000 + prep_time.as_secs() as i64,
+                delivered_at: 1_000_000 + (prep_time + DELIVERY_TIME).as_secs() as i64,
+            })
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt::{self, Write};
+
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::{error::AspirinEatsError, food::Order, http::HttpRequest, store::OrderStore};
+
+/// Header an API key can be sent in, as an alternative to `Authorization: Bearer <token>`
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Number of random bytes in a generated token
+const TOKEN_BYTES: usize = 32;
+
+/// What the holder of a token is allowed to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// Can place orders in their own name, and see and cancel only those orders
+    Customer(String),
+
+    /// Can see and change every order, and delete them
+    Staff,
+
+    /// Can do everything staff can, and also purge deleted orders for good
+    Admin,
+}
+
+impl Role {
+    /// Returns true if this role may see and cancel the given order
+    pub fn owns(&self, order: &Order) -> bool {
+        match self {
+            Role::Customer(customer) => *customer == order.customer,
+            Role::Staff | Role::Admin => true,
+        }
+    }
+
+    /// Fail with `Forbidden` unless this is a staff or admin role
+    pub fn require_staff(&self) -> Result<(), AspirinEatsError> {
+        match self {
+            Role::Staff | Role::Admin => Ok(()),
+            Role::Customer(_) => Err(AspirinEatsError::Forbidden),
+        }
+    }
+
+    /// Fail with `Forbidden` unless this is an admin role
+    pub fn require_admin(&self) -> Result<(), AspirinEatsError> {
+        match self {
+            Role::Admin => Ok(()),
+            Role::Customer(_) | Role::Staff => Err(AspirinEatsError::Forbidden),
+        }
+    }
+}
+
+/// Who holds the role, as recorded against the changes they make: `customer:<name>`, `staff` or
+/// `admin`
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Customer(customer) => write!(f, "customer:{}", customer),
+            Role::Staff => write!(f, "staff"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Generate a new random token, as a hex string
+pub fn generate_token() -> String {
+    let mut bytes = [0; TOKEN_BYTES];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate random token");
+    to_hex(&bytes)
+}
+
+/// Hash a token for storage, so the database never holds tokens that could be used directly.
+/// Tokens are long and random, so a single unsalted SHA-256 is enough to make them unguessable
+/// from their hash while still letting them be looked up by it
+pub fn hash_token(token: &str) -> String {
+    sha256_hex(token.as_bytes())
+}
+
+/// The SHA-256 hash of some data, as a hex string
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+/// Find out who sent a request from its `Authorization: Bearer` or `X-Api-Key` header. Fails
+/// with `Unauthorized` if the request has no token or the token isn't known
+pub fn authenticate<S: OrderStore>(
+    db: &S,
+    request: &HttpRequest,
+) -> Result<Role, AspirinEatsError> {
+    let token = request_token(request).ok_or(AspirinEatsError::Unauthorized)?;
+    db.find_api_key(token)?
+        .ok_or(AspirinEatsError::Unauthorized)
+}
+
+/// The token a request was sent with, if any
+pub(crate) fn request_token(request: &HttpRequest) -> Option<&str> {
+    let bearer = request.headers.get("Authorization").and_then(|value| {
+        let (scheme, token) = value.trim().split_once(' ')?;
+        scheme.eq_ignore_ascii_case("Bearer").then(|| token.trim())
+    });
+    bearer
+        .or_else(|| request.headers.get(API_KEY_HEADER).map(str::trim))
+        .filter(|token| !token.is_empty())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_tokens() {
+        let token = generate_token();
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert_ne!(token, generate_token());
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let db = MemoryStore::new();
+        let token = generate_token();
+        db.add_api_key(&token, &Role::Customer("Amit".to_string()))
+            .unwrap();
+        let authenticate = |header: &str| {
+            let request = format!("GET /orders HTTP/1.1\r\n{}\r\n\r\n", header);
+            authenticate(&db, &HttpRequest::from_str(&request).unwrap())
+        };
+
+        let amit = Role::Customer("Amit".to_string());
+        assert_eq!(
+            authenticate(&format!("Authorization: Bearer {}", token)).unwrap(),
+            amit
+        );
+        assert_eq!(
+            authenticate(&format!("Authorization: bearer  {} ", token)).unwrap(),
+            amit
+        );
+        assert_eq!(
+            authenticate(&format!("X-Api-Key: {}", token)).unwrap(),
+            amit
+        );
+        for header in [
+            "X-Other: 1".to_string(),
+            "Authorization: Bearer wrong".to_string(),
+            format!("Authorization: Basic {}", token),
+            "X-Api-Key: ".to_string(),
+        ] {
+            assert!(
+                matches!(authenticate(&header), Err(AspirinEatsError::Unauthorized)),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn test_roles() {
+        let amit = Role::Customer("Amit".to_string());
+        assert_eq!(amit.to_string(), "customer:Amit");
+        assert_eq!(Role::Admin.to_string(), "admin");
+
+        assert!(amit.require_staff().is_err());
+        assert!(Role::Staff.require_staff().is_ok());
+        assert!(Role::Admin.require_staff().is_ok());
+        assert!(Role::Staff.require_admin().is_err());
+        assert!(Role::Admin.require_admin().is_ok());
+    }
+}
@@ -1,9 +1,143 @@
-use aspirin_eats::db::AspirinEatsDb;
+use std::{
+    env,
+    net::TcpListener,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+};
 
-/// Change this path to match where you want to store the database file
-const DB_PATH: &str =
-    "/home/amit/Documents/code/aspirin/dev-aspirin/assignments/05-networking/aspirin_eats.db";
+use aspirin_eats::{
+    api,
+    auth::{self, Role},
+    config::{LogLevel, OriginConfig},
+    kitchen::{Kitchen, SystemClock},
+    menu::PriceList,
+    server,
+    store::OrderStore,
+};
+
+const USAGE: &str = "Usage: origin [options]
+       origin create-key admin|staff|customer <name> [options]
+
+create-key stores a new API key in the database and prints its token, which clients send as
+`Authorization: Bearer <token>`. Only a hash of the token is kept, so it can't be shown again.
+Staff can see, change and delete every order, and admins can also purge deleted orders.
+
+Options:
+    --config <path>              JSON file with any of the settings below, using _ for -
+    --bind <address>             address to listen on (default 127.0.0.1:8080)
+    --database <path>|:memory:   SQLite database file (default aspirin_eats.db)
+    --menu <path>                price list to load (default menu.json if it exists)
+    --log-level error|warn|info  info also logs every request (default info)
+    --workers <count>            connections with ordinary requests served at once (default 8).
+                                 Event streams don't hold a worker: each is sent on its own
+                                 thread, up to 256 at once and 4 per client
+    --idle-timeout <seconds>     (default 5)
+    --request-timeout <seconds>  time allowed to send a whole request (default 10)
+    --kitchen true|false         simulate the kitchen and drivers, moving orders along on their
+                                 own (default true)
+
+Every option can also be set with an environment variable such as ASPIRIN_EATS_BIND or
+ASPIRIN_EATS_LOG_LEVEL. Command line options take priority over environment variables, which
+take priority over the config file.";
 
 fn main() {
-    let db = AspirinEatsDb::from_path(DB_PATH).expect("Failed to open database");
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let (new_key, args) = match args.first().map(String::as_str) {
+        Some("create-key") => parse_role(&args[1..]).unwrap_or_else(|e| {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }),
+        _ => (None, &args[..]),
+    };
+    let config = OriginConfig::load(args, |name| env::var(name).ok()).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+
+    let db = config.open_database().unwrap_or_else(|e| {
+        eprintln!("Failed to open database {}: {}", config.database, e);
+        std::process::exit(1);
+    });
+    if let Some(role) = new_key {
+        let token = auth::generate_token();
+        db.add_api_key(&token, &role).unwrap_or_else(|e| {
+            eprintln!("Failed to store API key: {}", e);
+            std::process::exit(1);
+        });
+        println!("{}", token);
+        return;
+    }
+
+    let corrupt = db.verify_integrity().unwrap_or_else(|e| {
+        eprintln!("Failed to check database {}: {}", config.database, e);
+        std::process::exit(1);
+    });
+    if config.log_level >= LogLevel::Warn {
+        for corrupt in corrupt {
+            eprintln!("Warning: {}", corrupt);
+        }
+    }
+
+    let prices = match config.menu_path() {
+        Some(path) => PriceList::from_path(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => PriceList::default(),
+    };
+    if let Err(e) = db.install_price_list(&prices) {
+        eprintln!("Failed to install menu: {}", e);
+        std::process::exit(1);
+    }
+
+    let listener = TcpListener::bind(&config.bind).unwrap_or_else(|e| {
+        eprintln!("Failed to bind to {}: {}", config.bind, e);
+        std::process::exit(1);
+    });
+    if config.log_level >= LogLevel::Info {
+        println!("Listening on {}", config.bind);
+    }
+
+    let server_config = config.server_config();
+    server_config
+        .shutdown
+        .trigger_on_signals()
+        .expect("Failed to listen for signals");
+
+    // the database connection can only be used by one thread at a time, but reading and
+    // writing requests, which is where clients are slow, happens outside the lock
+    let db = Arc::new(Mutex::new(db));
+    let kitchen = config.kitchen.then(|| {
+        let (db, shutdown) = (db.clone(), server_config.shutdown.clone());
+        thread::spawn(move || Kitchen::new(Arc::new(SystemClock)).run(&db, &shutdown))
+    });
+    let router = api::router();
+    server::serve_pool(&listener, &server_config, config.workers, move |request| {
+        let db = db.lock().unwrap_or_else(PoisonError::into_inner);
+        router.handle(&*db, request)
+    });
+    if let Some(kitchen) = kitchen {
+        let _ = kitchen.join();
+    }
+    if config.log_level >= LogLevel::Info {
+        println!("Shut down");
+    }
+}
+
+/// Parse the role given to `create-key`, returning it along with the options that follow it
+fn parse_role(args: &[String]) -> Result<(Option<Role>, &[String]), String> {
+    match args {
+        [role, rest @ ..] if role == "staff" => Ok((Some(Role::Staff), rest)),
+        [role, rest @ ..] if role == "admin" => Ok((Some(Role::Admin), rest)),
+        [role, name, rest @ ..] if role == "customer" && !name.starts_with("--") => {
+            Ok((Some(Role::Customer(name.clone())), rest))
+        }
+        _ => {
+            Err("create-key needs a role: admin, staff, or customer followed by a name".to_string())
+        }
+    }
 }
@@ -1,12 +1,183 @@
-use std::env;
+use std::{
+    env,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use aspirin_eats::{
+    http::HttpRequest,
+    proxy::{Proxy, ProxyConfig},
+    server::{self, ServerConfig},
+    tls::TlsCertificates,
+};
+
+const USAGE: &str = "Usage: proxy <proxy-from> <proxy-to>... [options]
+
+Options:
+    --balance round-robin|least-connections
+    --health-interval <seconds>
+    --cache-size <bytes>            0 turns caching off
+    --cache-ttl <seconds>           for menu responses that set no max-age
+    --rate-limit <requests/second>  per client IP, 0 turns rate limiting off
+    --burst <requests>
+    --max-header-size <bytes>
+    --max-body-size <bytes>
+    --request-timeout <seconds>     time allowed to send a whole request
+    --idle-timeout <seconds>
+    --tls-cert <path>               PEM certificate chain, to accept HTTPS instead of HTTP
+    --tls-key <path>                PEM private key for the certificate
+    --tls-reload-interval <seconds> how often to check for a new certificate";
+
+/// Everything configured on the command line
+struct Args {
+    proxy_addr: String,
+    origin_addrs: Vec<SocketAddr>,
+    proxy: ProxyConfig,
+    server: ServerConfig,
+    tls: Option<TlsArgs>,
+}
+
+/// Where to load the TLS certificate from, and how often to check it for changes
+struct TlsArgs {
+    cert_path: String,
+    key_path: String,
+    reload_interval: Duration,
+}
 
 fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() < 3 {
-        eprintln!("Usage: {} <proxy-from> <proxy-to>", args[0]);
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let args = parse_args(&args).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
         std::process::exit(2);
+    });
+
+    let listener = TcpListener::bind(&args.proxy_addr).expect("Failed to bind to address");
+    println!(
+        "Proxying {}{} to {}",
+        if args.tls.is_some() {
+            "https://"
+        } else {
+            "http://"
+        },
+        args.proxy_addr,
+        args.origin_addrs
+            .iter()
+            .map(SocketAddr::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let proxy = Arc::new(Proxy::new(args.origin_addrs, args.proxy));
+    proxy.check_health();
+    proxy.spawn_health_checks();
+    args.server
+        .shutdown
+        .trigger_on_signals()
+        .expect("Failed to listen for signals");
+    let handler = move |request: &HttpRequest| proxy.handle(request);
+    match args.tls {
+        Some(tls) => {
+            let certificates = TlsCertificates::from_pem_files(&tls.cert_path, &tls.key_path)
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            let certificates = Arc::new(certificates);
+            certificates.spawn_reloader(tls.reload_interval);
+            server::serve_tls_concurrently(&listener, &args.server, certificates, handler);
+        }
+        None => server::serve_concurrently(&listener, &args.server, handler),
+    }
+}
+
+/// Parse the address to listen on, the upstream origin addresses and any options
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut proxy = ProxyConfig::default();
+    let mut server = ServerConfig {
+        access_log: true,
+        ..ServerConfig::default()
+    };
+    let (mut cert_path, mut key_path) = (None, None);
+    let mut tls_reload_interval = Duration::from_secs(10);
+    let mut addrs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .clone()
+            .next()
+            .filter(|_| arg.starts_with("--"))
+            .ok_or_else(|| format!("Missing value for {}", arg));
+        let seconds = || parse::<u64>(arg, value.clone()?).map(Duration::from_secs);
+        match arg.as_str() {
+            "--balance" => proxy.balancing = parse(arg, value?)?,
+            "--health-interval" => proxy.health_check_interval = seconds()?,
+            "--cache-size" => proxy.cache_size = parse(arg, value?)?,
+            "--cache-ttl" => proxy.cache_ttl = seconds()?,
+            "--rate-limit" => proxy.rate_limit = parse(arg, value?)?,
+            "--burst" => proxy.rate_limit_burst = parse(arg, value?)?,
+            "--max-header-size" => server.limits.max_header_size = parse(arg, value?)?,
+            "--max-body-size" => server.limits.max_body_size = parse(arg, value?)?,
+            "--request-timeout" => server.request_timeout = seconds()?,
+            "--idle-timeout" => server.idle_timeout = seconds()?,
+            "--tls-cert" => cert_path = Some(value?.clone()),
+            "--tls-key" => key_path = Some(value?.clone()),
+            "--tls-reload-interval" => tls_reload_interval = seconds()?,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => {
+                addrs.push(arg.clone());
+                continue;
+            }
+        }
+        args.next();
+    }
+
+    if addrs.len() < 2 {
+        return Err("Expected an address to listen on and at least one origin".to_string());
+    }
+    let proxy_addr = addrs.remove(0);
+    let origin_addrs = addrs
+        .iter()
+        .map(|addr| {
+            addr.to_socket_addrs()
+                .ok()
+                .and_then(|mut resolved| resolved.next())
+                .ok_or_else(|| format!("Invalid origin address {}", addr))
+        })
+        .collect::<Result<_, _>>()?;
+    if server.request_timeout.is_zero() || server.idle_timeout.is_zero() {
+        return Err("Timeouts must be at least one second".to_string());
     }
+    if proxy.health_check_interval.is_zero() || tls_reload_interval.is_zero() {
+        return Err("Intervals must be at least one second".to_string());
+    }
+    if !(proxy.rate_limit >= 0.0 && proxy.rate_limit.is_finite()) {
+        return Err("Rate limit can't be negative".to_string());
+    }
+
+    let tls = match (cert_path, key_path) {
+        (Some(cert_path), Some(key_path)) => Some(TlsArgs {
+            cert_path,
+            key_path,
+            reload_interval: tls_reload_interval,
+        }),
+        (None, None) => None,
+        _ => return Err("--tls-cert and --tls-key must be given together".to_string()),
+    };
+
+    Ok(Args {
+        proxy_addr,
+        origin_addrs,
+        proxy,
+        server,
+        tls,
+    })
+}
 
-    let proxy_addr = &args[1];
-    let origin_addr = &args[2];
+/// Parse the value of an option
+fn parse<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, option))
 }
@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    api::{self, IDEMPOTENCY_KEY_HEADER},
+    error::AspirinEatsError,
+    food::{Order, OrderRequest, OrderStatus},
+    http::{etag, Headers, HttpRequest, HttpResponse, Method, Version},
+    store::{OrderPage, OrderQuery},
+};
+
+/// How long the client waits for the server before giving up, by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error from a request made with a [`Client`]
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    /// Error when the server can't be reached, or sends back something the client can't read
+    #[error(transparent)]
+    Http(#[from] AspirinEatsError),
+
+    /// Error response from the server, holding its status code and the message in its body
+    #[error("{status}: {message}")]
+    Status { status: u16, message: String },
+}
+
+impl ClientError {
+    /// The status code of the error response, if the server sent one
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Status { status, .. } => Some(*status),
+            ClientError::Http(_) => None,
+        }
+    }
+}
+
+/// A blocking client for the Aspirin Eats API, such as an origin server or a proxy in front of
+/// one. Every request is sent on a connection of its own
+#[derive(Debug, Clone)]
+pub struct Client {
+    addr: SocketAddr,
+
+    /// The API key requests are sent with, if any
+    token: Option<String>,
+
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Self {
+        Client {
+            addr,
+            token: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Send every request with an API key
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Wait at most `timeout` when connecting to the server and for each read and write
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Place an order. If `idempotency_key` is given, retrying with the same key can't place the
+    /// order twice
+    pub fn create_order(
+        &self,
+        order: &OrderRequest,
+        idempotency_key: Option<&str>,
+    ) -> Result<Order, ClientError> {
+        let mut headers = Headers::new();
+        if let Some(key) = idempotency_key {
+            headers.insert(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        let body = serde_json::to_string(order).map_err(AspirinEatsError::from)?;
+        parse(&self.send(Method::Post, "/orders", headers, Some(body))?)
+    }
+
+    pub fn get_order(&self, id: i64) -> Result<Order, ClientError> {
+        let target = format!("/orders/{}", id);
+        parse(&self.send(Method::Get, &target, Headers::new(), None)?)
+    }
+
+    /// Get a page of the orders matching a query. Only `limit` orders are returned, starting at
+    /// `offset`, so the rest can be fetched by querying again with `next_offset`
+    pub fn list_orders(&self, query: &OrderQuery) -> Result<OrderPage, ClientError> {
+        let target = format!("/orders?{}", api::order_query_string(query, query.offset));
+        let response = self.send(Method::Get, &target, Headers::new(), None)?;
+        let total_count = response
+            .headers()
+            .get("X-Total-Count")
+            .and_then(|count| count.parse().ok())
+            .ok_or(AspirinEatsError::InvalidResponse)?;
+        let next_offset = query
+            .limit
+            .map(|limit| query.offset + limit)
+            .filter(|next| *next < total_count);
+        Ok(OrderPage {
+            orders: parse(&response)?,
+            total_count,
+            next_offset,
+        })
+    }
+
+    /// Change the status of an order, as long as it is still at `version`
+    pub fn update_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+        version: u32,
+    ) -> Result<Order, ClientError> {
+        let mut headers = Headers::new();
+        headers.insert("If-Match", &etag(version));
+        let target = format!("/orders/{}", id);
+        let body = format!("{{\"status\":{}}}", status);
+        parse(&self.send(Method::Patch, &target, headers, Some(body))?)
+    }
+
+    /// Send a request on a new connection and read the response, turning an error response into
+    /// `ClientError::Status`
+    fn send(
+        &self,
+        method: Method,
+        target: &str,
+        mut headers: Headers,
+        body: Option<String>,
+    ) -> Result<HttpResponse, ClientError> {
+        headers.insert("Host", &self.addr.to_string());
+        headers.insert("Connection", "close");
+        if let Some(token) = &self.token {
+            headers.insert("Authorization", &format!("Bearer {}", token));
+        }
+        if body.is_some() {
+            headers.insert("Content-Type", "application/json");
+        }
+        let request = HttpRequest {
+            method,
+            target: target.to_string(),
+            path: target.split('?').next().unwrap_or_default().to_string(),
+            query: HashMap::new(),
+            version: Version::Http11,
+            headers,
+            body,
+            remote_addr: None,
+            tls: false,
+        };
+
+        let mut stream =
+            TcpStream::connect_timeout(&self.addr, self.timeout).map_err(AspirinEatsError::from)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|_| stream.set_write_timeout(Some(self.timeout)))
+            .and_then(|_| request.write_to(&mut stream))
+            .map_err(AspirinEatsError::from)?;
+        let response = HttpResponse::read_response_to(&mut BufReader::new(stream), &method)?;
+        if response.status_code() >= 400 {
+            return Err(ClientError::Status {
+                status: response.status_code(),
+                message: response.body().trim().to_string(),
+            });
+        }
+        Ok(response)
+    }
+}
+
+/// Read the JSON body of a response
+fn parse<T: DeserializeOwned>(response: &HttpResponse) -> Result<T, ClientError> {
+    Ok(serde_json::from_str(response.body()).map_err(|_| AspirinEatsError::InvalidResponse)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, net::TcpListener, thread};
+
+    use super::*;
+    use crate::food::MenuItem;
+
+    /// Start a server that answers one connection with `response`, returning its address and the
+    /// request it received
+    fn respond_once(response: String) -> (SocketAddr, thread::JoinHandle<HttpRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request =
+                HttpRequest::read_from(&mut BufReader::new(stream.try_clone().unwrap())).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn test_requests() {
+        let order = r#"{"id":1,"customer":"Amit","food":["Fries"],"status":"Pending","total":5,"breakdown":{"items":[5],"subtotal":5,"discounts":[],"tax":0},"price_list":1,"version":1}"#;
+        let (addr, server) = respond_once(format!(
+            "HTTP/1.1 201 Created\r\nContent-Length: {}\r\n\r\n{}",
+            order.len(),
+            order
+        ));
+        let request = OrderRequest {
+            customer: "Amit".to_string(),
+            food: vec![MenuItem::Fries],
+            promo_code: None,
+        };
+        let created = Client::new(addr)
+            .with_token("amit-token")
+            .create_order(&request, Some("key-1"))
+            .unwrap();
+        assert_eq!(created.id, Some(1));
+
+        let request = server.join().unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.path, "/orders");
+        assert_eq!(
+            request.headers.get("Authorization"),
+            Some("Bearer amit-token")
+        );
+        assert_eq!(request.headers.get(IDEMPOTENCY_KEY_HEADER), Some("key-1"));
+        assert_eq!(
+            request.body.as_deref(),
+            Some(r#"{"customer":"Amit","food":["Fries"]}"#)
+        );
+    }
+
+    #[test]
+    fn test_list_orders() {
+        let (addr, server) = respond_once(
+            "HTTP/1.1 200 OK\r\nX-Total-Count: 3\r\nContent-Length: 2\r\n\r\n[]".to_string(),
+        );
+        let query = OrderQuery {
+            status: Some(OrderStatus::Pending),
+            limit: Some(1),
+            offset: 1,
+            ..OrderQuery::default()
+        };
+        let page = Client::new(addr).list_orders(&query).unwrap();
+        assert_eq!(page.total_count, 3);
+        assert_eq!(page.next_offset, Some(2));
+
+        let request = server.join().unwrap();
+        assert_eq!(request.query_param("status"), Some("Pending"));
+        assert_eq!(request.query_param("limit"), Some("1"));
+        assert_eq!(request.query_param("offset"), Some("1"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let (addr, server) = respond_once(
+            "HTTP/1.1 412 Precondition Failed\r\nContent-Length: 38\r\n\r\nOrder has changed since it was fetched"
+                .to_string(),
+        );
+        let error = Client::new(addr)
+            .update_status(1, OrderStatus::Cancelled, 2)
+            .unwrap_err();
+        assert_eq!(error.status(), Some(412));
+        assert_eq!(
+            error.to_string(),
+            "412: Order has changed since it was fetched"
+        );
+        let request = server.join().unwrap();
+        assert_eq!(request.headers.get("If-Match"), Some("\"2\""));
+        assert_eq!(request.body.as_deref(), Some(r#"{"status":"Cancelled"}"#));
+
+        // nothing listening
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let error = Client::new(addr).get_order(1).unwrap_err();
+        assert!(matches!(error, ClientError::Http(AspirinEatsError::Io(_))));
+        assert_eq!(error.status(), None);
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    fmt::Display,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{db::AspirinEatsDb, error::AspirinEatsError, server::ServerConfig};
+
+/// Database path that keeps the database in memory, so it is lost when the server stops
+pub const IN_MEMORY_DATABASE: &str = ":memory:";
+
+/// Prefix of the environment variables that override the config file
+pub const ENV_PREFIX: &str = "ASPIRIN_EATS_";
+
+/// Menu loaded at startup if no other is configured and it exists
+const DEFAULT_MENU_PATH: &str = "menu.json";
+
+/// How much the origin server writes to the console
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Only errors that stop the server or lose a connection
+    Error,
+
+    /// Errors, plus problems found in the database at startup
+    Warn,
+
+    /// Everything, including an access log line for every request
+    Info,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            _ => Err("expected error, warn or info".to_string()),
+        }
+    }
+}
+
+/// Error when the origin server's configuration can't be loaded or doesn't make sense
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file {0}: {1}")]
+    Read(PathBuf, #[source] std::io::Error),
+
+    #[error("Invalid config file {0}: {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+
+    #[error("Unknown option {0}")]
+    UnknownOption(String),
+
+    #[error("Missing value for {0}")]
+    MissingValue(String),
+
+    /// A setting has a value that can't be used. Holds the setting's name and what is wrong
+    #[error("Invalid {0}: {1}")]
+    Invalid(&'static str, String),
+}
+
+/// Settings for the origin server. These come from, in increasing order of priority: the
+/// defaults, a JSON config file, `ASPIRIN_EATS_*` environment variables, and command line options
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OriginConfig {
+    /// Address to listen on
+    pub bind: String,
+
+    /// Path of the SQLite database file, or `:memory:`
+    pub database: String,
+
+    /// Price list to load at startup. If unset, `menu.json` is used if it exists, or else the
+    /// bundled default menu
+    pub menu: Option<PathBuf>,
+    pub log_level: LogLevel,
+
+    /// Number of connections with ordinary requests served at once. Event streams are sent on
+    /// their own threads instead, capped by `ServerConfig::max_streams` and
+    /// `ServerConfig::max_streams_per_client`
+    pub workers: usize,
+
+    /// Seconds to wait for the next request on a connection before closing it
+    pub idle_timeout: u64,
+
+    /// Seconds a client has to send the whole of a request
+    pub request_timeout: u64,
+
+    /// Whether to run the simulated kitchen, which moves orders along on its own. Without it,
+    /// orders only change when someone changes them
+    pub kitchen: bool,
+}
+
+/// Names of the settings, as used for command line options (`--bind`) and, upper-cased with the
+/// prefix, for environment variables (`ASPIRIN_EATS_BIND`)
+const SETTINGS: [&str; 8] = [
+    "bind",
+    "database",
+    "menu",
+    "log-level",
+    "workers",
+    "idle-timeout",
+    "request-timeout",
+    "kitchen",
+];
+
+impl Default for OriginConfig {
+    fn default() -> Self {
+        OriginConfig {
+            bind: "127.0.0.1:8080".to_string(),
+            database: "aspirin_eats.db".to_string(),
+            menu: None,
+            log_level: LogLevel::Info,
+            workers: 8,
+            idle_timeout: 5,
+            request_timeout: 10,
+            kitchen: true,
+        }
+    }
+}
+
+impl OriginConfig {
+    /// Load the configuration from command line arguments and environment variables, where `env`
+    /// looks up a variable. A config file is read first if one is given with `--config` or
+    /// `ASPIRIN_EATS_CONFIG`. The result is validated before it is returned
+    pub fn load<E>(args: &[String], env: E) -> Result<Self, ConfigError>
+    where
+        E: Fn(&str) -> Option<String>,
+    {
+        let options = parse_options(args)?;
+        let config_path = options
+            .iter()
+            .rev()
+            .find(|(name, _)| name == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| env(&env_var("config")));
+        let mut config = match config_path {
+            Some(path) => OriginConfig::from_path(path)?,
+            None => OriginConfig::default(),
+        };
+
+        for setting in SETTINGS {
+            if let Some(value) = env(&env_var(setting)) {
+                config.set(setting, &value)?;
+            }
+        }
+        for (name, value) in &options {
+            if name != "config" {
+                config.set(name, value)?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read a JSON config file. Settings it leaves out keep their defaults
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        serde_json::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Change a setting from the text of a command line option or environment variable
+    fn set(&mut self, setting: &str, value: &str) -> Result<(), ConfigError> {
+        match setting {
+            "bind" => self.bind = value.to_string(),
+            "database" => self.database = value.to_string(),
+            "menu" => self.menu = Some(PathBuf::from(value)),
+            "log-level" => self.log_level = parse("log level", value)?,
+            "workers" => self.workers = parse("worker count", value)?,
+            "idle-timeout" => self.idle_timeout = parse("idle timeout", value)?,
+            "request-timeout" => self.request_timeout = parse("request timeout", value)?,
+            "kitchen" => self.kitchen = parse("kitchen", value)?,
+            _ => return Err(ConfigError::UnknownOption(format!("--{}", setting))),
+        }
+        Ok(())
+    }
+
+    /// Check that every setting can be used, so the server fails at startup rather than later
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.bind_addr()?;
+        if self.database.is_empty() {
+            return Err(invalid("database", "path is empty"));
+        }
+        if self.database != IN_MEMORY_DATABASE {
+            let dir = Path::new(&self.database)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty());
+            if dir.is_some_and(|dir| !dir.is_dir()) {
+                return Err(invalid(
+                    "database",
+                    format!("directory of {} doesn't exist", self.database),
+                ));
+            }
+        }
+        if let Some(menu) = &self.menu {
+            if !menu.is_file() {
+                return Err(invalid("menu", format!("{} doesn't exist", menu.display())));
+            }
+        }
+        if !(1..=1024).contains(&self.workers) {
+            return Err(invalid("worker count", "must be between 1 and 1024"));
+        }
+        if self.idle_timeout == 0 || self.request_timeout == 0 {
+            return Err(invalid("timeout", "must be at least one second"));
+        }
+        Ok(())
+    }
+
+    /// The address to listen on
+    pub fn bind_addr(&self) -> Result<SocketAddr, ConfigError> {
+        self.bind
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| invalid("bind address", format!("can't resolve {}", self.bind)))
+    }
+
+    /// Open the configured database, creating it if it doesn't exist
+    pub fn open_database(&self) -> Result<AspirinEatsDb, AspirinEatsError> {
+        let db = if self.database == IN_MEMORY_DATABASE {
+            AspirinEatsDb::in_memory()?
+        } else {
+            AspirinEatsDb::from_path(&self.database)?
+        };
+        Ok(db)
+    }
+
+    /// The menu file to load, if there is one
+    pub fn menu_path(&self) -> Option<&Path> {
+        match &self.menu {
+            Some(menu) => Some(menu),
+            None => Some(Path::new(DEFAULT_MENU_PATH)).filter(|path| path.is_file()),
+        }
+    }
+
+    /// Settings for serving connections
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig {
+            idle_timeout: Duration::from_secs(self.idle_timeout),
+            request_timeout: Duration::from_secs(self.request_timeout),
+            access_log: self.log_level >= LogLevel::Info,
+            ..ServerConfig::default()
+        }
+    }
+}
+
+/// Split `--name value` pairs into names and values
+fn parse_options(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut options = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .filter(|name| *name == "config" || SETTINGS.contains(name))
+            .ok_or_else(|| ConfigError::UnknownOption(arg.clone()))?;
+        let value = args
+            .next()
+            .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+        options.push((name.to_string(), value.clone()));
+    }
+    Ok(options)
+}
+
+/// The environment variable that overrides a setting
+fn env_var(setting: &str) -> String {
+    format!("{}{}", ENV_PREFIX, setting.to_uppercase().replace('-', "_"))
+}
+
+fn parse<T>(setting: &'static str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| invalid(setting, format!("{:?}: {}", value, e)))
+}
+
+fn invalid(setting: &'static str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid(setting, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn load(cli: &[&str], env: &[(&str, &str)]) -> Result<OriginConfig, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        OriginConfig::load(&args(cli), |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load(&[], &[]).unwrap();
+        assert_eq!(config, OriginConfig::default());
+        assert_eq!(config.bind_addr().unwrap().port(), 8080);
+        assert!(config.server_config().access_log);
+    }
+
+    #[test]
+    fn test_command_line_overrides_environment_overrides_file() {
+        let path = std::env::temp_dir().join(format!("origin-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"bind": "127.0.0.1:9000", "database": ":memory:", "workers": 2, "log_level": "warn"}"#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = load(
+            &["--config", path, "--workers", "4"],
+            &[
+                ("ASPIRIN_EATS_WORKERS", "3"),
+                ("ASPIRIN_EATS_IDLE_TIMEOUT", "30"),
+                ("ASPIRIN_EATS_KITCHEN", "false"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.bind, "127.0.0.1:9000");
+        assert_eq!(config.database, IN_MEMORY_DATABASE);
+        assert_eq!(config.log_level, LogLevel::Warn);
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.idle_timeout, 30);
+        assert!(!config.kitchen);
+        assert!(!config.server_config().access_log);
+        assert!(config.open_database().is_ok());
+
+        // the config file can come from the environment too
+        let config = load(&[], &[("ASPIRIN_EATS_CONFIG", path)]).unwrap();
+        assert_eq!(config.workers, 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let error = |cli: &[&str]| load(cli, &[]).unwrap_err().to_string();
+
+        assert_eq!(error(&["--port", "80"]), "Unknown option --port");
+        assert_eq!(error(&["--workers"]), "Missing value for --workers");
+        assert_eq!(
+            error(&["--workers", "many"]),
+            "Invalid worker count: \"many\": invalid digit found in string"
+        );
+        assert_eq!(
+            error(&["--workers", "0"]),
+            "Invalid worker count: must be between 1 and 1024"
+        );
+        assert_eq!(
+            error(&["--log-level", "loud"]),
+            "Invalid log level: \"loud\": expected error, warn or info"
+        );
+        assert_eq!(
+            error(&["--kitchen", "yes"]),
+            "Invalid kitchen: \"yes\": provided string was not `true` or `false`"
+        );
+        assert_eq!(
+            error(&["--request-timeout", "0"]),
+            "Invalid timeout: must be at least one second"
+        );
+        assert!(error(&["--bind", "not an address"]).starts_with("Invalid bind address"));
+        assert!(error(&["--database", "/no/such/dir/orders.db"]).starts_with("Invalid database"));
+        assert!(error(&["--menu", "/no/such/menu.json"]).starts_with("Invalid menu"));
+        assert!(error(&["--config", "/no/such/config.json"]).starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn test_config_file_must_only_have_known_settings() {
+        let path = std::env::temp_dir().join(format!("origin-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"port": 80}"#).unwrap();
+        let error = OriginConfig::from_path(&path).unwrap_err();
+        assert!(matches!(error, ConfigError::Parse(..)), "{}", error);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,135 +1,760 @@
-use std::path::Path;
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    path::Path,
+    str::FromStr,
+    sync::mpsc::Receiver,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use rusqlite::{Connection, Result};
+use rusqlite::{
+    params_from_iter,
+    types::{Type, Value},
+    Connection, OptionalExtension, Result, Row,
+};
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::food::*;
+use crate::{
+    auth::{hash_token, Role},
+    error::AspirinEatsError,
+    events::OrderEvents,
+    food::*,
+    menu::PriceList,
+    money::Money,
+    pricing::{Discount, PriceBreakdown},
+    store::{
+        check_new_price_list, check_status_change, OrderEvent, OrderEventKind, OrderPage,
+        OrderQuery, OrderSort, OrderStore, SavedResponse, SortDirection, IDEMPOTENCY_KEY_LIFETIME,
+    },
+};
+
+mod migrations;
+
+/// Most orders whose food is loaded in one query, keeping well under SQLite's limit on the
+/// number of parameters in a statement
+const MAX_ORDERS_PER_QUERY: usize = 500;
 
 pub struct AspirinEatsDb {
     conn: Connection,
+
+    /// Where changes to orders are published, for clients watching them
+    events: OrderEvents,
 }
 
 impl AspirinEatsDb {
     /// Create a new AspirinEatsDb instance from a given path
-    /// If the database does not exist, it will be created
+    /// If the database does not exist, it will be created. If it was created by an older version,
+    /// its schema is migrated to the current one
     pub fn from_path<P>(db_path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let db = Self {
-            conn: Connection::open(db_path)?,
-        };
-        db.create_table()?;
-        Ok(db)
+        Self::open(Connection::open(db_path)?)
     }
 
     /// Create a new AspirinEatsDb instance in memory. Useful for testing
     pub fn in_memory() -> Result<Self> {
-        let db = Self {
-            conn: Connection::open_in_memory()?,
+        Self::open(Connection::open_in_memory()?)
+    }
+
+    fn open(mut conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrations::migrate(&mut conn)?;
+        Ok(Self {
+            conn,
+            events: OrderEvents::new(),
+        })
+    }
+}
+
+impl OrderStore for AspirinEatsDb {
+    fn add_order(&self, order: Order, actor: &str) -> Result<i64, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let id = insert_order(&tx, &order, actor)?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(self.load_order(id)?)
+    }
+
+    fn query_orders(&self, query: &OrderQuery) -> Result<OrderPage, AspirinEatsError> {
+        let mut conditions = vec!["deleted_at IS NULL"];
+        let mut params: Vec<Value> = Vec::new();
+        if let Some(customer) = &query.customer {
+            conditions.push("customer = ?");
+            params.push(customer.clone().into());
+        }
+        if let Some(status) = &query.status {
+            conditions.push("status = ?");
+            params.push(variant_name(status)?.into());
+        }
+        if let Some(min_total) = query.min_total {
+            conditions.push("total_cents >= ?");
+            params.push(min_total.cents().into());
+        }
+        if let Some(max_total) = query.max_total {
+            conditions.push("total_cents <= ?");
+            params.push(max_total.cents().into());
+        }
+        let filter = format!("WHERE {}", conditions.join(" AND "));
+
+        let total_count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM orders {}", filter),
+            params_from_iter(&params),
+            |row| row.get(0),
+        )?;
+
+        // fetch one extra row to find out whether there is another page after this one
+        let column = match query.sort {
+            OrderSort::Id => "id",
+            OrderSort::Total => "total_cents",
+        };
+        let direction = match query.direction {
+            SortDirection::Ascending => "ASC",
+            SortDirection::Descending => "DESC",
+        };
+        let limit = query.limit.map_or(-1, |limit| limit as i64 + 1);
+        params.push(limit.into());
+        params.push((query.offset as i64).into());
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM orders {}
+            ORDER BY {} {}, id {} LIMIT ? OFFSET ?",
+            ORDER_COLUMNS, filter, column, direction, direction
+        ))?;
+        let mut orders = stmt
+            .query_map(params_from_iter(&params), order_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+
+        let next_offset = match query.limit {
+            Some(limit) if orders.len() > limit => {
+                orders.truncate(limit);
+                Some(query.offset + limit)
+            }
+            _ => None,
         };
-        db.create_table()?;
-        Ok(db)
+
+        Ok(OrderPage {
+            orders: self.with_food(orders)?,
+            total_count: total_count as usize,
+            next_offset,
+        })
     }
 
-    fn create_table(&self) -> Result<()> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS orders (
-            id	        INTEGER NOT NULL,
-            customer	TEXT NOT NULL,
-            food        TEXT NOT NULL,
-            status	    TEXT NOT NULL,
-            total       REAL NOT NULL,
-            PRIMARY KEY(id AUTOINCREMENT)
-        )",
-            [], // no params for this query
+    fn update_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+        expected_version: Option<u32>,
+        actor: &str,
+    ) -> Result<Order, AspirinEatsError> {
+        let mut order = self.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+        check_status_change(&order, &status, expected_version)?;
+
+        // the version check is repeated here in case the order changed since it was loaded
+        let tx = self.conn.unchecked_transaction()?;
+        let updated = tx.execute(
+            "UPDATE orders SET status = ?1, version = version + 1
+            WHERE id = ?2 AND version = ?3 AND deleted_at IS NULL",
+            (variant_name(&status)?, id, order.version),
         )?;
-        Ok(())
+        if updated == 0 {
+            return Err(AspirinEatsError::PreconditionFailed);
+        }
+        let kind = match status {
+            OrderStatus::Cancelled => OrderEventKind::Cancelled,
+            _ => OrderEventKind::StatusChanged,
+        };
+        record_event(&tx, id, kind, Some(&status), actor)?;
+        tx.commit()?;
+        order.status = status;
+        order.version += 1;
+        self.events.publish(&order);
+        Ok(order)
     }
-}
 
-impl AspirinEatsDb {
-    /// Insert a new Order into the database
-    pub fn add_order(&self, order: Order) -> Result<i64> {
+    fn unfinished_orders(&self, after: i64) -> Result<Vec<Order>, AspirinEatsError> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM orders
+            WHERE id > ?1 AND status NOT IN (?2, ?3) AND deleted_at IS NULL
+            ORDER BY id",
+            ORDER_COLUMNS
+        ))?;
+        let orders = stmt
+            .query_map(
+                (
+                    after,
+                    variant_name(&OrderStatus::Completed)?,
+                    variant_name(&OrderStatus::Cancelled)?,
+                ),
+                order_from_row,
+            )?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(self.with_food(orders)?)
+    }
+
+    fn set_estimate(&self, id: i64, estimate: Option<Estimate>) -> Result<(), AspirinEatsError> {
         self.conn.execute(
-            "INSERT INTO orders (customer, food, status, total) VALUES (?1, ?2, ?3, ?4)",
-            [
-                order.customer,
-                serde_json::to_string(&order.food).expect("Failed to serialize food"),
-                serde_json::to_string(&order.status).expect("Failed to serialize status"),
-                order.total.to_string(),
-            ],
+            "UPDATE orders SET ready_at = ?1, delivered_at = ?2
+            WHERE id = ?3 AND deleted_at IS NULL",
+            (
+                estimate.map(|estimate| estimate.ready_at),
+                estimate.map(|estimate| estimate.delivered_at),
+                id,
+            ),
         )?;
-        Ok(self.conn.last_insert_rowid())
+        Ok(())
     }
 
-    /// Get an order by ID from the database
-    pub fn get_order(&self, id: i64) -> Result<Option<Order>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT customer, food, status, total FROM orders WHERE id = ?1")?;
-        let mut rows = stmt.query([&id])?;
-
-        if let Some(row) = rows.next()? {
-            Ok(Some(Order {
-                id: Some(id),
-                customer: row.get(0)?,
-                food: {
-                    let food_str: String = row.get(1)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
-                },
-                status: {
-                    let status: String = row.get(2)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
-                },
-                total: row.get(3)?,
-            }))
-        } else {
-            Ok(None)
+    fn remove_order(&self, id: i64, actor: &str) -> Result<(), AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let deleted = tx.execute(
+            "UPDATE orders SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            (unix_time()?, id),
+        )?;
+        if deleted > 0 {
+            record_event(&tx, id, OrderEventKind::Deleted, None, actor)?;
         }
+        tx.commit()?;
+        self.events.close(id);
+        Ok(())
     }
 
-    /// Remove an order by ID from the database
-    pub fn remove_order(&self, id: i64) -> Result<()> {
-        self.conn
-            .execute("DELETE FROM orders WHERE id = ?1", [&id])?;
+    fn reset_orders(&self, actor: &str) -> Result<(), AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let now = unix_time()?;
+        tx.execute(
+            "INSERT INTO order_events (order_id, kind, actor, created_at)
+            SELECT id, ?1, ?2, ?3 FROM orders WHERE deleted_at IS NULL ORDER BY id",
+            (variant_name(&OrderEventKind::Deleted)?, actor, now),
+        )?;
+        tx.execute(
+            "UPDATE orders SET deleted_at = ?1 WHERE deleted_at IS NULL",
+            [now],
+        )?;
+        tx.commit()?;
+        self.events.close_all();
         Ok(())
     }
 
-    /// Remove all orders from the database
-    pub fn reset_orders(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM orders", [])?;
+    fn purge_deleted_orders(&self, actor: &str) -> Result<usize, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO order_events (order_id, kind, actor, created_at)
+            SELECT id, ?1, ?2, ?3 FROM orders WHERE deleted_at IS NOT NULL ORDER BY id",
+            (variant_name(&OrderEventKind::Purged)?, actor, unix_time()?),
+        )?;
+        let purged = tx.execute("DELETE FROM orders WHERE deleted_at IS NOT NULL", [])?;
+        tx.commit()?;
+        Ok(purged)
+    }
+
+    fn order_history(&self, id: i64) -> Result<Vec<OrderEvent>, AspirinEatsError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT kind, status, actor, created_at FROM order_events
+            WHERE order_id = ?1 ORDER BY id",
+        )?;
+        let events = stmt
+            .query_map([id], |row| {
+                Ok(OrderEvent {
+                    order_id: id,
+                    kind: decode(id, "event kind", row.get(0)?)?,
+                    status: row
+                        .get::<_, Option<String>>(1)?
+                        .map(|status| decode(id, "event status", status))
+                        .transpose()?,
+                    actor: row.get(2)?,
+                    timestamp: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+        Ok(events)
+    }
+
+    fn subscribe(&self, id: i64) -> Receiver<Order> {
+        self.events.subscribe(id)
+    }
+
+    fn install_price_list(&self, prices: &PriceList) -> Result<(), AspirinEatsError> {
+        let existing = self.get_price_list(prices.version())?;
+        if !check_new_price_list(prices, existing, &self.current_price_list()?)? {
+            return Ok(());
+        }
         self.conn.execute(
-            "UPDATE SQLITE_SEQUENCE SET SEQ='0' WHERE NAME='orders';",
-            [],
+            "INSERT INTO price_lists (version, prices) VALUES (?1, ?2)",
+            (prices.version(), serde_json::to_string(prices)?),
         )?;
         Ok(())
     }
 
-    /// Get all orders from the database
-    pub fn get_all_orders(&self) -> Result<Vec<Order>> {
-        let mut stmt = self
+    fn current_price_list(&self) -> Result<PriceList, AspirinEatsError> {
+        Ok(self.conn.query_row(
+            "SELECT prices FROM price_lists ORDER BY version DESC LIMIT 1",
+            [],
+            price_list_from_row,
+        )?)
+    }
+
+    fn get_price_list(&self, version: u32) -> Result<Option<PriceList>, AspirinEatsError> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT prices FROM price_lists WHERE version = ?1",
+                [version],
+                price_list_from_row,
+            )
+            .optional()?)
+    }
+
+    fn add_api_key(&self, token: &str, role: &Role) -> Result<(), AspirinEatsError> {
+        let (role, customer) = match role {
+            Role::Customer(customer) => ("Customer", Some(customer.as_str())),
+            Role::Staff => ("Staff", None),
+            Role::Admin => ("Admin", None),
+        };
+        let inserted = self.conn.execute(
+            "INSERT INTO api_keys (token_hash, role, customer) VALUES (?1, ?2, ?3)",
+            (hash_token(token), role, customer),
+        );
+        match inserted {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+            {
+                Err(AspirinEatsError::DuplicateApiKey)
+            }
+            inserted => {
+                inserted?;
+                Ok(())
+            }
+        }
+    }
+
+    fn find_api_key(&self, token: &str) -> Result<Option<Role>, AspirinEatsError> {
+        Ok(self
             .conn
-            .prepare("SELECT id, customer, food, status, total FROM orders")?;
-
-        let order_iter = stmt.query_map([], |row| {
-            Ok(Order {
-                id: row.get(0)?,
-                customer: row.get(1)?,
-                food: {
-                    let food_str: String = row.get(2)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
+            .query_row(
+                "SELECT role, customer FROM api_keys WHERE token_hash = ?1",
+                [hash_token(token)],
+                |row| {
+                    let role: String = row.get(0)?;
+                    // anything unexpected is an error rather than a guess, so a bad row can't
+                    // grant more access than it was meant to
+                    match (role.as_str(), row.get(1)?) {
+                        ("Customer", Some(customer)) => Ok(Role::Customer(customer)),
+                        ("Staff", None) => Ok(Role::Staff),
+                        ("Admin", None) => Ok(Role::Admin),
+                        _ => Err(rusqlite::Error::FromSqlConversionFailure(
+                            0,
+                            Type::Text,
+                            format!("invalid API key role: {}", role).into(),
+                        )),
+                    }
                 },
-                status: {
-                    let status: String = row.get(3)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
+            )
+            .optional()?)
+    }
+
+    fn find_idempotent_response(
+        &self,
+        scope: &str,
+        key: &str,
+    ) -> Result<Option<SavedResponse>, AspirinEatsError> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT request_hash, status_code, body, location, etag FROM idempotency_keys
+                WHERE scope = ?1 AND key = ?2 AND created_at > ?3",
+                (
+                    scope,
+                    key,
+                    unix_time()? - IDEMPOTENCY_KEY_LIFETIME.as_secs() as i64,
+                ),
+                |row| {
+                    Ok(SavedResponse {
+                        request_hash: row.get(0)?,
+                        status_code: row.get(1)?,
+                        body: row.get(2)?,
+                        location: row.get(3)?,
+                        etag: row.get(4)?,
+                    })
                 },
-                total: row.get(4)?,
+            )
+            .optional()?)
+    }
+
+    fn add_order_idempotently(
+        &self,
+        order: Order,
+        actor: &str,
+        scope: &str,
+        key: &str,
+        respond: &dyn Fn(i64) -> Result<SavedResponse, AspirinEatsError>,
+    ) -> Result<i64, AspirinEatsError> {
+        let now = unix_time()?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM idempotency_keys WHERE created_at <= ?1",
+            [now - IDEMPOTENCY_KEY_LIFETIME.as_secs() as i64],
+        )?;
+        let id = insert_order(&tx, &order, actor)?;
+        let response = respond(id)?;
+        // dropping the transaction without committing it takes the order back out
+        let saved = tx.execute(
+            "INSERT OR IGNORE INTO idempotency_keys
+                (scope, key, request_hash, status_code, body, location, etag, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                scope,
+                key,
+                &response.request_hash,
+                response.status_code,
+                &response.body,
+                &response.location,
+                &response.etag,
+                now,
+            ),
+        )?;
+        if saved == 0 {
+            return Err(AspirinEatsError::IdempotencyKeyReused);
+        }
+        tx.commit()?;
+        Ok(id)
+    }
+}
+
+impl AspirinEatsDb {
+    /// Get all orders from the database, apart from deleted ones
+    pub fn get_all_orders(&self) -> Result<Vec<Order>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM orders WHERE deleted_at IS NULL",
+            ORDER_COLUMNS
+        ))?;
+
+        let orders = stmt
+            .query_map([], order_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+        self.with_food(orders)
+    }
+
+    /// Try to decode every order in the database, returning the ones that are corrupt. A corrupt
+    /// order makes any request that loads it fail, so this can be used to find and repair them
+    pub fn verify_integrity(&self) -> Result<Vec<CorruptOrder>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM orders WHERE deleted_at IS NULL ORDER BY id")?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut corrupt = Vec::new();
+        for id in ids {
+            match self.load_order(id) {
+                Ok(_) => {}
+                Err(rusqlite::Error::FromSqlConversionFailure(_, _, cause))
+                    if cause.is::<CorruptOrder>() =>
+                {
+                    let cause = cause
+                        .downcast::<CorruptOrder>()
+                        .expect("cause was checked to be a CorruptOrder");
+                    corrupt.push(*cause);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Load an order that hasn't been deleted. Unlike `get_order`, an order that fails to decode
+    /// is reported as the underlying rusqlite error
+    fn load_order(&self, id: i64) -> Result<Option<Order>> {
+        let order = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM orders WHERE id = ?1 AND deleted_at IS NULL",
+                    ORDER_COLUMNS
+                ),
+                [&id],
+                order_from_row,
+            )
+            .optional()?;
+        match order {
+            Some(order) => Ok(self.with_food(vec![order])?.pop()),
+            None => Ok(None),
+        }
+    }
+
+    /// Fill in the food, item prices and discounts of orders loaded by `order_from_row`. They are
+    /// loaded for many orders in each query, rather than with a query per order
+    fn with_food(&self, orders: Vec<(i64, Order)>) -> Result<Vec<Order>> {
+        let mut items: HashMap<i64, Vec<ItemRow>> = HashMap::new();
+        let mut discounts: HashMap<i64, Vec<Discount>> = HashMap::new();
+        for chunk in orders.chunks(MAX_ORDERS_PER_QUERY) {
+            let ids: Vec<i64> = chunk.iter().map(|(id, _)| *id).collect();
+            let placeholders = vec!["?"; ids.len()].join(", ");
+
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "SELECT i.order_id, i.id, i.kind, i.bun, i.patty, t.topping, i.price_cents
+                FROM order_items i LEFT JOIN toppings t ON t.item_id = i.id
+                WHERE i.order_id IN ({})
+                ORDER BY i.order_id, i.position, t.position",
+                placeholders
+            ))?;
+            let mut rows = stmt.query(params_from_iter(&ids))?;
+
+            // each item appears once per topping, so group the rows back up by item
+            while let Some(row) = rows.next()? {
+                let order_id: i64 = row.get(0)?;
+                let item_id: i64 = row.get(1)?;
+                let order_items = items.entry(order_id).or_default();
+                if order_items.last().map(|item| item.id) != Some(item_id) {
+                    order_items.push(ItemRow {
+                        id: item_id,
+                        kind: row.get(2)?,
+                        bun: row.get(3)?,
+                        patty: row.get(4)?,
+                        toppings: Vec::new(),
+                        price_cents: row.get(6)?,
+                    });
+                }
+                if let (Some(item), Some(topping)) =
+                    (order_items.last_mut(), row.get::<_, Option<String>>(5)?)
+                {
+                    item.toppings.push(decode(order_id, "topping", topping)?);
+                }
+            }
+
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "SELECT order_id, description, amount_cents FROM order_discounts
+                WHERE order_id IN ({}) ORDER BY order_id, position",
+                placeholders
+            ))?;
+            let mut rows = stmt.query(params_from_iter(&ids))?;
+            while let Some(row) = rows.next()? {
+                discounts.entry(row.get(0)?).or_default().push(Discount {
+                    description: row.get(1)?,
+                    amount: Money::from_cents(row.get(2)?),
+                });
+            }
+        }
+
+        orders
+            .into_iter()
+            .map(|(id, mut order)| {
+                let items = items.remove(&id).unwrap_or_default();
+                order.breakdown.items = items
+                    .iter()
+                    .map(|item| {
+                        item.price_cents
+                            .map(Money::from_cents)
+                            .ok_or_else(|| corrupt(id, "item has no price"))
+                    })
+                    .collect::<Result<_>>()?;
+                order.food = items
+                    .into_iter()
+                    .map(|item| match (item.kind.as_str(), item.bun, item.patty) {
+                        ("Burger", Some(bun), Some(patty)) => Ok(MenuItem::Burger(Burger::new(
+                            decode(id, "bun", bun)?,
+                            decode(id, "patty", patty)?,
+                            item.toppings,
+                        ))),
+                        ("Burger", _, _) => Err(corrupt(id, "burger is missing its bun or patty")),
+                        ("Fries", _, _) => Ok(MenuItem::Fries),
+                        ("Drink", _, _) => Ok(MenuItem::Drink),
+                        (kind, _, _) => Err(corrupt(id, format!("unknown menu item {}", kind))),
+                    })
+                    .collect::<Result<_>>()?;
+                order.breakdown.discounts = discounts.remove(&id).unwrap_or_default();
+                Ok(order)
             })
-        })?;
+            .collect()
+    }
+}
+
+/// A row of the order_items table, along with its toppings
+struct ItemRow {
+    id: i64,
+    kind: String,
+    bun: Option<String>,
+    patty: Option<String>,
+    toppings: Vec<Topping>,
+    price_cents: Option<i64>,
+}
+
+/// Columns selected from the orders table to build an Order with `order_from_row`
+const ORDER_COLUMNS: &str = "id, customer, status, total_cents, price_list, subtotal_cents, \
+    tax_cents, version, ready_at, delivered_at";
+
+/// Build an Order from a row of ORDER_COLUMNS, along with its ID. The food, item prices and
+/// discounts are loaded separately, with `with_food`
+fn order_from_row(row: &Row) -> Result<(i64, Order)> {
+    let id = row.get(0)?;
+    let order = Order {
+        id: Some(id),
+        customer: row.get(1)?,
+        food: Vec::new(),
+        status: decode(id, "status", row.get(2)?)?,
+        total: Money::from_cents(row.get(3)?),
+        price_list: row.get(4)?,
+        breakdown: PriceBreakdown {
+            items: Vec::new(),
+            subtotal: Money::from_cents(row.get(5)?),
+            discounts: Vec::new(),
+            tax: Money::from_cents(row.get(6)?),
+        },
+        version: row.get(7)?,
+        estimate: match (row.get(8)?, row.get(9)?) {
+            (Some(ready_at), Some(delivered_at)) => Some(Estimate {
+                ready_at,
+                delivered_at,
+            }),
+            _ => None,
+        },
+    };
+    Ok((id, order))
+}
+
+/// Build a PriceList from a row holding its JSON
+fn price_list_from_row(row: &Row) -> Result<PriceList> {
+    let prices: String = row.get(0)?;
+    PriceList::from_str(&prices)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, e.into()))
+}
+
+/// Insert a new order, with its food and pricing, and record that `actor` created it
+fn insert_order(tx: &Connection, order: &Order, actor: &str) -> Result<i64> {
+    tx.execute(
+        "INSERT INTO orders (customer, status, total_cents, price_list, subtotal_cents, tax_cents)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            &order.customer,
+            variant_name(&order.status)?,
+            order.total.cents(),
+            order.price_list,
+            order.breakdown.subtotal.cents(),
+            order.breakdown.tax.cents(),
+        ),
+    )?;
+    let id = tx.last_insert_rowid();
+    let item_ids = insert_food(tx, id, &order.food)?;
+    for (item_id, price) in item_ids.into_iter().zip(&order.breakdown.items) {
+        tx.execute(
+            "UPDATE order_items SET price_cents = ?1 WHERE id = ?2",
+            (price.cents(), item_id),
+        )?;
+    }
+    for (position, discount) in order.breakdown.discounts.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO order_discounts (order_id, position, description, amount_cents)
+            VALUES (?1, ?2, ?3, ?4)",
+            (id, position, &discount.description, discount.amount.cents()),
+        )?;
+    }
+    record_event(tx, id, OrderEventKind::Created, Some(&order.status), actor)?;
+    Ok(id)
+}
+
+/// Insert the food of an order into the order_items and toppings tables, returning the ID of each
+/// item's row
+fn insert_food(conn: &Connection, order_id: i64, food: &[MenuItem]) -> Result<Vec<i64>> {
+    let mut item_ids = Vec::with_capacity(food.len());
+    for (position, item) in food.iter().enumerate() {
+        let (kind, bun, patty, toppings) = match item {
+            MenuItem::Burger(burger) => (
+                "Burger",
+                Some(variant_name(burger.bun())),
+                Some(variant_name(burger.patty())),
+                burger.toppings(),
+            ),
+            MenuItem::Fries => ("Fries", None, None, &[][..]),
+            MenuItem::Drink => ("Drink", None, None, &[][..]),
+        };
+        let (bun, patty) = (bun.transpose()?, patty.transpose()?);
+        conn.execute(
+            "INSERT INTO order_items (order_id, position, kind, bun, patty)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            (order_id, position, kind, bun, patty),
+        )?;
 
-        Ok(order_iter.map(Result::unwrap).collect())
+        let item_id = conn.last_insert_rowid();
+        item_ids.push(item_id);
+        for (position, topping) in toppings.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO toppings (item_id, position, topping) VALUES (?1, ?2, ?3)",
+                (item_id, position, variant_name(topping)?),
+            )?;
+        }
     }
+    Ok(item_ids)
+}
+
+/// The current time in seconds since the Unix epoch
+fn unix_time() -> Result<i64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+}
+
+/// Append an event to an order's history
+fn record_event(
+    conn: &Connection,
+    order_id: i64,
+    kind: OrderEventKind,
+    status: Option<&OrderStatus>,
+    actor: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO order_events (order_id, kind, status, actor, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        (
+            order_id,
+            variant_name(&kind)?,
+            status.map(variant_name).transpose()?,
+            actor,
+            unix_time()?,
+        ),
+    )?;
+    Ok(())
+}
+
+/// The name of a unit enum variant, which is how statuses and burger parts are stored
+fn variant_name<T: Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => Ok(name),
+        Ok(other) => Err(rusqlite::Error::ToSqlConversionFailure(
+            format!("{} is not a unit enum variant", other).into(),
+        )),
+        Err(e) => Err(rusqlite::Error::ToSqlConversionFailure(e.into())),
+    }
+}
+
+/// Convert a name stored with `variant_name` back into its enum variant
+fn decode<T: DeserializeOwned>(order_id: i64, field: &str, name: String) -> Result<T> {
+    serde_json::from_value(serde_json::Value::String(name))
+        .map_err(|e| corrupt(order_id, format!("invalid {}: {}", field, e)))
+}
+
+/// Error for an order that is in the database but can't be decoded
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("Order {order_id} is corrupt: {reason}")]
+pub struct CorruptOrder {
+    pub order_id: i64,
+    pub reason: String,
+}
+
+/// Build the rusqlite error reported when an order fails to decode. This is turned into
+/// `AspirinEatsError::CorruptOrder` when converted to an AspirinEatsError
+fn corrupt(order_id: i64, reason: impl ToString) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(
+        0,
+        Type::Text,
+        Box::new(CorruptOrder {
+            order_id,
+            reason: reason.to_string(),
+        }),
+    )
 }
 
 #[cfg(test)]
@@ -137,13 +762,41 @@ mod tests {
     use super::*;
 
     fn get_test_order() -> Order {
-        Order {
-            id: None,
+        test_order(vec![MenuItem::Fries, MenuItem::Drink])
+    }
+
+    fn test_order(food: Vec<MenuItem>) -> Order {
+        Order::try_from(OrderRequest {
             customer: "Amit".to_string(),
-            food: vec![MenuItem::Fries, MenuItem::Drink],
-            status: OrderStatus::Pending,
-            total: 8.0,
-        }
+            food,
+            promo_code: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_burger_round_trip() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let mut order = test_order(vec![
+            MenuItem::Burger(Burger::new(Bun::Plain, Patty::Beef, vec![])),
+            MenuItem::Fries,
+            MenuItem::Burger(Burger::new(
+                Bun::GlutenFree,
+                Patty::Chicken,
+                vec![Topping::Bacon, Topping::Lettuce, Topping::Bacon],
+            )),
+        ]);
+
+        order.id = Some(db.add_order(order.clone(), "staff").unwrap());
+        assert_eq!(db.get_order(order.id.unwrap()).unwrap().unwrap(), order);
+
+        db.remove_order(order.id.unwrap(), "staff").unwrap();
+        db.purge_deleted_orders("admin").unwrap();
+        let items: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM order_items", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(items, 0);
     }
 
     #[test]
@@ -151,46 +804,457 @@ mod tests {
         let db = AspirinEatsDb::in_memory().unwrap();
         let mut order = get_test_order();
 
-        order.id = Some(db.add_order(order.clone()).unwrap());
+        order.id = Some(db.add_order(order.clone(), "staff").unwrap());
 
         let got = db.get_order(order.id.unwrap()).unwrap().unwrap();
         assert_eq!(got, order);
     }
 
+    #[test]
+    fn test_breakdown_round_trip() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let mut menu = serde_json::to_value(PriceList::default()).unwrap();
+        menu["version"] = 2.into();
+        menu["combos"] = serde_json::json!([
+            {"name": "Snack", "items": ["Fries", "Drink"], "discount": 1}
+        ]);
+        menu["promo_codes"] = serde_json::json!({"HALF": {"Percent": 50}});
+        menu["tax_basis_points"] = 1000.into();
+        let prices = PriceList::from_str(&menu.to_string()).unwrap();
+
+        let request = OrderRequest {
+            customer: "Amit".to_string(),
+            food: vec![MenuItem::Fries, MenuItem::Drink, MenuItem::Fries],
+            promo_code: Some("HALF".to_string()),
+        };
+        let mut order = Order::from_request(request, &prices).unwrap();
+        assert_eq!(order.breakdown.discounts.len(), 2);
+        order.id = Some(db.add_order(order.clone(), "staff").unwrap());
+        assert_eq!(db.get_order(order.id.unwrap()).unwrap().unwrap(), order);
+
+        db.remove_order(order.id.unwrap(), "staff").unwrap();
+        db.purge_deleted_orders("admin").unwrap();
+        let discounts: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM order_discounts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(discounts, 0);
+    }
+
     #[test]
     fn test_get_all_orders() {
         let db = AspirinEatsDb::in_memory().unwrap();
         let mut order1 = get_test_order();
         let mut order2 = get_test_order();
 
-        order1.id = Some(db.add_order(order1.clone()).unwrap());
-        order2.id = Some(db.add_order(order2.clone()).unwrap());
+        order1.id = Some(db.add_order(order1.clone(), "staff").unwrap());
+        order2.id = Some(db.add_order(order2.clone(), "staff").unwrap());
 
         let got = db.get_all_orders().unwrap();
         assert_eq!(got, vec![order1, order2]);
     }
 
+    #[test]
+    fn test_food_is_loaded_for_many_orders() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let burger = |toppings| MenuItem::Burger(Burger::new(Bun::Sesame, Patty::Veggie, toppings));
+        let mut orders: Vec<Order> = (0..MAX_ORDERS_PER_QUERY + 2)
+            .map(|i| match i % 3 {
+                0 => test_order(vec![burger(vec![Topping::Onion, Topping::Cheese])]),
+                1 => test_order(vec![MenuItem::Drink, burger(vec![])]),
+                _ => test_order(vec![]),
+            })
+            .collect();
+        for order in &mut orders {
+            order.id = Some(db.add_order(order.clone(), "staff").unwrap());
+        }
+
+        // the orders are split across queries, and those without food still come back
+        assert_eq!(db.get_all_orders().unwrap(), orders);
+        let page = db.query_orders(&OrderQuery::default()).unwrap();
+        assert_eq!(page.orders, orders);
+    }
+
+    #[test]
+    fn test_update_status() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let id = db.add_order(get_test_order(), "staff").unwrap();
+
+        let updated = db
+            .update_status(id, OrderStatus::Preparing, None, "staff")
+            .unwrap();
+        assert_eq!(updated.status, OrderStatus::Preparing);
+        assert_eq!(db.get_order(id).unwrap().unwrap(), updated);
+        assert_eq!(updated.version, 2);
+
+        // an update for a version the order has moved on from is refused
+        assert!(matches!(
+            db.update_status(id, OrderStatus::Cancelled, Some(1), "staff"),
+            Err(AspirinEatsError::PreconditionFailed)
+        ));
+
+        assert!(matches!(
+            db.update_status(id, OrderStatus::Pending, None, "staff"),
+            Err(AspirinEatsError::InvalidTransition {
+                from: OrderStatus::Preparing,
+                to: OrderStatus::Pending
+            })
+        ));
+        assert_eq!(
+            db.get_order(id).unwrap().unwrap().status,
+            OrderStatus::Preparing
+        );
+
+        assert!(matches!(
+            db.update_status(id + 1, OrderStatus::Preparing, None, "staff"),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_status_changes_are_published() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let id = db.add_order(get_test_order(), "staff").unwrap();
+        let updates = db.subscribe(id);
+
+        db.update_status(id, OrderStatus::Preparing, None, "staff")
+            .unwrap();
+        assert!(db
+            .update_status(id, OrderStatus::Pending, None, "staff")
+            .is_err());
+        let updated = db
+            .update_status(id, OrderStatus::Cancelled, None, "staff")
+            .unwrap();
+        let statuses: Vec<_> = updates.iter().map(|order| order.status).collect();
+        assert_eq!(
+            statuses,
+            vec![OrderStatus::Preparing, OrderStatus::Cancelled]
+        );
+        assert_eq!(db.get_order(id).unwrap().unwrap(), updated);
+
+        // deleting an order ends its updates
+        let id = db.add_order(get_test_order(), "staff").unwrap();
+        let updates = db.subscribe(id);
+        db.remove_order(id, "staff").unwrap();
+        assert!(updates.recv().is_err());
+    }
+
+    #[test]
+    fn test_query_orders() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let orders: Vec<Order> = [("Amit", 800), ("Bea", 2000), ("Amit", 300), ("Cy", 1250)]
+            .into_iter()
+            .map(|(customer, total)| {
+                let mut order = get_test_order();
+                order.customer = customer.to_string();
+                order.total = Money::from_cents(total);
+                order.id = Some(db.add_order(order.clone(), "staff").unwrap());
+                order
+            })
+            .collect();
+        db.update_status(2, OrderStatus::Preparing, None, "staff")
+            .unwrap();
+
+        let page = db
+            .query_orders(&OrderQuery {
+                customer: Some("Amit".to_string()),
+                ..OrderQuery::default()
+            })
+            .unwrap();
+        assert_eq!(page.orders, vec![orders[0].clone(), orders[2].clone()]);
+        assert_eq!(page.total_count, 2);
+        assert_eq!(page.next_offset, None);
+
+        let page = db
+            .query_orders(&OrderQuery {
+                status: Some(OrderStatus::Pending),
+                min_total: Some(Money::from_dollars(5)),
+                max_total: Some(Money::from_cents(1250)),
+                ..OrderQuery::default()
+            })
+            .unwrap();
+        let ids: Vec<_> = page.orders.iter().map(|order| order.id.unwrap()).collect();
+        assert_eq!(ids, vec![1, 4]);
+
+        let query = OrderQuery {
+            sort: OrderSort::Total,
+            direction: SortDirection::Descending,
+            limit: Some(3),
+            ..OrderQuery::default()
+        };
+        let page = db.query_orders(&query).unwrap();
+        let ids: Vec<_> = page.orders.iter().map(|order| order.id.unwrap()).collect();
+        assert_eq!(ids, vec![2, 4, 1]);
+        assert_eq!(page.total_count, 4);
+        assert_eq!(page.next_offset, Some(3));
+
+        let page = db.query_orders(&OrderQuery { offset: 3, ..query }).unwrap();
+        let ids: Vec<_> = page.orders.iter().map(|order| order.id.unwrap()).collect();
+        assert_eq!(ids, vec![3]);
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[test]
+    fn test_corrupt_orders_are_reported() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let order = test_order(vec![MenuItem::Burger(Burger::new(
+            Bun::Plain,
+            Patty::Beef,
+            vec![Topping::Cheese],
+        ))]);
+        for _ in 0..4 {
+            db.add_order(order.clone(), "staff").unwrap();
+        }
+        db.conn
+            .execute_batch(
+                "UPDATE orders SET status = 'Lost' WHERE id = 1;
+                UPDATE order_items SET kind = 'Pizza' WHERE order_id = 2;
+                UPDATE toppings SET topping = 'Ketchup'
+                    WHERE item_id = (SELECT id FROM order_items WHERE order_id = 4);",
+            )
+            .unwrap();
+
+        assert!(matches!(
+            db.get_order(1),
+            Err(AspirinEatsError::CorruptOrder(1))
+        ));
+        let error: AspirinEatsError = db.get_all_orders().unwrap_err().into();
+        assert!(matches!(error, AspirinEatsError::CorruptOrder(1)));
+        assert!(db.get_order(3).unwrap().is_some());
+
+        let corrupt = db.verify_integrity().unwrap();
+        let ids: Vec<_> = corrupt.iter().map(|order| order.order_id).collect();
+        assert_eq!(ids, vec![1, 2, 4]);
+        assert_eq!(corrupt[1].reason, "unknown menu item Pizza");
+    }
+
+    #[test]
+    fn test_price_lists() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        assert_eq!(db.current_price_list().unwrap(), PriceList::default());
+        db.install_price_list(&PriceList::default()).unwrap();
+
+        let mut menu: serde_json::Value = serde_json::to_value(PriceList::default()).unwrap();
+        menu["fries"] = 6.into();
+        let changed = PriceList::from_str(&menu.to_string()).unwrap();
+        assert!(matches!(
+            db.install_price_list(&changed),
+            Err(AspirinEatsError::InvalidMenu(_))
+        ));
+
+        menu["version"] = 3.into();
+        let v3 = PriceList::from_str(&menu.to_string()).unwrap();
+        db.install_price_list(&v3).unwrap();
+        assert_eq!(db.current_price_list().unwrap(), v3);
+        assert_eq!(db.get_price_list(1).unwrap().unwrap(), PriceList::default());
+        assert_eq!(db.get_price_list(2).unwrap(), None);
+
+        // re-installing an existing version is fine, but a new one can't go backwards
+        db.install_price_list(&PriceList::default()).unwrap();
+        menu["version"] = 2.into();
+        let v2 = PriceList::from_str(&menu.to_string()).unwrap();
+        assert!(matches!(
+            db.install_price_list(&v2),
+            Err(AspirinEatsError::InvalidMenu(_))
+        ));
+        assert_eq!(db.current_price_list().unwrap(), v3);
+    }
+
     #[test]
     fn test_remove_order() {
         let db = AspirinEatsDb::in_memory().unwrap();
         let order = get_test_order();
 
-        let id = db.add_order(order.clone()).unwrap();
+        let id = db.add_order(order.clone(), "staff").unwrap();
 
-        db.remove_order(id).unwrap();
+        db.remove_order(id, "staff").unwrap();
         let got = db.get_order(id).unwrap();
         assert_eq!(got, None);
+        assert!(matches!(
+            db.update_status(id, OrderStatus::Preparing, None, "staff"),
+            Err(AspirinEatsError::NotFound)
+        ));
+
+        // the row is kept until it is purged
+        let rows = |db: &AspirinEatsDb| -> i64 {
+            db.conn
+                .query_row("SELECT COUNT(*) FROM orders", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(rows(&db), 1);
+        assert_eq!(db.purge_deleted_orders("admin").unwrap(), 1);
+        assert_eq!(rows(&db), 0);
+        assert_eq!(db.purge_deleted_orders("admin").unwrap(), 0);
     }
 
     #[test]
     fn test_reset_orders() {
         let db = AspirinEatsDb::in_memory().unwrap();
         for _id in 0..5 {
-            db.add_order(get_test_order()).unwrap();
+            db.add_order(get_test_order(), "staff").unwrap();
         }
 
-        db.reset_orders().unwrap();
+        db.reset_orders("staff").unwrap();
         let orders = db.get_all_orders().unwrap();
         assert_eq!(orders.len(), 0);
+        assert_eq!(
+            db.query_orders(&OrderQuery::default()).unwrap().total_count,
+            0
+        );
+
+        // IDs aren't reused, so the history of each one stays unambiguous
+        assert_eq!(db.add_order(get_test_order(), "staff").unwrap(), 6);
+        let kinds: Vec<_> = db
+            .order_history(5)
+            .unwrap()
+            .iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![OrderEventKind::Created, OrderEventKind::Deleted]
+        );
+    }
+
+    #[test]
+    fn test_order_history() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let id = db.add_order(get_test_order(), "customer:Amit").unwrap();
+        db.update_status(id, OrderStatus::Preparing, None, "staff")
+            .unwrap();
+        db.update_status(id, OrderStatus::Cancelled, None, "customer:Amit")
+            .unwrap();
+        db.remove_order(id, "staff").unwrap();
+        db.purge_deleted_orders("admin").unwrap();
+
+        let history = db.order_history(id).unwrap();
+        let events: Vec<_> = history
+            .iter()
+            .map(|event| (event.kind, event.status.clone(), event.actor.as_str()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    OrderEventKind::Created,
+                    Some(OrderStatus::Pending),
+                    "customer:Amit"
+                ),
+                (
+                    OrderEventKind::StatusChanged,
+                    Some(OrderStatus::Preparing),
+                    "staff"
+                ),
+                (
+                    OrderEventKind::Cancelled,
+                    Some(OrderStatus::Cancelled),
+                    "customer:Amit"
+                ),
+                (OrderEventKind::Deleted, None, "staff"),
+                (OrderEventKind::Purged, None, "admin"),
+            ]
+        );
+        assert!(history.iter().all(|event| event.timestamp > 0));
+        assert_eq!(db.order_history(id + 1).unwrap(), vec![]);
+
+        // the history can only be added to
+        for sql in [
+            "UPDATE order_events SET actor = 'nobody'",
+            "DELETE FROM order_events",
+        ] {
+            assert!(db.conn.execute(sql, []).is_err(), "{}", sql);
+        }
+        assert_eq!(db.order_history(id).unwrap(), history);
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let amit = Role::Customer("Amit".to_string());
+        db.add_api_key("customer-token", &amit).unwrap();
+        db.add_api_key("staff-token", &Role::Staff).unwrap();
+        db.add_api_key("admin-token", &Role::Admin).unwrap();
+
+        assert_eq!(db.find_api_key("customer-token").unwrap(), Some(amit));
+        assert_eq!(db.find_api_key("staff-token").unwrap(), Some(Role::Staff));
+        assert_eq!(db.find_api_key("admin-token").unwrap(), Some(Role::Admin));
+        assert_eq!(db.find_api_key("other-token").unwrap(), None);
+
+        // the tokens themselves are never stored
+        let stored: String = db
+            .conn
+            .query_row("SELECT group_concat(token_hash) FROM api_keys", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(!stored.contains("token"));
+
+        // a row that slipped past the constraints doesn't fall back to any role
+        db.conn
+            .execute_batch("PRAGMA ignore_check_constraints = ON")
+            .unwrap();
+        for (token, role, customer) in [
+            ("typo-token", "staff", None),
+            ("nameless-token", "Customer", None),
+            ("named-token", "Staff", Some("Amit")),
+        ] {
+            db.conn
+                .execute(
+                    "INSERT INTO api_keys (token_hash, role, customer) VALUES (?1, ?2, ?3)",
+                    (hash_token(token), role, customer),
+                )
+                .unwrap();
+            assert!(db.find_api_key(token).is_err(), "{}", token);
+        }
+    }
+
+    #[test]
+    fn test_idempotent_responses() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let respond = |id: i64| {
+            Ok(SavedResponse {
+                request_hash: "hash".to_string(),
+                status_code: 201,
+                body: "{}".to_string(),
+                location: Some(format!("/orders/{}", id)),
+                etag: Some("\"1\"".to_string()),
+            })
+        };
+        let id = db
+            .add_order_idempotently(get_test_order(), "staff", "staff", "abc", &respond)
+            .unwrap();
+        let saved = respond(id).unwrap();
+
+        assert_eq!(
+            db.find_idempotent_response("staff", "abc").unwrap(),
+            Some(saved.clone())
+        );
+        assert_eq!(db.find_idempotent_response("staff", "def").unwrap(), None);
+        assert_eq!(
+            db.find_idempotent_response("customer:Amit", "abc").unwrap(),
+            None
+        );
+
+        // a key that is already used takes the new order back out
+        assert!(matches!(
+            db.add_order_idempotently(get_test_order(), "staff", "staff", "abc", &respond),
+            Err(AspirinEatsError::IdempotencyKeyReused)
+        ));
+        assert_eq!(db.get_all_orders().unwrap().len(), 1);
+
+        // expired responses are never replayed
+        db.conn
+            .execute("UPDATE idempotency_keys SET created_at = 0", [])
+            .unwrap();
+        assert_eq!(db.find_idempotent_response("staff", "abc").unwrap(), None);
+        db.add_order_idempotently(get_test_order(), "staff", "staff", "def", &respond)
+            .unwrap();
+        let count: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM idempotency_keys", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
     }
 }
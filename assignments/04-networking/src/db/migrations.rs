@@ -0,0 +1,426 @@
+use std::str::FromStr;
+
+use rusqlite::{types::Type, Connection, Result, Transaction};
+
+use super::variant_name;
+use crate::food::{MenuItem, OrderStatus};
+
+/// A single step that moves the schema from one version to the next
+type Migration = fn(&Transaction) -> Result<()>;
+
+/// Every migration, in order. The schema version stored in `PRAGMA user_version` is the number of
+/// these that have been applied, so new migrations must only ever be appended
+const MIGRATIONS: &[Migration] = &[
+    create_orders_table,
+    normalise_orders,
+    total_in_cents,
+    versioned_price_lists,
+    itemised_pricing,
+    api_keys,
+    order_versions,
+    order_history,
+    order_estimates,
+    idempotent_etags,
+];
+
+/// Apply any migrations the database hasn't seen yet. Each migration runs in its own
+/// transaction, so a failure leaves the database at the last good version
+pub(super) fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Version 1: the original single-table layout, with the food stored as a JSON blob. Databases
+/// created before migrations were tracked already have this table, so this is a no-op for them
+fn create_orders_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS orders (
+            id	        INTEGER NOT NULL,
+            customer	TEXT NOT NULL,
+            food        TEXT NOT NULL,
+            status	    TEXT NOT NULL,
+            total       REAL NOT NULL,
+            PRIMARY KEY(id AUTOINCREMENT)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 2: split the food out into `order_items` and `toppings`, and store the status as a
+/// plain string so it can be indexed. Order IDs, and the sequence new IDs are drawn from, are kept
+fn normalise_orders(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE orders RENAME TO legacy_orders;
+
+        CREATE TABLE orders (
+            id          INTEGER NOT NULL,
+            customer    TEXT NOT NULL,
+            status      TEXT NOT NULL,
+            total       REAL NOT NULL,
+            PRIMARY KEY(id AUTOINCREMENT)
+        );
+        CREATE INDEX orders_customer ON orders(customer);
+        CREATE INDEX orders_status ON orders(status);
+
+        CREATE TABLE order_items (
+            id          INTEGER NOT NULL PRIMARY KEY,
+            order_id    INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+            position    INTEGER NOT NULL,
+            kind        TEXT NOT NULL,
+            bun         TEXT,
+            patty       TEXT
+        );
+        CREATE INDEX order_items_order_id ON order_items(order_id);
+        CREATE INDEX order_items_kind ON order_items(kind);
+
+        CREATE TABLE toppings (
+            item_id     INTEGER NOT NULL REFERENCES order_items(id) ON DELETE CASCADE,
+            position    INTEGER NOT NULL,
+            topping     TEXT NOT NULL
+        );
+        CREATE INDEX toppings_item_id ON toppings(item_id);",
+    )?;
+
+    let legacy_orders = {
+        let mut stmt = tx.prepare("SELECT id, customer, food, status, total FROM legacy_orders")?;
+        let rows = stmt.query_map([], |row| {
+            let food: String = row.get(2)?;
+            let food: Vec<MenuItem> = serde_json::from_str(&food)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, Type::Text, e.into()))?;
+            let status: String = row.get(3)?;
+            let status = OrderStatus::from_str(&status)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, e.into()))?;
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                food,
+                status,
+                row.get::<_, f64>(4)?,
+            ))
+        })?;
+        rows.collect::<Result<Vec<_>>>()?
+    };
+
+    // the food is written out here rather than with the helper the store uses, which follows the
+    // latest schema, so this migration always does what it did when it was first applied
+    for (id, customer, food, status, total) in legacy_orders {
+        tx.execute(
+            "INSERT INTO orders (id, customer, status, total) VALUES (?1, ?2, ?3, ?4)",
+            (id, customer, variant_name(&status)?, total),
+        )?;
+        for (position, item) in food.iter().enumerate() {
+            let (kind, bun, patty, toppings) = match item {
+                MenuItem::Burger(burger) => (
+                    "Burger",
+                    Some(variant_name(burger.bun())?),
+                    Some(variant_name(burger.patty())?),
+                    burger.toppings(),
+                ),
+                MenuItem::Fries => ("Fries", None, None, &[][..]),
+                MenuItem::Drink => ("Drink", None, None, &[][..]),
+            };
+            tx.execute(
+                "INSERT INTO order_items (order_id, position, kind, bun, patty)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                (id, position, kind, bun, patty),
+            )?;
+            let item_id = tx.last_insert_rowid();
+            for (position, topping) in toppings.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO toppings (item_id, position, topping) VALUES (?1, ?2, ?3)",
+                    (item_id, position, variant_name(topping)?),
+                )?;
+            }
+        }
+    }
+
+    // carry the sequence over so IDs of deleted orders are never handed out again
+    tx.execute_batch(
+        "DELETE FROM sqlite_sequence WHERE name = 'orders';
+        INSERT INTO sqlite_sequence (name, seq)
+            SELECT 'orders', seq FROM sqlite_sequence WHERE name = 'legacy_orders';
+        DROP TABLE legacy_orders;",
+    )?;
+    Ok(())
+}
+
+/// Version 3: store totals as a whole number of cents rather than a floating point number of
+/// dollars, rounding any existing totals to the nearest cent
+fn total_in_cents(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE orders ADD COLUMN total_cents INTEGER NOT NULL DEFAULT 0;
+        UPDATE orders SET total_cents = CAST(ROUND(total * 100) AS INTEGER);
+        ALTER TABLE orders DROP COLUMN total;",
+    )
+}
+
+/// The prices that were hard-coded before price lists were versioned. Every order placed before
+/// version 4 was priced with these
+const ORIGINAL_PRICE_LIST: &str = r#"{
+    "version": 1,
+    "fries": 5.0,
+    "drink": 3.0,
+    "buns": {"Sesame": 1.0, "Plain": 0.0, "GlutenFree": 2.0},
+    "patties": {"Beef": 8.0, "Chicken": 7.0, "Veggie": 6.0},
+    "toppings": {
+        "Lettuce": 0.0, "Tomato": 0.0, "Onion": 0.0, "Pickle": 0.0, "Cheese": 1.0, "Bacon": 2.0
+    }
+}"#;
+
+/// Version 4: keep every price list that has been used, and record which one each order was
+/// priced with. Existing orders were priced with the original hard-coded prices
+fn versioned_price_lists(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE price_lists (
+            version     INTEGER NOT NULL PRIMARY KEY,
+            prices      TEXT NOT NULL
+        );
+        ALTER TABLE orders ADD COLUMN price_list INTEGER NOT NULL DEFAULT 1;",
+    )?;
+    tx.execute(
+        "INSERT INTO price_lists (version, prices) VALUES (1, ?1)",
+        [ORIGINAL_PRICE_LIST],
+    )?;
+    Ok(())
+}
+
+/// Version 5: store an itemised breakdown of each order's price. Existing orders were priced
+/// before combos, promo codes and tax existed, so their items are priced from their price list,
+/// their subtotal is their total and they have no discounts or tax
+fn itemised_pricing(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE order_items ADD COLUMN price_cents INTEGER;
+        ALTER TABLE orders ADD COLUMN subtotal_cents INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE orders ADD COLUMN tax_cents INTEGER NOT NULL DEFAULT 0;
+        UPDATE orders SET subtotal_cents = total_cents;
+
+        CREATE TABLE order_discounts (
+            order_id        INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+            position        INTEGER NOT NULL,
+            description     TEXT NOT NULL,
+            amount_cents    INTEGER NOT NULL
+        );
+        CREATE INDEX order_discounts_order_id ON order_discounts(order_id);
+
+        UPDATE order_items SET price_cents = (
+            SELECT CAST(ROUND(100 * CASE order_items.kind
+                WHEN 'Fries' THEN json_extract(p.prices, '$.fries')
+                WHEN 'Drink' THEN json_extract(p.prices, '$.drink')
+                ELSE json_extract(p.prices, '$.buns.' || order_items.bun)
+                    + json_extract(p.prices, '$.patties.' || order_items.patty)
+                    + (SELECT TOTAL(json_extract(p.prices, '$.toppings.' || t.topping))
+                        FROM toppings t WHERE t.item_id = order_items.id)
+                END) AS INTEGER)
+            FROM orders o JOIN price_lists p ON p.version = o.price_list
+            WHERE o.id = order_items.order_id
+        );",
+    )
+}
+
+/// Version 6: API keys, stored as hashes of their tokens. A customer key is tied to the customer
+/// name it may place and see orders for
+fn api_keys(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE api_keys (
+            id          INTEGER NOT NULL PRIMARY KEY,
+            token_hash  TEXT NOT NULL UNIQUE,
+            role        TEXT NOT NULL CHECK (role IN ('Customer', 'Staff')),
+            customer    TEXT,
+            CHECK ((role = 'Customer') = (customer IS NOT NULL))
+        );",
+    )
+}
+
+/// Version 7: count the changes to each order, so conflicting updates can be detected, and save
+/// the responses to requests made with an `Idempotency-Key` so retries can be answered the same
+/// way. Existing orders start at version 1
+fn order_versions(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE orders ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+        CREATE TABLE idempotency_keys (
+            scope           TEXT NOT NULL,
+            key             TEXT NOT NULL,
+            request_hash    TEXT NOT NULL,
+            status_code     INTEGER NOT NULL,
+            body            TEXT NOT NULL,
+            location        TEXT,
+            created_at      INTEGER NOT NULL,
+            PRIMARY KEY (scope, key)
+        );
+        CREATE INDEX idempotency_keys_created_at ON idempotency_keys(created_at);",
+    )
+}
+
+/// Version 8: soft deletes and an audit trail. Deleted orders are kept with `deleted_at` set
+/// until an admin purges them, and every change to an order is recorded in `order_events`, which
+/// triggers keep append-only. Events aren't tied to `orders` by a foreign key, so they outlive
+/// purged orders. Orders placed before this version have no events. The `api_keys` table is
+/// rebuilt to allow the admin role, since SQLite can't change a CHECK constraint in place
+fn order_history(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE orders ADD COLUMN deleted_at INTEGER;
+        CREATE INDEX orders_deleted_at ON orders(deleted_at);
+
+        CREATE TABLE order_events (
+            id          INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            order_id    INTEGER NOT NULL,
+            kind        TEXT NOT NULL,
+            status      TEXT,
+            actor       TEXT NOT NULL,
+            created_at  INTEGER NOT NULL
+        );
+        CREATE INDEX order_events_order_id ON order_events(order_id);
+        CREATE TRIGGER order_events_no_update BEFORE UPDATE ON order_events
+        BEGIN
+            SELECT RAISE(ABORT, 'order_events is append-only');
+        END;
+        CREATE TRIGGER order_events_no_delete BEFORE DELETE ON order_events
+        BEGIN
+            SELECT RAISE(ABORT, 'order_events is append-only');
+        END;
+
+        CREATE TABLE new_api_keys (
+            id          INTEGER NOT NULL PRIMARY KEY,
+            token_hash  TEXT NOT NULL UNIQUE,
+            role        TEXT NOT NULL CHECK (role IN ('Customer', 'Staff', 'Admin')),
+            customer    TEXT,
+            CHECK ((role = 'Customer') = (customer IS NOT NULL))
+        );
+        INSERT INTO new_api_keys SELECT id, token_hash, role, customer FROM api_keys;
+        DROP TABLE api_keys;
+        ALTER TABLE new_api_keys RENAME TO api_keys;",
+    )
+}
+
+/// Version 9: when the kitchen expects each order to be ready and delivered, as seconds since the
+/// Unix epoch. Both are null until the kitchen has queued the order
+fn order_estimates(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE orders ADD COLUMN ready_at INTEGER;
+        ALTER TABLE orders ADD COLUMN delivered_at INTEGER;",
+    )
+}
+
+/// Version 10: save the ETag of responses to requests made with an `Idempotency-Key`, so a replay
+/// carries it too. Responses saved before this have none
+fn idempotent_etags(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("ALTER TABLE idempotency_keys ADD COLUMN etag TEXT;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::AspirinEatsDb, food::*, menu::PriceList, money::Money, pricing::PriceBreakdown,
+        store::OrderStore,
+    };
+
+    const LEGACY_SCHEMA: &str = "CREATE TABLE orders (
+        id	        INTEGER NOT NULL,
+        customer	TEXT NOT NULL,
+        food        TEXT NOT NULL,
+        status	    TEXT NOT NULL,
+        total       REAL NOT NULL,
+        PRIMARY KEY(id AUTOINCREMENT)
+    )";
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_new_database_is_at_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+
+        // running again is a no-op
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_original_price_list_matches_default() {
+        assert_eq!(
+            PriceList::from_str(ORIGINAL_PRICE_LIST).unwrap(),
+            PriceList::default()
+        );
+    }
+
+    #[test]
+    fn test_legacy_database_is_migrated() {
+        let path = std::env::temp_dir().join(format!("aspirin-eats-{}.db", uuid::Uuid::new_v4()));
+        let burger = MenuItem::Burger(Burger::new(
+            Bun::Sesame,
+            Patty::Veggie,
+            vec![Topping::Onion, Topping::Cheese],
+        ));
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute(LEGACY_SCHEMA, []).unwrap();
+            for (customer, food, status) in [
+                (
+                    "Amit",
+                    vec![burger.clone(), MenuItem::Drink],
+                    OrderStatus::Preparing,
+                ),
+                ("Bea", vec![MenuItem::Fries], OrderStatus::Pending),
+                ("Cy", vec![MenuItem::Drink], OrderStatus::Pending),
+            ] {
+                conn.execute(
+                    "INSERT INTO orders (customer, food, status, total) VALUES (?1, ?2, ?3, ?4)",
+                    (
+                        customer,
+                        serde_json::to_string(&food).unwrap(),
+                        serde_json::to_string(&status).unwrap(),
+                        0.1 + 0.2,
+                    ),
+                )
+                .unwrap();
+            }
+            conn.execute("DELETE FROM orders WHERE id IN (2, 3)", [])
+                .unwrap();
+        }
+
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        assert_eq!(
+            db.get_all_orders().unwrap(),
+            vec![Order {
+                id: Some(1),
+                customer: "Amit".to_string(),
+                food: vec![burger, MenuItem::Drink],
+                status: OrderStatus::Preparing,
+                total: Money::from_cents(30),
+                breakdown: PriceBreakdown {
+                    items: vec![Money::from_dollars(8), Money::from_dollars(3)],
+                    subtotal: Money::from_cents(30),
+                    discounts: vec![],
+                    tax: Money::ZERO,
+                },
+                price_list: 1,
+                version: 1,
+                estimate: None,
+            }]
+        );
+
+        // new orders continue after the highest ID the legacy table handed out
+        let id = db
+            .add_order(db.get_order(1).unwrap().unwrap(), "staff")
+            .unwrap();
+        assert_eq!(id, 4);
+        drop(db);
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
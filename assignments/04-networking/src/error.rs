@@ -1,5 +1,7 @@
 use thiserror;
 
+use crate::{db::CorruptOrder, food::OrderStatus, http::Method};
+
 #[derive(thiserror::Error, Debug)]
 pub enum AspirinEatsError {
     /// Error when trying to parse a JSON string
@@ -8,7 +10,11 @@ pub enum AspirinEatsError {
 
     /// Error when fetching or otherwise interacting with the database
     #[error("Failed to interact with database")]
-    Database(#[from] rusqlite::Error),
+    Database(#[source] rusqlite::Error),
+
+    /// Error when an order in the database can't be decoded
+    #[error("Order {0} is corrupt")]
+    CorruptOrder(i64),
 
     /// Error when reading/writing from Streams
     #[error("Failed to read/write from stream")]
@@ -18,11 +24,140 @@ pub enum AspirinEatsError {
     #[error("Invalid Request")]
     InvalidRequest,
 
+    /// Error when the request line is not of the form `METHOD target HTTP/x.y`
+    #[error("Malformed request line")]
+    MalformedRequestLine,
+
+    /// Error when the request uses an HTTP method we do not know about
+    #[error("Unsupported method: {0}")]
+    UnsupportedMethod(String),
+
+    /// Error when the request uses an HTTP version other than 1.0 or 1.1
+    #[error("Unsupported HTTP version: {0}")]
+    UnsupportedVersion(String),
+
+    /// Error when a header line is missing its colon or has an empty name
+    #[error("Malformed header")]
+    MalformedHeader,
+
+    /// Error when the path, query string or body is not valid percent-encoding or UTF-8
+    #[error("Invalid encoding in request")]
+    InvalidEncoding,
+
+    /// Error when Content-Length is not a number, is given twice with different values, or is
+    /// combined with Transfer-Encoding
+    #[error("Invalid Content-Length")]
+    InvalidContentLength,
+
+    /// Error when the request uses a Transfer-Encoding other than chunked
+    #[error("Unsupported Transfer-Encoding: {0}")]
+    UnsupportedTransferEncoding(String),
+
+    /// Error when a chunked body has a bad chunk size or is missing its CRLF delimiters
+    #[error("Invalid chunked body")]
+    InvalidChunkedBody,
+
+    /// Error when the stream ends before the full request has been read
+    #[error("Incomplete request")]
+    IncompleteRequest,
+
+    /// Error when the request line and headers are longer than the server allows
+    #[error("Request headers too large")]
+    HeadersTooLarge,
+
+    /// Error when the request body is longer than the server allows
+    #[error("Request body too large")]
+    PayloadTooLarge,
+
+    /// Error when a client takes too long to send a complete request
+    #[error("Request timed out")]
+    RequestTimeout,
+
+    /// Error when a client has sent more requests than it is allowed to. Holds how many seconds
+    /// it should wait before trying again
+    #[error("Too many requests")]
+    TooManyRequests { retry_after: u64 },
+
+    /// Error when an upstream server sends back something that is not a valid HTTP response
+    #[error("Invalid response from upstream server")]
+    InvalidResponse,
+
+    /// Error when the server is already serving as many connections as it can
+    #[error("Server busy")]
+    ServerBusy,
+
+    /// Error when the proxy has no healthy upstream server to send a request to
+    #[error("No healthy upstream servers")]
+    NoHealthyUpstream,
+
+    /// Error when a TLS certificate or private key can't be loaded or don't match
+    #[error("Invalid TLS certificate: {0}")]
+    InvalidCertificate(String),
+
+    /// Error when a request needs a token but has none, or one that isn't known
+    #[error("Authentication required")]
+    Unauthorized,
+
+    /// Error when the sender of a request is known but isn't allowed to do what it asks
+    #[error("Forbidden")]
+    Forbidden,
+
+    /// Error when trying to move an order to a status it is not allowed to reach from its current one
+    #[error("Cannot change order status from {from:?} to {to:?}")]
+    InvalidTransition { from: OrderStatus, to: OrderStatus },
+
+    /// Error when a request header has a value we can't use
+    #[error("Invalid {0} header")]
+    InvalidHeader(String),
+
+    /// Error when a change is made with `If-Match` and the order has changed since the client
+    /// last saw it
+    #[error("Order has changed since it was fetched")]
+    PreconditionFailed,
+
+    /// Error when a change that needs `If-Match` is made without it
+    #[error("If-Match header required")]
+    PreconditionRequired,
+
+    /// Error when an `Idempotency-Key` is reused for a request that is different from the one it
+    /// was first used for
+    #[error("Idempotency-Key was already used for a different request")]
+    IdempotencyKeyReused,
+
+    /// Error when a query string parameter has a value we can't use
+    #[error("Invalid query parameter: {0}")]
+    InvalidQuery(String),
+
+    /// Error when an API key is added for a token that already has one
+    #[error("An API key already exists for that token")]
+    DuplicateApiKey,
+
+    /// Error when a menu price list is malformed or conflicts with one already in use
+    #[error("Invalid menu: {0}")]
+    InvalidMenu(String),
+
+    /// Error when an order uses a promo code that isn't on the current menu
+    #[error("Unknown promo code: {0}")]
+    UnknownPromoCode(String),
+
     /// Error when receiving request for resource that does not exist
     #[error("Resource not found")]
     NotFound,
 
-    /// Error when request is for an HTTP method not supported on that path
+    /// Error when request is for an HTTP method not supported on that path. Holds the methods that
+    /// are supported, for the Allow header
     #[error("Method not allowed")]
-    MethodNotAllowed,
+    MethodNotAllowed(Vec<Method>),
+}
+
+impl From<rusqlite::Error> for AspirinEatsError {
+    /// Database errors caused by an order failing to decode become `CorruptOrder`
+    fn from(error: rusqlite::Error) -> Self {
+        if let rusqlite::Error::FromSqlConversionFailure(_, _, cause) = &error {
+            if let Some(corrupt) = cause.downcast_ref::<CorruptOrder>() {
+                return AspirinEatsError::CorruptOrder(corrupt.order_id);
+            }
+        }
+        AspirinEatsError::Database(error)
+    }
 }
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    food::Order,
+    http::{BodyStream, StreamChunk},
+};
+
+/// How long an event stream can go without sending anything before a comment is sent to keep it
+/// open. This is also how a stream finds out that its client has gone away
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Lets clients watch orders change. Whatever changes an order publishes its new state here, and
+/// every subscriber to that order receives it
+#[derive(Debug, Default)]
+pub struct OrderEvents {
+    subscribers: Mutex<HashMap<i64, Vec<Sender<Order>>>>,
+}
+
+impl OrderEvents {
+    pub fn new() -> Self {
+        OrderEvents::default()
+    }
+
+    /// Receive every change to an order from now on. The receiver is disconnected once the order
+    /// reaches a final status or is deleted
+    pub fn subscribe(&self, id: i64) -> Receiver<Order> {
+        let (sender, receiver) = mpsc::channel();
+        self.lock().entry(id).or_default().push(sender);
+        receiver
+    }
+
+    /// Send the new state of an order to its subscribers
+    pub fn publish(&self, order: &Order) {
+        let Some(id) = order.id else {
+            return;
+        };
+        let mut subscribers = self.lock();
+        if order.status.is_final() {
+            for sender in subscribers.remove(&id).unwrap_or_default() {
+                let _ = sender.send(order.clone());
+            }
+            return;
+        }
+        if let Some(senders) = subscribers.get_mut(&id) {
+            // forget subscribers that have stopped listening
+            senders.retain(|sender| sender.send(order.clone()).is_ok());
+            if senders.is_empty() {
+                subscribers.remove(&id);
+            }
+        }
+    }
+
+    /// Disconnect everyone watching an order, such as when it is deleted
+    pub fn close(&self, id: i64) {
+        self.lock().remove(&id);
+    }
+
+    /// Disconnect everyone watching any order
+    pub fn close_all(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<i64, Vec<Sender<Order>>>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A stream of server-sent events for an order, starting with its current state and followed by
+/// every update received. Each event is a `status` event whose data is the order as JSON
+pub fn order_event_stream(order: Order, updates: Option<Receiver<Order>>) -> BodyStream {
+    let mut current = Some(order);
+    let mut last_sent = Instant::now();
+    BodyStream::new(move |timeout| {
+        let next = match (current.take(), &updates) {
+            (Some(order), _) => Ok(order),
+            (None, Some(updates)) => updates.recv_timeout(timeout),
+            (None, None) => Err(RecvTimeoutError::Disconnected),
+        };
+        match next {
+            Ok(order) => {
+                last_sent = Instant::now();
+                StreamChunk::Data(format!("event: status\ndata: {}\n\n", order).into_bytes())
+            }
+            Err(RecvTimeoutError::Timeout) if last_sent.elapsed() < KEEP_ALIVE_INTERVAL => {
+                StreamChunk::Idle
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                last_sent = Instant::now();
+                StreamChunk::Data(b": keep-alive\n\n".to_vec())
+            }
+            Err(RecvTimeoutError::Disconnected) => StreamChunk::End,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        food::{MenuItem, OrderRequest, OrderStatus},
+        menu::PriceList,
+    };
+
+    fn test_order(id: i64, status: OrderStatus) -> Order {
+        let request = OrderRequest {
+            customer: "Amit".to_string(),
+            food: vec![MenuItem::Fries],
+            promo_code: None,
+        };
+        let mut order = Order::from_request(request, &PriceList::default()).unwrap();
+        order.id = Some(id);
+        order.status = status;
+        order
+    }
+
+    #[test]
+    fn test_subscribers_receive_changes_until_order_is_final() {
+        let events = OrderEvents::new();
+        let (first, second, other) = (
+            events.subscribe(1),
+            events.subscribe(1),
+            events.subscribe(2),
+        );
+
+        events.publish(&test_order(1, OrderStatus::Preparing));
+        drop(second);
+        events.publish(&test_order(1, OrderStatus::Completed));
+        events.publish(&test_order(1, OrderStatus::Completed));
+
+        let statuses: Vec<_> = first.iter().map(|order| order.status).collect();
+        assert_eq!(
+            statuses,
+            vec![OrderStatus::Preparing, OrderStatus::Completed]
+        );
+        assert!(other.try_recv().is_err());
+
+        events.close(2);
+        assert_eq!(other.recv(), Err(mpsc::RecvError));
+    }
+
+    #[test]
+    fn test_order_event_stream() {
+        let events = OrderEvents::new();
+        let order = test_order(1, OrderStatus::Pending);
+        let stream = order_event_stream(order.clone(), Some(events.subscribe(1)));
+        let timeout = Duration::from_millis(10);
+
+        let expected = format!("event: status\ndata: {}\n\n", order);
+        assert_eq!(
+            stream.next(timeout),
+            StreamChunk::Data(expected.into_bytes())
+        );
+        assert_eq!(stream.next(timeout), StreamChunk::Idle);
+
+        let cancelled = test_order(1, OrderStatus::Cancelled);
+        events.publish(&cancelled);
+        let expected = format!("event: status\ndata: {}\n\n", cancelled);
+        assert_eq!(
+            stream.next(timeout),
+            StreamChunk::Data(expected.into_bytes())
+        );
+        assert_eq!(stream.next(timeout), StreamChunk::End);
+
+        // an order that is already final has nothing more to say after its current state
+        let stream = order_event_stream(cancelled, None);
+        assert!(matches!(stream.next(timeout), StreamChunk::Data(_)));
+        assert_eq!(stream.next(timeout), StreamChunk::End);
+    }
+}
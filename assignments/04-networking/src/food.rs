@@ -1,6 +1,15 @@
+use std::time::Duration;
+
 use display_json::{DisplayAsJson, FromStrAsJson};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    error::AspirinEatsError,
+    menu::PriceList,
+    money::Money,
+    pricing::{self, PriceBreakdown},
+};
+
 /// Struct that represents an order
 #[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
 pub struct Order {
@@ -17,30 +26,81 @@ pub struct Order {
     pub status: OrderStatus,
 
     /// Total price of the order
-    pub total: f64,
+    pub total: Money,
+
+    /// How the total was calculated
+    pub breakdown: PriceBreakdown,
+
+    /// Version of the price list the order was priced with
+    pub price_list: u32,
+
+    /// Starts at 1 and goes up by one every time the order changes, so a client can tell whether
+    /// the order has changed since it last saw it
+    pub version: u32,
+
+    /// When the kitchen expects the order to be ready and delivered, once it has been queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimate: Option<Estimate>,
+}
+
+/// When an order is expected to reach the later stages, in seconds since the Unix epoch
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Estimate {
+    /// When the food will be ready and the order will leave the kitchen
+    pub ready_at: i64,
+
+    /// When the order will reach the customer
+    pub delivered_at: i64,
 }
 
 /// Struct that represents an incoming order request to be added to the database. Separate from the
 /// Order struct because many of the fields will be generated for new orders
-#[derive(Deserialize, FromStrAsJson)]
+#[derive(Serialize, Deserialize, FromStrAsJson, Debug, Clone)]
 pub struct OrderRequest {
     /// Customer Name
     pub customer: String,
 
     /// Vec of all the food items in the order
     pub food: Vec<MenuItem>,
+
+    /// Promo code to apply to the order, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promo_code: Option<String>,
 }
 
-impl From<OrderRequest> for Order {
-    /// Create an Order from an OrderRequest by filling in the ID, status, and total fields
-    fn from(order_request: OrderRequest) -> Self {
-        Order {
+impl Order {
+    /// Create an Order from an OrderRequest, pricing it with the given price list. Fails if the
+    /// promo code isn't on the price list
+    pub fn from_request(
+        order_request: OrderRequest,
+        prices: &PriceList,
+    ) -> Result<Self, AspirinEatsError> {
+        let breakdown = pricing::price_order(
+            &order_request.food,
+            order_request.promo_code.as_deref(),
+            prices,
+        )?;
+        Ok(Order {
             id: None,
             customer: order_request.customer,
-            status: OrderStatus::Pending,
-            total: order_request.food.iter().map(|item| item.price()).sum(),
             food: order_request.food,
-        }
+            status: OrderStatus::Pending,
+            total: breakdown.total(),
+            breakdown,
+            price_list: prices.version(),
+            version: 1,
+            estimate: None,
+        })
+    }
+}
+
+impl TryFrom<OrderRequest> for Order {
+    type Error = AspirinEatsError;
+
+    /// Create an Order from an OrderRequest by filling in the ID, status, and pricing fields,
+    /// using the default price list
+    fn try_from(order_request: OrderRequest) -> Result<Self, Self::Error> {
+        Order::from_request(order_request, &PriceList::default())
     }
 }
 
@@ -54,6 +114,53 @@ pub enum OrderStatus {
     Cancelled,
 }
 
+impl OrderStatus {
+    /// Every status, in the order they are declared
+    pub const ALL: [OrderStatus; 5] = [
+        OrderStatus::Pending,
+        OrderStatus::Preparing,
+        OrderStatus::Transporting,
+        OrderStatus::Completed,
+        OrderStatus::Cancelled,
+    ];
+
+    /// Whether an order in this status is allowed to move to `next`. Orders move forward through
+    /// Pending, Preparing, Transporting and Completed, and can be cancelled until they leave the
+    /// kitchen. Completed and Cancelled are terminal
+    pub fn can_transition_to(&self, next: &OrderStatus) -> bool {
+        matches!(
+            (self, next),
+            (OrderStatus::Pending, OrderStatus::Preparing)
+                | (OrderStatus::Preparing, OrderStatus::Transporting)
+                | (OrderStatus::Transporting, OrderStatus::Completed)
+                | (OrderStatus::Pending, OrderStatus::Cancelled)
+                | (OrderStatus::Preparing, OrderStatus::Cancelled)
+        )
+    }
+
+    /// Whether an order in this status will never change again
+    pub fn is_final(&self) -> bool {
+        matches!(self, OrderStatus::Completed | OrderStatus::Cancelled)
+    }
+
+    /// The status an order moves on to from this one when nothing goes wrong
+    pub fn next(&self) -> Option<OrderStatus> {
+        match self {
+            OrderStatus::Pending => Some(OrderStatus::Preparing),
+            OrderStatus::Preparing => Some(OrderStatus::Transporting),
+            OrderStatus::Transporting => Some(OrderStatus::Completed),
+            OrderStatus::Completed | OrderStatus::Cancelled => None,
+        }
+    }
+}
+
+/// Struct that represents a request to change the status of an existing order
+#[derive(Deserialize, FromStrAsJson)]
+pub struct OrderStatusUpdate {
+    /// The status to move the order to
+    pub status: OrderStatus,
+}
+
 /// Enum that represents a particular menu item
 #[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum MenuItem {
@@ -63,11 +170,14 @@ pub enum MenuItem {
 }
 
 impl MenuItem {
-    fn price(&self) -> f64 {
+    /// How long the kitchen takes to make this item. Every topping adds to a burger's time
+    pub fn prep_time(&self) -> Duration {
         match self {
-            MenuItem::Burger(burger) => burger.price(),
-            MenuItem::Fries => 5.0,
-            MenuItem::Drink => 3.0,
+            MenuItem::Burger(burger) => {
+                Duration::from_secs(4 * 60 + 30 * burger.toppings.len() as u64)
+            }
+            MenuItem::Fries => Duration::from_secs(3 * 60),
+            MenuItem::Drink => Duration::from_secs(30),
         }
     }
 }
@@ -89,19 +199,23 @@ impl Burger {
         }
     }
 
-    fn price(&self) -> f64 {
-        self.bun.price()
-            + self.patty.price()
-            + self
-                .toppings
-                .iter()
-                .map(|topping| topping.price())
-                .sum::<f64>()
+    pub fn bun(&self) -> &Bun {
+        &self.bun
+    }
+
+    pub fn patty(&self) -> &Patty {
+        &self.patty
+    }
+
+    pub fn toppings(&self) -> &[Topping] {
+        &self.toppings
     }
 }
 
 /// Enum that represents a type of bun
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone,
+)]
 pub enum Bun {
     Sesame,
     Plain,
@@ -109,17 +223,14 @@ pub enum Bun {
 }
 
 impl Bun {
-    fn price(&self) -> f64 {
-        match self {
-            Bun::Sesame => 1.0,
-            Bun::Plain => 0.0,
-            Bun::GlutenFree => 2.0,
-        }
-    }
+    /// Every type of bun, in the order they are declared
+    pub const ALL: [Bun; 3] = [Bun::Sesame, Bun::Plain, Bun::GlutenFree];
 }
 
 /// Enum that represents a type of patty
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone,
+)]
 pub enum Patty {
     Beef,
     Chicken,
@@ -127,17 +238,14 @@ pub enum Patty {
 }
 
 impl Patty {
-    fn price(&self) -> f64 {
-        match self {
-            Patty::Beef => 8.0,
-            Patty::Chicken => 7.0,
-            Patty::Veggie => 6.0,
-        }
-    }
+    /// Every type of patty, in the order they are declared
+    pub const ALL: [Patty; 3] = [Patty::Beef, Patty::Chicken, Patty::Veggie];
 }
 
 /// Enum that represents a type of topping
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone,
+)]
 pub enum Topping {
     Lettuce,
     Tomato,
@@ -148,16 +256,15 @@ pub enum Topping {
 }
 
 impl Topping {
-    fn price(&self) -> f64 {
-        match self {
-            Topping::Lettuce => 0.0,
-            Topping::Tomato => 0.0,
-            Topping::Onion => 0.0,
-            Topping::Pickle => 0.0,
-            Topping::Cheese => 1.0,
-            Topping::Bacon => 2.0,
-        }
-    }
+    /// Every type of topping, in the order they are declared
+    pub const ALL: [Topping; 6] = [
+        Topping::Lettuce,
+        Topping::Tomato,
+        Topping::Onion,
+        Topping::Pickle,
+        Topping::Cheese,
+        Topping::Bacon,
+    ];
 }
 
 #[cfg(test)]
@@ -187,17 +294,77 @@ mod tests {
                 MenuItem::Fries,
                 MenuItem::Drink,
             ],
+            promo_code: None,
         };
-        let order = Order::from(order_request);
+        let order = Order::try_from(order_request).unwrap();
         assert_eq!(
             order,
             Order {
                 id: None,
                 customer: "Alice".to_string(),
                 status: OrderStatus::Pending,
-                total: 20.0,
+                total: Money::from_dollars(20),
+                breakdown: PriceBreakdown {
+                    items: vec![
+                        Money::from_dollars(12),
+                        Money::from_dollars(5),
+                        Money::from_dollars(3)
+                    ],
+                    subtotal: Money::from_dollars(20),
+                    discounts: vec![],
+                    tax: Money::ZERO,
+                },
                 food,
+                price_list: 1,
+                version: 1,
+                estimate: None,
             }
         );
     }
+
+    #[test]
+    fn test_order_status_transitions() {
+        use OrderStatus::*;
+        let all = OrderStatus::ALL;
+        let allowed = [
+            (Pending, Preparing),
+            (Preparing, Transporting),
+            (Transporting, Completed),
+            (Pending, Cancelled),
+            (Preparing, Cancelled),
+        ];
+
+        for from in &all {
+            for to in &all {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from.clone(), to.clone())),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+            // a status is final exactly when nothing can follow it
+            assert_eq!(
+                from.is_final(),
+                all.iter().all(|to| !from.can_transition_to(to)),
+                "{:?}",
+                from
+            );
+            assert_eq!(from.is_final(), from.next().is_none(), "{:?}", from);
+            if let Some(next) = from.next() {
+                assert!(from.can_transition_to(&next), "{:?}", from);
+            }
+        }
+    }
+
+    #[test]
+    fn test_prep_times() {
+        let burger = |toppings| MenuItem::Burger(Burger::new(Bun::Plain, Patty::Beef, toppings));
+        let plain = burger(vec![]).prep_time();
+        let loaded = burger(vec![Topping::Cheese, Topping::Bacon]).prep_time();
+        assert_eq!(loaded - plain, Duration::from_secs(60));
+        assert!(MenuItem::Drink.prep_time() < MenuItem::Fries.prep_time());
+        assert!(MenuItem::Fries.prep_time() < plain);
+    }
 }
@@ -1,33 +1,706 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    io::{self, BufRead, Write},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
 
-use crate::error::AspirinEatsError;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{error::AspirinEatsError, food::Order};
+
+/// Header used to tag a request with an id, which is echoed back in the response
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest `X-Request-Id` accepted from a client; longer ids are replaced with a new one
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// HTTP methods understood by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+}
+
+impl FromStr for Method {
+    type Err = AspirinEatsError;
+
+    /// Parse a method token. Methods are case-sensitive, so `get` is rejected
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "PATCH" => Ok(Method::Patch),
+            "DELETE" => Ok(Method::Delete),
+            "OPTIONS" => Ok(Method::Options),
+            other => Err(AspirinEatsError::UnsupportedMethod(other.to_string())),
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let method = match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+        };
+        write!(f, "{}", method)
+    }
+}
+
+/// HTTP protocol versions understood by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl FromStr for Version {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HTTP/1.0" => Ok(Version::Http10),
+            "HTTP/1.1" => Ok(Version::Http11),
+            other if other.starts_with("HTTP/") => {
+                Err(AspirinEatsError::UnsupportedVersion(other.to_string()))
+            }
+            _ => Err(AspirinEatsError::MalformedRequestLine),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/// Collection of HTTP headers. Names are matched case-insensitively but keep the case they were
+/// inserted with, and repeated headers are kept in the order they were received
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers::default()
+    }
+
+    /// Get the first value for the given header name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get every value for the given header name, in the order they were received
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns true if at least one header with the given name is present
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Set a header, replacing any existing values with the same name
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Add a header, keeping any existing values with the same name. Any CR or LF is stripped
+    /// from the name and value, so a value taken from a request can't add headers of its own or
+    /// split the message
+    pub fn append(&mut self, name: &str, value: &str) {
+        let clean = |s: &str| s.replace(['\r', '\n'], "");
+        self.entries.push((clean(name), clean(value)));
+    }
+
+    /// Remove every header with the given name
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// Iterate over all headers as `(name, value)` pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Largest request a server will read, so a client can't exhaust its memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLimits {
+    /// Maximum size of the request line and headers together, in bytes
+    pub max_header_size: usize,
+
+    /// Maximum size of the body, in bytes, after any chunked encoding is removed
+    pub max_body_size: usize,
+}
+
+impl RequestLimits {
+    /// No limits at all, for messages that are already in memory
+    pub fn unlimited() -> Self {
+        RequestLimits {
+            max_header_size: usize::MAX,
+            max_body_size: usize::MAX,
+        }
+    }
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_header_size: 8 * 1024,
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Largest response head, and chunk size line, that will be read from a server. Bodies aren't
+/// limited, since the server is trusted to send what was asked for
+const RESPONSE_LIMITS: RequestLimits = RequestLimits {
+    max_header_size: 64 * 1024,
+    max_body_size: usize::MAX,
+};
+
+/// A response that goes over [`RESPONSE_LIMITS`] is the server's fault, not the client's
+fn too_large_response(error: AspirinEatsError) -> AspirinEatsError {
+    match error {
+        AspirinEatsError::HeadersTooLarge => AspirinEatsError::InvalidResponse,
+        error => error,
+    }
+}
 
 /// Simple wrapper for an HTTP Request
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpRequest {
     /// The HTTP method used in the request (GET, POST, etc)
-    pub method: Option<String>,
+    pub method: Method,
+
+    /// The request target exactly as the client sent it, including any query string
+    pub target: String,
+
+    /// The percent-decoded path requested by the client, without the query string
+    pub path: String,
 
-    /// The path requested by the client
-    pub path: Option<String>,
+    /// The decoded query string parameters. If a key is repeated, the last value wins
+    pub query: HashMap<String, String>,
+
+    /// The protocol version from the request line
+    pub version: Version,
+
+    /// The request headers
+    pub headers: Headers,
 
     /// The body of the request
     pub body: Option<String>,
+
+    /// The address of the client, if the request was read from a network connection
+    pub remote_addr: Option<SocketAddr>,
+
+    /// Whether the request arrived over a TLS connection
+    pub tls: bool,
+}
+
+impl HttpRequest {
+    /// Read a single request from a stream, within the default [`RequestLimits`]. The body is read
+    /// using Content-Length or chunked Transfer-Encoding; a request with neither has no body. Any
+    /// bytes after the request are left in the reader, so this can be called repeatedly on the
+    /// same connection
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Self, AspirinEatsError> {
+        Self::read_with_limits(reader, &RequestLimits::default())
+    }
+
+    /// Read a single request from a stream, failing with `HeadersTooLarge` or `PayloadTooLarge`
+    /// as soon as it goes over one of the limits
+    pub fn read_with_limits<R: BufRead>(
+        reader: &mut R,
+        limits: &RequestLimits,
+    ) -> Result<Self, AspirinEatsError> {
+        let mut request = Self::read_head(reader, limits.max_header_size)?;
+        let body = match body_framing(&request.headers)? {
+            BodyFraming::None => Vec::new(),
+            framing => read_body(reader, framing, limits)?,
+        };
+        request.body = body_from_bytes(body)?;
+        Ok(request)
+    }
+
+    /// Get a query string parameter by name
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+
+    /// The id the request was tagged with in `X-Request-Id`, if it is a sensible one. Ids are
+    /// passed along so the proxy's and origin's logs for a request can be matched up
+    pub fn request_id(&self) -> Option<&str> {
+        self.headers.get(REQUEST_ID_HEADER).filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+    }
+
+    /// Tag the request with a new random `X-Request-Id`, unless it already has one, returning
+    /// the id
+    pub fn ensure_request_id(&mut self) -> String {
+        if let Some(id) = self.request_id() {
+            return id.to_string();
+        }
+        let id = Uuid::new_v4().to_string();
+        self.headers.insert(REQUEST_ID_HEADER, &id);
+        id
+    }
+
+    /// Whether the client asked for the connection to stay open after this request. HTTP/1.1
+    /// connections are persistent unless the client sends `Connection: close`, while HTTP/1.0
+    /// clients have to opt in with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers
+                .get_all("Connection")
+                .flat_map(|value| value.split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+        match self.version {
+            Version::Http11 => !has_token("close"),
+            Version::Http10 => has_token("keep-alive"),
+        }
+    }
+
+    /// Write the request to a stream as HTTP/1.1, such as to forward it to an upstream server. The
+    /// body is always sent with a Content-Length, since it was de-chunked when it was read
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{} {} HTTP/1.1\r\n", self.method, self.target)?;
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length")
+                && !name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                write!(writer, "{}: {}\r\n", name, value)?;
+            }
+        }
+        let body = self.body.as_deref().unwrap_or_default();
+        if !body.is_empty() || matches!(self.method, Method::Post | Method::Put | Method::Patch) {
+            write!(writer, "Content-Length: {}\r\n", body.len())?;
+        }
+        writer.write_all(b"\r\n")?;
+        writer.write_all(body.as_bytes())?;
+        writer.flush()
+    }
+
+    /// Read the request line and headers, leaving the reader positioned at the start of the body.
+    /// Fails with `HeadersTooLarge` if they take up more than `max_size` bytes
+    fn read_head<R: BufRead>(reader: &mut R, max_size: usize) -> Result<Self, AspirinEatsError> {
+        let mut budget = max_size;
+        let request_line =
+            read_line_within(reader, &mut budget)?.ok_or(AspirinEatsError::IncompleteRequest)?;
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if parts.next().is_none() => {
+                (method, target, version)
+            }
+            _ => return Err(AspirinEatsError::MalformedRequestLine),
+        };
+        let method = Method::from_str(method)?;
+        let version = Version::from_str(version)?;
+        if !target.starts_with('/') {
+            return Err(AspirinEatsError::MalformedRequestLine);
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, parse_query(query)?),
+            None => (target, HashMap::new()),
+        };
+        let path = decode_path(path)?;
+
+        let headers = read_headers(reader, &mut budget)?;
+
+        Ok(HttpRequest {
+            method,
+            target: target.to_string(),
+            path,
+            query,
+            version,
+            headers,
+            body: None,
+            remote_addr: None,
+            tls: false,
+        })
+    }
 }
 
 impl FromStr for HttpRequest {
     type Err = AspirinEatsError;
 
-    // Parse a string into an HTTP Request
+    // Parse a string into an HTTP Request. Since the whole message is available, a request
+    // without Content-Length or Transfer-Encoding takes the rest of the string as its body
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        todo!()
+        let mut reader = s.as_bytes();
+        let mut request = HttpRequest::read_head(&mut reader, usize::MAX)?;
+        if let BodyFraming::None = body_framing(&request.headers)? {
+            request.body = body_from_bytes(reader.to_vec())?;
+            Ok(request)
+        } else {
+            // re-parse from the start so the body is framed the same way as on a stream
+            HttpRequest::read_with_limits(&mut s.as_bytes(), &RequestLimits::unlimited())
+        }
+    }
+}
+
+/// How the end of a message body is determined
+enum BodyFraming {
+    None,
+    Length(usize),
+    Chunked,
+}
+
+/// Work out how the body of a message is delimited from its headers
+fn body_framing(headers: &Headers) -> Result<BodyFraming, AspirinEatsError> {
+    let mut lengths = headers.get_all("Content-Length").peekable();
+    let transfer_encoding = headers.get("Transfer-Encoding");
+
+    if let Some(encoding) = transfer_encoding {
+        if lengths.peek().is_some() {
+            return Err(AspirinEatsError::InvalidContentLength);
+        }
+        if !encoding.eq_ignore_ascii_case("chunked") {
+            return Err(AspirinEatsError::UnsupportedTransferEncoding(
+                encoding.to_string(),
+            ));
+        }
+        return Ok(BodyFraming::Chunked);
+    }
+
+    let mut length = None;
+    for value in lengths.flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(AspirinEatsError::InvalidContentLength);
+        }
+        let parsed = value
+            .parse::<usize>()
+            .map_err(|_| AspirinEatsError::InvalidContentLength)?;
+        match length {
+            Some(existing) if existing != parsed => {
+                return Err(AspirinEatsError::InvalidContentLength)
+            }
+            _ => length = Some(parsed),
+        }
+    }
+
+    Ok(length.map_or(BodyFraming::None, BodyFraming::Length))
+}
+
+/// Read header lines up to and including the empty line that ends them, taking their size out of
+/// `budget`
+fn read_headers<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<Headers, AspirinEatsError> {
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line_within(reader, budget)?.ok_or(AspirinEatsError::IncompleteRequest)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(AspirinEatsError::MalformedHeader)?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(AspirinEatsError::MalformedHeader);
+        }
+        headers.append(name, value.trim());
+    }
+}
+
+/// Read a message body that is delimited by Content-Length or chunked encoding, failing with
+/// `PayloadTooLarge` if it is longer than the limit
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    framing: BodyFraming,
+    limits: &RequestLimits,
+) -> Result<Vec<u8>, AspirinEatsError> {
+    match framing {
+        BodyFraming::None => Ok(Vec::new()),
+        BodyFraming::Length(length) if length > limits.max_body_size => {
+            Err(AspirinEatsError::PayloadTooLarge)
+        }
+        BodyFraming::Length(length) => {
+            let mut body = vec![0; length];
+            read_exact(reader, &mut body)?;
+            Ok(body)
+        }
+        BodyFraming::Chunked => read_chunked_body(reader, limits),
+    }
+}
+
+/// Fill `buf` from the reader. Running out of data means the message was cut short, but other
+/// errors, such as timeouts, are passed on
+fn read_exact<R: BufRead>(reader: &mut R, buf: &mut [u8]) -> Result<(), AspirinEatsError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => AspirinEatsError::IncompleteRequest,
+        _ => AspirinEatsError::Io(e),
+    })
+}
+
+/// Read a single CRLF (or bare LF) terminated line, without the line ending, taking its length
+/// out of `budget`. Returns None if the stream is already at EOF. Fails with `HeadersTooLarge` if
+/// the line doesn't end within the budget
+fn read_line_within<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<Option<String>, AspirinEatsError> {
+    let mut line = Vec::new();
+    let read = <&mut R as io::Read>::take(reader, *budget as u64).read_until(b'\n', &mut line)?;
+    *budget -= read;
+    if read == 0 && *budget > 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(if *budget == 0 {
+            AspirinEatsError::HeadersTooLarge
+        } else {
+            AspirinEatsError::IncompleteRequest
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| AspirinEatsError::InvalidEncoding)
+}
+
+/// Read a body sent with `Transfer-Encoding: chunked`, discarding any chunk extensions and
+/// trailers. Fails with `PayloadTooLarge` once the body goes over the limit. Each chunk size line
+/// must fit within the header size limit, and so must the trailers together, failing with
+/// `InvalidChunkedBody` and `HeadersTooLarge` respectively
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<Vec<u8>, AspirinEatsError> {
+    let max_size = limits.max_body_size;
+    let read_chunk_line = |reader: &mut R| {
+        let mut budget = limits.max_header_size;
+        read_line_within(reader, &mut budget).map_err(|e| match e {
+            AspirinEatsError::HeadersTooLarge => AspirinEatsError::InvalidChunkedBody,
+            e => e,
+        })
+    };
+    let mut body = Vec::new();
+    loop {
+        let line = read_chunk_line(reader)?.ok_or(AspirinEatsError::IncompleteRequest)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(AspirinEatsError::InvalidChunkedBody);
+        }
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| AspirinEatsError::InvalidChunkedBody)?;
+        if size == 0 {
+            break;
+        }
+
+        let start = body.len();
+        if size > max_size - start {
+            return Err(AspirinEatsError::PayloadTooLarge);
+        }
+        body.resize(start + size, 0);
+        read_exact(reader, &mut body[start..])?;
+        match read_chunk_line(reader)? {
+            Some(line) if line.is_empty() => {}
+            Some(_) => return Err(AspirinEatsError::InvalidChunkedBody),
+            None => return Err(AspirinEatsError::IncompleteRequest),
+        }
+    }
+
+    // trailers end with an empty line
+    let mut budget = limits.max_header_size;
+    loop {
+        match read_line_within(reader, &mut budget)? {
+            Some(line) if line.is_empty() => return Ok(body),
+            Some(_) => continue,
+            None => return Err(AspirinEatsError::IncompleteRequest),
+        }
     }
 }
 
+/// Convert raw body bytes into the optional string body stored on a request
+fn body_from_bytes(body: Vec<u8>) -> Result<Option<String>, AspirinEatsError> {
+    if body.is_empty() {
+        return Ok(None);
+    }
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| AspirinEatsError::InvalidEncoding)
+}
+
+/// Parse an `a=1&b=2` query string into decoded key/value pairs
+fn parse_query(query: &str) -> Result<HashMap<String, String>, AspirinEatsError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(key, true)?, percent_decode(value, true)?))
+        })
+        .collect()
+}
+
+/// Decode the path of a request. An encoded `/` is rejected rather than decoded, since routing
+/// splits the decoded path on `/` and `/orders/1%2Fhistory` must not reach `/orders/1/history`
+fn decode_path(path: &str) -> Result<String, AspirinEatsError> {
+    let decoded = percent_decode(path, false)?;
+    if decoded.matches('/').count() != path.matches('/').count() {
+        return Err(AspirinEatsError::InvalidEncoding);
+    }
+    Ok(decoded)
+}
+
+/// Decode `%XX` escapes, and `+` as a space when decoding a query string
+fn percent_decode(s: &str, plus_as_space: bool) -> Result<String, AspirinEatsError> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let high = bytes.next().and_then(hex_value);
+                let low = bytes.next().and_then(hex_value);
+                match (high, low) {
+                    (Some(high), Some(low)) => decoded.push(high << 4 | low),
+                    _ => return Err(AspirinEatsError::InvalidEncoding),
+                }
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| AspirinEatsError::InvalidEncoding)
+}
+
+/// Build an `a=1&b=2` query string, percent-encoding every key and value
+pub fn encode_query<'a, I>(params: I) -> String
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect::<String>()
+    };
+    params
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Value of a single hex digit
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// An HTTP Response, which can be written straight to a stream with [`HttpResponse::write_to`]
+#[derive(Debug, Clone, PartialEq)]
 pub struct HttpResponse {
     status_code: u16,
     status_text: String,
+    headers: Headers,
     body: String,
+
+    /// The rest of the body, sent after `body` as it becomes available
+    stream: Option<BodyStream>,
+}
+
+/// What a [`BodyStream`] produced when asked for more of the body
+#[derive(Debug, PartialEq)]
+pub enum StreamChunk {
+    /// The next piece of the body
+    Data(Vec<u8>),
+
+    /// Nothing arrived in time, but there may be more later
+    Idle,
+
+    /// The body is complete
+    End,
+}
+
+/// A response body that is sent a piece at a time as it is produced, such as a stream of
+/// server-sent events. A response with one has no Content-Length, so it ends when the connection
+/// is closed. Clones share the same stream, so each piece is only produced once
+#[derive(Clone)]
+pub struct BodyStream {
+    next: Arc<Mutex<dyn FnMut(Duration) -> StreamChunk + Send>>,
+}
+
+impl BodyStream {
+    /// Create a stream from a function that waits up to the given time for the next piece
+    pub fn new<F>(next: F) -> Self
+    where
+        F: FnMut(Duration) -> StreamChunk + Send + 'static,
+    {
+        BodyStream {
+            next: Arc::new(Mutex::new(next)),
+        }
+    }
+
+    /// Wait up to `timeout` for the next piece of the body
+    pub fn next(&self, timeout: Duration) -> StreamChunk {
+        let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+        next(timeout)
+    }
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
+impl PartialEq for BodyStream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.next, &other.next)
+    }
 }
 
 impl HttpResponse {
@@ -35,45 +708,648 @@ impl HttpResponse {
         HttpResponse {
             status_code,
             status_text: status_text.to_string(),
+            headers: Headers::new(),
             body: body.to_string(),
+            stream: None,
+        }
+    }
+
+    /// Create a response whose body is the JSON representation of `value`
+    pub fn json<T: Serialize>(
+        status_code: u16,
+        status_text: &str,
+        value: &T,
+    ) -> Result<Self, AspirinEatsError> {
+        Ok(
+            HttpResponse::new(status_code, status_text, &serde_json::to_string(value)?)
+                .with_header("Content-Type", "application/json"),
+        )
+    }
+
+    /// Create the response for an error, quoting the id of the request that caused it in the
+    /// body so a failure a client reports can be found in the logs
+    pub fn from_error(error: AspirinEatsError, request_id: Option<&str>) -> Self {
+        let mut response = HttpResponse::from(error);
+        if let Some(id) = request_id {
+            response.body = format!("{}\nRequest ID: {}", response.body, id);
+        }
+        response
+    }
+
+    /// Set a header on the response, replacing any existing value
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Send the rest of the body from a stream, after the body the response already has
+    pub fn with_stream(mut self, stream: BodyStream) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    /// The stream the rest of the body comes from, if it is streamed
+    pub fn stream(&self) -> Option<&BodyStream> {
+        self.stream.as_ref()
+    }
+
+    /// Whether the response is a stream of server-sent events, which carries on until one side
+    /// closes the connection
+    pub fn is_event_stream(&self) -> bool {
+        self.headers.get("Content-Type").is_some_and(|value| {
+            value
+                .split(';')
+                .next()
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+        })
+    }
+
+    /// Read a response from a stream, such as one sent back by an upstream server. A response
+    /// with neither Content-Length nor Transfer-Encoding is delimited by the connection closing.
+    /// A chunked body is de-chunked, so the response can be re-sent with a Content-Length
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Self, AspirinEatsError> {
+        Self::read_response_to(reader, &Method::Get)
+    }
+
+    /// Read the response to a request made with `method`. Responses to HEAD requests never have a
+    /// body, even though their headers describe one
+    pub fn read_response_to<R: BufRead>(
+        reader: &mut R,
+        method: &Method,
+    ) -> Result<Self, AspirinEatsError> {
+        Self::read_head(reader)?.read_body(reader, method)
+    }
+
+    /// Read the status line and headers of a response, leaving the reader positioned at the
+    /// start of the body. The body can then be read with [`HttpResponse::read_body`], or passed
+    /// on as it arrives if it is an event stream
+    pub fn read_head<R: BufRead>(reader: &mut R) -> Result<Self, AspirinEatsError> {
+        let mut budget = RESPONSE_LIMITS.max_header_size;
+        let status_line = read_line_within(reader, &mut budget)
+            .map_err(too_large_response)?
+            .ok_or(AspirinEatsError::IncompleteRequest)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let (status_code, status_text) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(version), Some(code), text) if version.starts_with("HTTP/1.") => (
+                code.parse::<u16>()
+                    .map_err(|_| AspirinEatsError::InvalidResponse)?,
+                text.unwrap_or_default(),
+            ),
+            _ => return Err(AspirinEatsError::InvalidResponse),
+        };
+
+        let mut response = HttpResponse::new(status_code, status_text, "");
+        response.headers = read_headers(reader, &mut budget).map_err(too_large_response)?;
+        Ok(response)
+    }
+
+    /// Read the body of a response whose head was read with [`HttpResponse::read_head`], in
+    /// answer to a request made with `method`
+    pub fn read_body<R: BufRead>(
+        mut self,
+        reader: &mut R,
+        method: &Method,
+    ) -> Result<Self, AspirinEatsError> {
+        let status_code = self.status_code;
+        let body = match body_framing(&self.headers)? {
+            // informational, No Content and Not Modified responses never have a body
+            _ if *method == Method::Head => Vec::new(),
+            BodyFraming::None if status_code < 200 || status_code == 204 || status_code == 304 => {
+                Vec::new()
+            }
+            BodyFraming::None => {
+                let mut body = Vec::new();
+                reader.read_to_end(&mut body)?;
+                body
+            }
+            BodyFraming::Chunked => {
+                self.headers.remove("Transfer-Encoding");
+                read_body(reader, BodyFraming::Chunked, &RESPONSE_LIMITS)
+                    .map_err(too_large_response)?
+            }
+            framing => read_body(reader, framing, &RESPONSE_LIMITS)?,
+        };
+        self.body = String::from_utf8(body).map_err(|_| AspirinEatsError::InvalidEncoding)?;
+        Ok(self)
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn status_text(&self) -> &str {
+        &self.status_text
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Write the response to a stream without first building it into a single string. Only the
+    /// start of a streamed body is written; the rest has to be sent as the stream produces it
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.status_line())?;
+        for (name, value) in self.all_headers() {
+            write!(writer, "{}: {}\r\n", name, value)?;
         }
+        writer.write_all(b"\r\n")?;
+        writer.write_all(self.body.as_bytes())?;
+        writer.flush()
+    }
+
+    fn status_line(&self) -> String {
+        format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status_text)
+    }
+
+    /// The headers that will be sent, including a Content-Length computed from the body unless
+    /// one was set explicitly or the body is streamed
+    fn all_headers(&self) -> impl Iterator<Item = (&str, String)> {
+        let content_length = (!self.headers.contains("Content-Length")
+            && !self.headers.contains("Transfer-Encoding")
+            && self.stream.is_none())
+        .then(|| ("Content-Length", self.body.len().to_string()));
+        self.headers
+            .iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .chain(content_length)
     }
 }
 
 impl Display for HttpResponse {
     /// Convert an HttpResponse struct to a valid HTTP Response
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        write!(f, "{}", self.status_line())?;
+        for (name, value) in self.all_headers() {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        write!(f, "\r\n{}", self.body)
+    }
+}
+
+impl From<Order> for HttpResponse {
+    /// Respond with the JSON representation of an order, tagged with its version
+    fn from(order: Order) -> Self {
+        HttpResponse::new(200, "OK", &order.to_string())
+            .with_header("Content-Type", "application/json")
+            .with_header("ETag", &order_etag(&order))
+    }
+}
+
+/// The ETag of the given version of an order. Since `If-Match` only compares versions, this
+/// matches the order at that version whatever its estimate
+pub fn etag(version: u32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// The ETag an order is sent with: its version, followed by its estimate once it has one, so a
+/// copy fetched before the kitchen set the estimate is never taken for the current one
+pub fn order_etag(order: &Order) -> String {
+    match order.estimate {
+        Some(estimate) => format!(
+            "\"{}-{}-{}\"",
+            order.version, estimate.ready_at, estimate.delivered_at
+        ),
+        None => etag(order.version),
     }
 }
 
+/// The version of an order an ETag was made for, ignoring any estimate. None for weak ETags and
+/// ones that aren't for an order
+pub fn etag_version(tag: &str) -> Option<u32> {
+    let tag = tag.strip_prefix('"')?.strip_suffix('"')?;
+    tag.split('-').next()?.parse().ok()
+}
+
 impl From<AspirinEatsError> for HttpResponse {
     /// Given an error type, convert it to an appropriate HTTP Response
     fn from(value: AspirinEatsError) -> Self {
-        todo!()
+        let (status_code, status_text) = match &value {
+            AspirinEatsError::ParseError(_)
+            | AspirinEatsError::InvalidRequest
+            | AspirinEatsError::MalformedRequestLine
+            | AspirinEatsError::MalformedHeader
+            | AspirinEatsError::InvalidEncoding
+            | AspirinEatsError::InvalidContentLength
+            | AspirinEatsError::InvalidChunkedBody
+            | AspirinEatsError::IncompleteRequest
+            | AspirinEatsError::InvalidQuery(_)
+            | AspirinEatsError::InvalidHeader(_)
+            | AspirinEatsError::UnknownPromoCode(_) => (400, "Bad Request"),
+            AspirinEatsError::Unauthorized => (401, "Unauthorized"),
+            AspirinEatsError::Forbidden => (403, "Forbidden"),
+            AspirinEatsError::RequestTimeout => (408, "Request Timeout"),
+            AspirinEatsError::PayloadTooLarge => (413, "Content Too Large"),
+            AspirinEatsError::TooManyRequests { .. } => (429, "Too Many Requests"),
+            AspirinEatsError::HeadersTooLarge => (431, "Request Header Fields Too Large"),
+            AspirinEatsError::NotFound => (404, "Not Found"),
+            AspirinEatsError::MethodNotAllowed(_) => (405, "Method Not Allowed"),
+            AspirinEatsError::InvalidTransition { .. } => (409, "Conflict"),
+            AspirinEatsError::PreconditionFailed => (412, "Precondition Failed"),
+            AspirinEatsError::IdempotencyKeyReused => (422, "Unprocessable Content"),
+            AspirinEatsError::PreconditionRequired => (428, "Precondition Required"),
+            AspirinEatsError::UnsupportedMethod(_)
+            | AspirinEatsError::UnsupportedTransferEncoding(_) => (501, "Not Implemented"),
+            AspirinEatsError::UnsupportedVersion(_) => (505, "HTTP Version Not Supported"),
+            AspirinEatsError::InvalidResponse => (502, "Bad Gateway"),
+            AspirinEatsError::NoHealthyUpstream | AspirinEatsError::ServerBusy => {
+                (503, "Service Unavailable")
+            }
+            AspirinEatsError::Database(_)
+            | AspirinEatsError::CorruptOrder(_)
+            | AspirinEatsError::InvalidMenu(_)
+            | AspirinEatsError::DuplicateApiKey
+            | AspirinEatsError::InvalidCertificate(_)
+            | AspirinEatsError::Io(_) => (500, "Internal Server Error"),
+        };
+
+        // don't leak the details of internal errors to the client
+        let body = if status_code == 500 {
+            status_text.to_string()
+        } else {
+            value.to_string()
+        };
+        let response = HttpResponse::new(status_code, status_text, &body)
+            .with_header("Content-Type", "text/plain; charset=utf-8");
+        match value {
+            AspirinEatsError::MethodNotAllowed(allowed) => {
+                let allowed: Vec<String> = allowed.iter().map(Method::to_string).collect();
+                response.with_header("Allow", &allowed.join(", "))
+            }
+            AspirinEatsError::Unauthorized => response.with_header("WWW-Authenticate", "Bearer"),
+            AspirinEatsError::TooManyRequests { retry_after } => {
+                response.with_header("Retry-After", &retry_after.to_string())
+            }
+            _ => response,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        food::{Estimate, MenuItem, OrderStatus},
+        money::Money,
+        pricing::PriceBreakdown,
+    };
 
     #[test]
     fn test_http_request_from_str() {
         let request = "GET /orders HTTP/1.1\r\nHost: localhost:8080\r\n\r\nthis is the body.";
         let http_request = HttpRequest::from_str(request).unwrap();
-        assert_eq!(http_request.method, Some("GET".to_string()));
-        assert_eq!(http_request.path, Some("/orders".to_string()));
+        assert_eq!(http_request.method, Method::Get);
+        assert_eq!(http_request.path, "/orders");
         assert_eq!(http_request.body, Some("this is the body.".to_string()));
     }
 
+    #[test]
+    fn test_headers_strip_line_breaks() {
+        let mut headers = Headers::new();
+        headers.insert("X-Name\r\n", "Amit\r\nSet-Cookie: stolen");
+        assert_eq!(headers.get("X-Name"), Some("AmitSet-Cookie: stolen"));
+        let response = HttpResponse::new(200, "OK", "").with_header("Location", "/\r\n\r\nbody");
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes).unwrap();
+        let written = String::from_utf8(bytes).unwrap();
+        assert!(written.contains("Location: /body\r\n"), "{}", written);
+        assert_eq!(written.matches("\r\n\r\n").count(), 1);
+    }
+
+    #[test]
+    fn test_http_request_headers_are_case_insensitive() {
+        let request = "POST /orders HTTP/1.1\r\nhost: localhost\r\nX-Custom:  spaced \r\n\r\n";
+        let http_request = HttpRequest::from_str(request).unwrap();
+        assert_eq!(http_request.headers.get("Host"), Some("localhost"));
+        assert_eq!(http_request.headers.get("HOST"), Some("localhost"));
+        assert_eq!(http_request.headers.get("x-custom"), Some("spaced"));
+        assert_eq!(http_request.headers.get("Content-Length"), None);
+        assert_eq!(http_request.body, None);
+    }
+
+    #[test]
+    fn test_http_request_query_string() {
+        let request =
+            "GET /orders/all%20time?status=Pending&customer=Amit+K&empty HTTP/1.1\r\n\r\n";
+        let http_request = HttpRequest::from_str(request).unwrap();
+        assert_eq!(http_request.path, "/orders/all time");
+        assert_eq!(http_request.query_param("status"), Some("Pending"));
+        assert_eq!(http_request.query_param("customer"), Some("Amit K"));
+        assert_eq!(http_request.query_param("empty"), Some(""));
+        assert_eq!(http_request.query_param("missing"), None);
+    }
+
+    #[test]
+    fn test_encode_query_round_trip() {
+        let query = encode_query([("customer", "Amit K&co"), ("status", "Pending")]);
+        assert_eq!(query, "customer=Amit%20K%26co&status=Pending");
+
+        let request = HttpRequest::from_str(&format!("GET /?{} HTTP/1.1\r\n\r\n", query)).unwrap();
+        assert_eq!(request.query_param("customer"), Some("Amit K&co"));
+        assert_eq!(request.query_param("status"), Some("Pending"));
+    }
+
+    #[test]
+    fn test_http_request_content_length_body() {
+        let mut stream =
+            "POST /orders HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n"
+                .as_bytes();
+        let first = HttpRequest::read_from(&mut stream).unwrap();
+        assert_eq!(first.method, Method::Post);
+        assert_eq!(first.body, Some("hello".to_string()));
+
+        // the next request on the stream is left untouched
+        let second = HttpRequest::read_from(&mut stream).unwrap();
+        assert_eq!(second.method, Method::Get);
+        assert_eq!(second.path, "/");
+        assert_eq!(second.body, None);
+    }
+
+    #[test]
+    fn test_http_request_chunked_body() {
+        let request = "POST /orders HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        let http_request = HttpRequest::from_str(request).unwrap();
+        assert_eq!(http_request.body, Some("hello, world".to_string()));
+    }
+
+    #[test]
+    fn test_http_request_errors() {
+        let parse = |request: &str| HttpRequest::from_str(request).unwrap_err();
+
+        assert!(matches!(
+            parse("GET /orders\r\n\r\n"),
+            AspirinEatsError::MalformedRequestLine
+        ));
+        assert!(matches!(
+            parse("GET orders HTTP/1.1\r\n\r\n"),
+            AspirinEatsError::MalformedRequestLine
+        ));
+        assert!(matches!(
+            parse("BREW /orders HTTP/1.1\r\n\r\n"),
+            AspirinEatsError::UnsupportedMethod(method) if method == "BREW"
+        ));
+        assert!(matches!(
+            parse("GET /orders HTTP/2.0\r\n\r\n"),
+            AspirinEatsError::UnsupportedVersion(version) if version == "HTTP/2.0"
+        ));
+        assert!(matches!(
+            parse("GET /orders HTTP/1.1\r\nno colon\r\n\r\n"),
+            AspirinEatsError::MalformedHeader
+        ));
+        assert!(matches!(
+            parse("GET /orders%zz HTTP/1.1\r\n\r\n"),
+            AspirinEatsError::InvalidEncoding
+        ));
+        assert!(matches!(
+            parse("GET /orders/1%2Fhistory HTTP/1.1\r\n\r\n"),
+            AspirinEatsError::InvalidEncoding
+        ));
+        assert!(matches!(
+            parse("GET /orders/1%2fhistory HTTP/1.1\r\n\r\n"),
+            AspirinEatsError::InvalidEncoding
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nContent-Length: abc\r\n\r\n"),
+            AspirinEatsError::InvalidContentLength
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"),
+            AspirinEatsError::InvalidContentLength
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            AspirinEatsError::IncompleteRequest
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            AspirinEatsError::UnsupportedTransferEncoding(encoding) if encoding == "gzip"
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"),
+            AspirinEatsError::InvalidChunkedBody
+        ));
+        assert!(matches!(
+            parse("GET /orders HTTP/1.1\r\nHost: localhost\r\n"),
+            AspirinEatsError::IncompleteRequest
+        ));
+    }
+
+    #[test]
+    fn test_http_request_limits() {
+        let limits = RequestLimits {
+            max_header_size: 64,
+            max_body_size: 4,
+        };
+        let parse = |request: &str| HttpRequest::read_with_limits(&mut request.as_bytes(), &limits);
+
+        assert!(parse("POST /orders HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd").is_ok());
+        assert!(matches!(
+            parse(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64))),
+            Err(AspirinEatsError::HeadersTooLarge)
+        ));
+        assert!(matches!(
+            parse(&format!(
+                "GET / HTTP/1.1\r\n{}\r\n",
+                "Header: value\r\n".repeat(4)
+            )),
+            Err(AspirinEatsError::HeadersTooLarge)
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nContent-Length: 5\r\n\r\nabcde"),
+            Err(AspirinEatsError::PayloadTooLarge)
+        ));
+        assert!(matches!(
+            parse("POST /orders HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n"),
+            Err(AspirinEatsError::PayloadTooLarge)
+        ));
+
+        // the lines around chunks are limited too, so an endless one can't exhaust memory
+        let chunked = "POST /orders HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(matches!(
+            parse(&format!(
+                "{}1;{}\r\na\r\n0\r\n\r\n",
+                chunked,
+                "x".repeat(64)
+            )),
+            Err(AspirinEatsError::InvalidChunkedBody)
+        ));
+        assert!(matches!(
+            parse(&format!("{}1\r\na{}\r\n0\r\n\r\n", chunked, "x".repeat(64))),
+            Err(AspirinEatsError::InvalidChunkedBody)
+        ));
+        assert!(parse(&format!("{}1\r\na\r\n0\r\nTrailer: 1\r\n\r\n", chunked)).is_ok());
+        assert!(matches!(
+            parse(&format!(
+                "{}1\r\na\r\n0\r\n{}\r\n",
+                chunked,
+                "Trailer: value\r\n".repeat(5)
+            )),
+            Err(AspirinEatsError::HeadersTooLarge)
+        ));
+    }
+
     #[test]
     fn test_http_response_to_string() {
         let response = HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!");
         assert_eq!(
             response.to_string(),
-            "HTTP/1.1 200 OK\r\n\r\nWelcome to Aspirin Eats!"
+            "HTTP/1.1 200 OK\r\nContent-Length: 24\r\n\r\nWelcome to Aspirin Eats!"
+        );
+    }
+
+    #[test]
+    fn test_http_response_headers() {
+        let response = HttpResponse::new(201, "Created", "")
+            .with_header("Location", "/orders/1")
+            .with_header("location", "/orders/2");
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 201 Created\r\nlocation: /orders/2\r\nContent-Length: 0\r\n\r\n"
+        );
+
+        // an explicit Content-Length is not overwritten
+        let response = HttpResponse::new(200, "OK", "abc").with_header("Content-Length", "3");
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc"
+        );
+    }
+
+    #[test]
+    fn test_http_response_write_to() {
+        let response = HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!");
+        let mut stream = Vec::new();
+        response.write_to(&mut stream).unwrap();
+        assert_eq!(String::from_utf8(stream).unwrap(), response.to_string());
+    }
+
+    #[test]
+    fn test_http_response_read_from() {
+        let response = HttpResponse::new(200, "OK", "hello").with_header("X-Test", "1");
+        let bytes = response.to_string();
+        assert_eq!(
+            HttpResponse::read_from(&mut bytes.as_bytes()).unwrap(),
+            response.with_header("Content-Length", "5")
+        );
+
+        let mut chunked =
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n".as_bytes();
+        let response = HttpResponse::read_from(&mut chunked).unwrap();
+        assert_eq!(response.body, "abc");
+        assert_eq!(response.headers.get("Transfer-Encoding"), None);
+
+        let mut unframed = "HTTP/1.0 404 Not Found\r\n\r\nuntil close".as_bytes();
+        let response = HttpResponse::read_from(&mut unframed).unwrap();
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response.status_text, "Not Found");
+        assert_eq!(response.body, "until close");
+
+        assert!(matches!(
+            HttpResponse::read_from(&mut "garbage\r\n\r\n".as_bytes()),
+            Err(AspirinEatsError::InvalidResponse)
+        ));
+
+        let endless = format!("HTTP/1.1 200 OK\r\nX-Test: {}", "x".repeat(64 * 1024));
+        assert!(matches!(
+            HttpResponse::read_from(&mut endless.as_bytes()),
+            Err(AspirinEatsError::InvalidResponse)
+        ));
+        let endless = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1;{}",
+            "x".repeat(64 * 1024)
+        );
+        assert!(matches!(
+            HttpResponse::read_from(&mut endless.as_bytes()),
+            Err(AspirinEatsError::InvalidChunkedBody)
+        ));
+    }
+
+    #[test]
+    fn test_http_request_write_to() {
+        let request = HttpRequest::from_str(
+            "POST /orders?customer=Amit%20K HTTP/1.0\r\nHost: localhost\r\n\
+            Transfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        let mut stream = Vec::new();
+        request.write_to(&mut stream).unwrap();
+        assert_eq!(
+            String::from_utf8(stream).unwrap(),
+            "POST /orders?customer=Amit%20K HTTP/1.1\r\nHost: localhost\r\n\
+            Content-Length: 3\r\n\r\nabc"
+        );
+
+        let request = HttpRequest::from_str("GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut stream = Vec::new();
+        request.write_to(&mut stream).unwrap();
+        assert_eq!(String::from_utf8(stream).unwrap(), "GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn test_http_response_to_head_request_has_no_body() {
+        let mut bytes =
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n"
+                .as_bytes();
+        let response = HttpResponse::read_response_to(&mut bytes, &Method::Head).unwrap();
+        assert_eq!(response.body, "");
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        let response = HttpResponse::read_from(&mut bytes).unwrap();
+        assert_eq!(response.status_code, 204);
+    }
+
+    #[test]
+    fn test_http_request_keep_alive() {
+        let parse = |request: &str| HttpRequest::from_str(request).unwrap().keep_alive();
+        assert!(parse("GET / HTTP/1.1\r\n\r\n"));
+        assert!(!parse("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!parse("GET / HTTP/1.0\r\n\r\n"));
+        assert!(parse("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_http_response_from_order() {
+        let order = Order {
+            id: Some(1),
+            customer: "Amit".to_string(),
+            food: vec![MenuItem::Fries],
+            status: OrderStatus::Pending,
+            total: Money::from_dollars(5),
+            breakdown: PriceBreakdown::default(),
+            price_list: 1,
+            version: 3,
+            estimate: None,
+        };
+        let response = HttpResponse::from(order.clone());
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers.get("ETag"), Some("\"3\""));
+        assert_eq!(
+            response.headers.get("content-type"),
+            Some("application/json")
         );
+        assert_eq!(Order::from_str(&response.body).unwrap(), order);
+
+        let response = HttpResponse::json(200, "OK", &vec![order.clone()]).unwrap();
+        assert_eq!(response.body, format!("[{}]", order));
+
+        // the estimate is part of the ETag, but not of the version it is for
+        let mut order = order;
+        order.estimate = Some(Estimate {
+            ready_at: 10,
+            delivered_at: 20,
+        });
+        let tag = order_etag(&order);
+        assert_eq!(tag, "\"3-10-20\"");
+        assert_eq!(etag_version(&tag), Some(3));
+        assert_eq!(etag_version("\"3\""), Some(3));
+        assert_eq!(etag_version("W/\"3\""), None);
     }
 
     #[test]
@@ -90,16 +1366,62 @@ mod tests {
         assert_eq!(response.status_text, "Not Found");
         assert_eq!(response.body, "Resource not found");
 
-        let error = AspirinEatsError::MethodNotAllowed;
+        let error = AspirinEatsError::MethodNotAllowed(vec![Method::Get, Method::Post]);
         let response: HttpResponse = error.into();
         assert_eq!(response.status_code, 405);
         assert_eq!(response.status_text, "Method Not Allowed");
         assert_eq!(response.body, "Method not allowed");
+        assert_eq!(response.headers.get("Allow"), Some("GET, POST"));
+
+        let error = AspirinEatsError::InvalidTransition {
+            from: OrderStatus::Completed,
+            to: OrderStatus::Pending,
+        };
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 409);
+        assert_eq!(response.status_text, "Conflict");
+        assert_eq!(
+            response.body,
+            "Cannot change order status from Completed to Pending"
+        );
+
+        let error = AspirinEatsError::UnsupportedMethod("BREW".to_string());
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 501);
+        assert_eq!(response.status_text, "Not Implemented");
+
+        let error = AspirinEatsError::CorruptOrder(3);
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 500);
+        assert_eq!(response.body, "Internal Server Error");
 
-        let error = AspirinEatsError::Io(std::io::Error::new(std::io::ErrorKind::Other, "test"));
+        let error = AspirinEatsError::Io(std::io::Error::other("test"));
         let response: HttpResponse = error.into();
         assert_eq!(response.status_code, 500);
         assert_eq!(response.status_text, "Internal Server Error");
         assert_eq!(response.body, "Internal Server Error");
+
+        let error = AspirinEatsError::TooManyRequests { retry_after: 2 };
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 429);
+        assert_eq!(response.status_text, "Too Many Requests");
+        assert_eq!(response.headers.get("Retry-After"), Some("2"));
+
+        let response: HttpResponse = AspirinEatsError::Unauthorized.into();
+        assert_eq!(response.status_code, 401);
+        assert_eq!(response.headers.get("WWW-Authenticate"), Some("Bearer"));
+
+        for (error, status_code) in [
+            (AspirinEatsError::Forbidden, 403),
+            (AspirinEatsError::PreconditionFailed, 412),
+            (AspirinEatsError::IdempotencyKeyReused, 422),
+            (AspirinEatsError::PreconditionRequired, 428),
+            (AspirinEatsError::RequestTimeout, 408),
+            (AspirinEatsError::PayloadTooLarge, 413),
+            (AspirinEatsError::HeadersTooLarge, 431),
+        ] {
+            let response: HttpResponse = error.into();
+            assert_eq!(response.status_code, status_code);
+        }
     }
 }
@@ -0,0 +1,361 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::AspirinEatsError,
+    food::{Estimate, MenuItem, Order, OrderStatus},
+    server::Shutdown,
+    store::OrderStore,
+};
+
+/// How long an order takes to reach the customer once it leaves the kitchen
+pub const DELIVERY_TIME: Duration = Duration::from_secs(15 * 60);
+
+/// Who the kitchen's changes are recorded as in the history of an order
+pub const KITCHEN_ACTOR: &str = "kitchen";
+
+/// How often the background kitchen looks for work
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the kitchen gets the time from, so tests can control it
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when it is told to
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// When a queued order is due to reach each stage
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timeline {
+    /// When the kitchen starts making the order
+    starts_at: SystemTime,
+    ready_at: SystemTime,
+    delivered_at: SystemTime,
+}
+
+impl Timeline {
+    /// The status the order should have reached by `now`
+    fn status_at(&self, now: SystemTime) -> OrderStatus {
+        if now >= self.delivered_at {
+            OrderStatus::Completed
+        } else if now >= self.ready_at {
+            OrderStatus::Transporting
+        } else if now >= self.starts_at {
+            OrderStatus::Preparing
+        } else {
+            OrderStatus::Pending
+        }
+    }
+
+    fn estimate(&self) -> Estimate {
+        Estimate {
+            ready_at: unix_seconds(self.ready_at),
+            delivered_at: unix_seconds(self.delivered_at),
+        }
+    }
+}
+
+/// Simulates the kitchen and its delivery drivers. Orders are made one at a time in the order
+/// they were placed, each taking as long as the prep times of its items added up, and are then
+/// delivered in `DELIVERY_TIME`, with a driver always free. Orders are moved along as the clock
+/// passes each stage, and the store is given an estimate of when each will be ready
+pub struct Kitchen {
+    clock: Arc<dyn Clock>,
+
+    /// Orders the kitchen has queued and not yet finished with, by ID
+    queue: BTreeMap<i64, Timeline>,
+
+    /// The ID of the last order queued. Orders are given IDs in the order they're placed, so only
+    /// those after it can be new
+    last_queued: i64,
+
+    /// When the kitchen will have made every order queued so far
+    free_at: SystemTime,
+}
+
+impl Kitchen {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let free_at = clock.now();
+        Kitchen {
+            clock,
+            queue: BTreeMap::new(),
+            last_queued: 0,
+            free_at,
+        }
+    }
+
+    /// Queue any orders the kitchen hasn't seen yet, then move every queued order along to the
+    /// status it should have reached by now. Orders that are cancelled or deleted are dropped
+    /// from the queue, though the time set aside for them isn't given to the orders after them.
+    /// Errors are logged and the rest of the orders carry on, since the next tick may succeed
+    pub fn tick<S: OrderStore>(&mut self, store: &S) {
+        let now = self.clock.now();
+        self.queue_new_orders(store, now);
+
+        let mut finished = Vec::new();
+        for (id, timeline) in &self.queue {
+            match advance(store, *id, &timeline.status_at(now)) {
+                Ok(true) => finished.push(*id),
+                Ok(false) => {}
+                Err(e) => eprintln!("Kitchen error with order {}: {}", id, e),
+            }
+        }
+        for id in finished {
+            self.queue.remove(&id);
+        }
+    }
+
+    /// Tick every `TICK_INTERVAL` until `shutdown` is triggered, only locking the store while
+    /// ticking
+    pub fn run<S: OrderStore>(mut self, store: &Mutex<S>, shutdown: &Shutdown) {
+        while !shutdown.is_triggered() {
+            self.tick(&*store.lock().unwrap_or_else(PoisonError::into_inner));
+            thread::sleep(TICK_INTERVAL);
+        }
+    }
+
+    /// Queue every order placed since the last one queued that still has somewhere to go, oldest
+    /// first. If one can't be queued, it and the orders after it are left for the next tick, so
+    /// none of them get ahead of it
+    fn queue_new_orders<S: OrderStore>(&mut self, store: &S, now: SystemTime) {
+        let orders = match store.unfinished_orders(self.last_queued) {
+            Ok(orders) => orders,
+            Err(e) => {
+                eprintln!("Kitchen error finding new orders: {}", e);
+                return;
+            }
+        };
+        for order in orders {
+            let Some(id) = order.id else {
+                continue;
+            };
+            let timeline = self.schedule(&order, now);
+            if let Err(e) = store.set_estimate(id, Some(timeline.estimate())) {
+                eprintln!("Kitchen error with order {}: {}", id, e);
+                return;
+            }
+            if order.status != OrderStatus::Transporting {
+                self.free_at = timeline.ready_at;
+            }
+            self.queue.insert(id, timeline);
+            self.last_queued = id;
+        }
+    }
+
+    /// Work out when an order would be made if it were queued now. An order that has already left
+    /// the kitchen, such as one that was on its way when the server restarted, only needs
+    /// delivering
+    fn schedule(&self, order: &Order, now: SystemTime) -> Timeline {
+        if order.status == OrderStatus::Transporting {
+            return Timeline {
+                starts_at: now,
+                ready_at: now,
+                delivered_at: now + DELIVERY_TIME,
+            };
+        }
+        let starts_at = self.free_at.max(now);
+        let ready_at = starts_at + order.food.iter().map(MenuItem::prep_time).sum::<Duration>();
+        Timeline {
+            starts_at,
+            ready_at,
+            delivered_at: ready_at + DELIVERY_TIME,
+        }
+    }
+}
+
+/// Move an order forward one status at a time until it reaches `target`, so every step is
+/// published and recorded. Returns true once the kitchen is done with the order, because it has
+/// been completed, cancelled or deleted
+fn advance<S: OrderStore>(
+    store: &S,
+    id: i64,
+    target: &OrderStatus,
+) -> Result<bool, AspirinEatsError> {
+    let Some(mut order) = store.get_order(id)? else {
+        return Ok(true);
+    };
+    if order.status == OrderStatus::Cancelled {
+        store.set_estimate(id, None)?;
+        return Ok(true);
+    }
+    while comes_before(&order.status, target) {
+        let Some(next) = order.status.next() else {
+            break;
+        };
+        order = store.update_status(id, next, Some(order.version), KITCHEN_ACTOR)?;
+    }
+    Ok(order.status.is_final())
+}
+
+/// Whether an order at `status` has yet to reach `target` on its way to being completed
+fn comes_before(status: &OrderStatus, target: &OrderStatus) -> bool {
+    let mut next = status.next();
+    while let Some(status) = next {
+        if status == *target {
+            return true;
+        }
+        next = status.next();
+    }
+    false
+}
+
+fn unix_seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        food::{Bun, Burger, OrderRequest, Patty, Topping},
+        store::{MemoryStore, OrderEventKind},
+    };
+
+    const START: Duration = Duration::from_secs(1_000_000);
+
+    fn place(store: &MemoryStore, food: Vec<MenuItem>) -> i64 {
+        let order = Order::try_from(OrderRequest {
+            customer: "Amit".to_string(),
+            food,
+            promo_code: None,
+        })
+        .unwrap();
+        store.add_order(order, "customer:Amit").unwrap()
+    }
+
+    fn status(store: &MemoryStore, id: i64) -> OrderStatus {
+        store.get_order(id).unwrap().unwrap().status
+    }
+
+    #[test]
+    fn test_orders_move_through_the_kitchen() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + START));
+        let mut kitchen = Kitchen::new(clock.clone());
+        let store = MemoryStore::new();
+
+        // 5 minutes for a burger with two toppings, then 3 for the fries
+        let burger = MenuItem::Burger(Burger::new(
+            Bun::Plain,
+            Patty::Beef,
+            vec![Topping::Cheese, Topping::Bacon],
+        ));
+        let first = place(&store, vec![burger]);
+        let second = place(&store, vec![MenuItem::Fries]);
+        let cancelled = place(&store, vec![MenuItem::Drink]);
+        kitchen.tick(&store);
+
+        let start = START.as_secs() as i64;
+        let estimate = |id| store.get_order(id).unwrap().unwrap().estimate;
+        assert_eq!(
+            estimate(first),
+            Some(Estimate {
+                ready_at: start + 300,
+                delivered_at: start + 1200
+            })
+        );
+        assert_eq!(estimate(second).unwrap().ready_at, start + 480);
+        assert_eq!(status(&store, first), OrderStatus::Preparing);
+        assert_eq!(status(&store, second), OrderStatus::Pending);
+
+        store
+            .update_status(cancelled, OrderStatus::Cancelled, None, "customer:Amit")
+            .unwrap();
+        clock.advance(Duration::from_secs(300));
+        kitchen.tick(&store);
+        assert_eq!(status(&store, first), OrderStatus::Transporting);
+        assert_eq!(status(&store, second), OrderStatus::Preparing);
+        assert_eq!(estimate(cancelled), None);
+
+        // a tick that comes late still takes the order through every status
+        clock.advance(Duration::from_secs(1200));
+        kitchen.tick(&store);
+        assert_eq!(status(&store, first), OrderStatus::Completed);
+        assert_eq!(status(&store, second), OrderStatus::Completed);
+        let history: Vec<_> = store
+            .order_history(second)
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.kind, event.status, event.actor))
+            .collect();
+        let step = |status| {
+            (
+                OrderEventKind::StatusChanged,
+                Some(status),
+                KITCHEN_ACTOR.to_string(),
+            )
+        };
+        assert_eq!(
+            history[1..],
+            [
+                step(OrderStatus::Preparing),
+                step(OrderStatus::Transporting),
+                step(OrderStatus::Completed)
+            ]
+        );
+        assert!(kitchen.queue.is_empty());
+
+        // an idle kitchen starts on a new order straight away
+        let next = place(&store, vec![MenuItem::Drink]);
+        kitchen.tick(&store);
+        assert_eq!(status(&store, next), OrderStatus::Preparing);
+    }
+
+    #[test]
+    fn test_orders_moved_on_by_staff() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + START));
+        let mut kitchen = Kitchen::new(clock.clone());
+        let store = MemoryStore::new();
+        let id = place(&store, vec![MenuItem::Fries]);
+        store
+            .update_status(id, OrderStatus::Preparing, None, "staff")
+            .unwrap();
+        store
+            .update_status(id, OrderStatus::Transporting, None, "staff")
+            .unwrap();
+
+        // the kitchen never moves an order backwards, and only has to deliver this one
+        kitchen.tick(&store);
+        assert_eq!(status(&store, id), OrderStatus::Transporting);
+        clock.advance(DELIVERY_TIME);
+        kitchen.tick(&store);
+        assert_eq!(status(&store, id), OrderStatus::Completed);
+    }
+}
@@ -1,4 +1,19 @@
+pub mod api;
+pub mod auth;
+pub mod client;
+pub mod config;
 pub mod db;
 pub mod error;
+pub mod events;
 pub mod food;
 pub mod http;
+pub mod kitchen;
+pub mod menu;
+pub mod money;
+pub mod openapi;
+pub mod pricing;
+pub mod proxy;
+pub mod router;
+pub mod server;
+pub mod store;
+pub mod tls;
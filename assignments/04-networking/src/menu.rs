@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AspirinEatsError,
+    food::{Bun, MenuItem, Patty, Topping},
+    money::Money,
+    pricing::{Combo, PromoDiscount},
+};
+
+/// The price list that ships with the server, used when no other menu is configured
+const DEFAULT_MENU: &str = include_str!("../menu.json");
+
+/// Prices for everything on the menu, along with the combos, promo codes and tax rate used to
+/// price orders. Price lists are versioned, and every order records the version it was priced
+/// with, so changing prices never changes what existing orders cost
+///
+/// A PriceList can only be built by deserializing it, which checks that it is valid
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "UncheckedPriceList")]
+pub struct PriceList {
+    version: u32,
+    fries: Money,
+    drink: Money,
+    buns: BTreeMap<Bun, Money>,
+    patties: BTreeMap<Patty, Money>,
+    toppings: BTreeMap<Topping, Money>,
+    combos: Vec<Combo>,
+    promo_codes: BTreeMap<String, PromoDiscount>,
+    tax_basis_points: u32,
+}
+
+/// A price list as written in a file, before it has been validated
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UncheckedPriceList {
+    version: u32,
+    fries: Money,
+    drink: Money,
+    buns: BTreeMap<Bun, Money>,
+    patties: BTreeMap<Patty, Money>,
+    toppings: BTreeMap<Topping, Money>,
+    #[serde(default)]
+    combos: Vec<Combo>,
+    #[serde(default)]
+    promo_codes: BTreeMap<String, PromoDiscount>,
+    /// Sales tax in hundredths of a percent, so 825 is 8.25%
+    #[serde(default)]
+    tax_basis_points: u32,
+}
+
+impl PriceList {
+    /// Load and validate a price list from a JSON file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, AspirinEatsError> {
+        Self::from_str(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The price of a single menu item
+    pub fn price(&self, item: &MenuItem) -> Money {
+        match item {
+            MenuItem::Burger(burger) => {
+                self.buns[burger.bun()]
+                    + self.patties[burger.patty()]
+                    + burger
+                        .toppings()
+                        .iter()
+                        .map(|topping| self.toppings[topping])
+                        .sum::<Money>()
+            }
+            MenuItem::Fries => self.fries,
+            MenuItem::Drink => self.drink,
+        }
+    }
+
+    /// Combo deals, in the order they should be applied
+    pub fn combos(&self) -> &[Combo] {
+        &self.combos
+    }
+
+    /// The discount for a promo code, if it exists. Codes are case sensitive
+    pub fn promo_code(&self, code: &str) -> Option<&PromoDiscount> {
+        self.promo_codes.get(code)
+    }
+
+    /// Sales tax in hundredths of a percent, so 825 is 8.25%
+    pub fn tax_basis_points(&self) -> u32 {
+        self.tax_basis_points
+    }
+}
+
+impl Default for PriceList {
+    fn default() -> Self {
+        Self::from_str(DEFAULT_MENU).expect("bundled menu.json should be valid")
+    }
+}
+
+impl FromStr for PriceList {
+    type Err = AspirinEatsError;
+
+    /// Parse and validate a price list from JSON
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|e| AspirinEatsError::InvalidMenu(e.to_string()))
+    }
+}
+
+impl TryFrom<UncheckedPriceList> for PriceList {
+    type Error = AspirinEatsError;
+
+    /// Check that the price list has a version, prices every bun, patty and topping, has no
+    /// negative prices or discounts, and has no percentages over 100
+    fn try_from(prices: UncheckedPriceList) -> Result<Self, Self::Error> {
+        let invalid = |reason: String| Err(AspirinEatsError::InvalidMenu(reason));
+        if prices.version == 0 {
+            return invalid("version must be at least 1".to_string());
+        }
+        if let Some(bun) = Bun::ALL.iter().find(|bun| !prices.buns.contains_key(bun)) {
+            return invalid(format!("missing price for bun {:?}", bun));
+        }
+        if let Some(patty) = Patty::ALL
+            .iter()
+            .find(|patty| !prices.patties.contains_key(patty))
+        {
+            return invalid(format!("missing price for patty {:?}", patty));
+        }
+        if let Some(topping) = Topping::ALL
+            .iter()
+            .find(|topping| !prices.toppings.contains_key(topping))
+        {
+            return invalid(format!("missing price for topping {:?}", topping));
+        }
+
+        let all_prices = [prices.fries, prices.drink]
+            .into_iter()
+            .chain(prices.buns.values().copied())
+            .chain(prices.patties.values().copied())
+            .chain(prices.toppings.values().copied());
+        if let Some(price) = all_prices.into_iter().find(|price| *price < Money::ZERO) {
+            return invalid(format!("price {} is negative", price));
+        }
+
+        for combo in &prices.combos {
+            if combo.items.is_empty() {
+                return invalid(format!("combo {} has no items", combo.name));
+            }
+            if combo.discount < Money::ZERO {
+                return invalid(format!("combo {} has a negative discount", combo.name));
+            }
+        }
+        for (code, discount) in &prices.promo_codes {
+            match discount {
+                PromoDiscount::Percent(percent) if *percent > 100 => {
+                    return invalid(format!("promo code {} is more than 100%", code));
+                }
+                PromoDiscount::Amount(amount) if *amount < Money::ZERO => {
+                    return invalid(format!("promo code {} has a negative discount", code));
+                }
+                _ => {}
+            }
+        }
+        if prices.tax_basis_points > 10_000 {
+            return invalid("tax rate is more than 100%".to_string());
+        }
+
+        Ok(PriceList {
+            version: prices.version,
+            fries: prices.fries,
+            drink: prices.drink,
+            buns: prices.buns,
+            patties: prices.patties,
+            toppings: prices.toppings,
+            combos: prices.combos,
+            promo_codes: prices.promo_codes,
+            tax_basis_points: prices.tax_basis_points,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::Burger;
+
+    fn menu_with(field: &str, value: serde_json::Value) -> String {
+        let mut menu: serde_json::Value = serde_json::from_str(DEFAULT_MENU).unwrap();
+        menu[field] = value;
+        menu.to_string()
+    }
+
+    fn error(menu: &str) -> String {
+        match PriceList::from_str(menu) {
+            Err(AspirinEatsError::InvalidMenu(reason)) => reason,
+            other => panic!("expected an invalid menu, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_default_prices() {
+        let prices = PriceList::default();
+        assert_eq!(prices.version(), 1);
+        assert_eq!(prices.price(&MenuItem::Fries), Money::from_dollars(5));
+        assert_eq!(prices.price(&MenuItem::Drink), Money::from_dollars(3));
+        let burger = Burger::new(
+            Bun::GlutenFree,
+            Patty::Chicken,
+            vec![Topping::Bacon, Topping::Lettuce, Topping::Bacon],
+        );
+        assert_eq!(
+            prices.price(&MenuItem::Burger(burger)),
+            Money::from_dollars(13)
+        );
+    }
+
+    #[test]
+    fn test_custom_prices() {
+        let prices = PriceList::from_str(&menu_with("fries", "4.25".into())).unwrap();
+        assert_eq!(prices.price(&MenuItem::Fries), Money::from_cents(425));
+    }
+
+    #[test]
+    fn test_invalid_menus_are_rejected() {
+        assert!(error(&menu_with("version", 0.into())).contains("version"));
+        assert!(error(&menu_with("drink", (-1).into())).contains("negative"));
+        assert!(error(&menu_with("buns", serde_json::json!({"Sesame": 1})))
+            .contains("missing price for bun Plain"));
+        assert!(
+            error(&menu_with("toppings", serde_json::json!({"Ketchup": 1}))).contains("Ketchup")
+        );
+        assert!(error(&menu_with("pizza", 10.into())).contains("pizza"));
+        assert!(error(&menu_with(
+            "combos",
+            serde_json::json!([{"name": "Nothing", "items": [], "discount": 1}])
+        ))
+        .contains("combo Nothing has no items"));
+        assert!(error(&menu_with(
+            "promo_codes",
+            serde_json::json!({"GREEDY": {"Percent": 150}})
+        ))
+        .contains("promo code GREEDY"));
+        assert!(error(&menu_with("tax_basis_points", 10_001.into())).contains("tax"));
+        assert!(error("not json").contains("expected"));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let prices = PriceList::default();
+        let json = serde_json::to_string(&prices).unwrap();
+        assert_eq!(PriceList::from_str(&json).unwrap(), prices);
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    fmt::Display,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Sub},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// An amount of money, stored as a whole number of cents so arithmetic is exact. It is written as
+/// a decimal number of dollars in JSON, such as `12.5`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money {
+    cents: i64,
+}
+
+impl Money {
+    pub const ZERO: Money = Money { cents: 0 };
+
+    pub const fn from_cents(cents: i64) -> Self {
+        Money { cents }
+    }
+
+    pub const fn from_dollars(dollars: i64) -> Self {
+        Money {
+            cents: dollars * 100,
+        }
+    }
+
+    pub const fn cents(&self) -> i64 {
+        self.cents
+    }
+
+    /// `numerator / denominator` of this amount, rounded to the nearest cent with halves rounded
+    /// away from zero. Used for percentages, such as `fraction(825, 10_000)` for 8.25%
+    pub fn fraction(&self, numerator: i64, denominator: i64) -> Money {
+        let scaled = self.cents as i128 * numerator as i128;
+        let denominator = denominator as i128;
+        let half = denominator / 2 * scaled.signum();
+        Money::from_cents(((scaled + half) / denominator) as i64)
+    }
+
+    /// Convert a decimal number of dollars, rounding to the nearest cent
+    fn from_f64(dollars: f64) -> Option<Self> {
+        let cents = (dollars * 100.0).round();
+        (cents.is_finite() && cents.abs() < i64::MAX as f64)
+            .then(|| Money::from_cents(cents as i64))
+    }
+}
+
+impl Display for Money {
+    /// Format as dollars with exactly two decimal places, such as `12.50`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.cents < 0 { "-" } else { "" };
+        let cents = self.cents.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+/// Error when a string isn't a valid amount of money
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("Invalid amount of money")]
+pub struct ParseMoneyError;
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    /// Parse a decimal number of dollars with at most two decimal places, such as `12`, `12.5`
+    /// or `-0.05`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (dollars, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if dollars.is_empty() || fraction.len() > 2 || !is_digits(dollars) || !is_digits(fraction) {
+            return Err(ParseMoneyError);
+        }
+
+        let dollars: i64 = dollars.parse().map_err(|_| ParseMoneyError)?;
+        let fraction: i64 = format!("{:0<2}", fraction)
+            .parse()
+            .map_err(|_| ParseMoneyError)?;
+        let cents = dollars
+            .checked_mul(100)
+            .and_then(|cents| cents.checked_add(fraction))
+            .ok_or(ParseMoneyError)?;
+        Ok(Money::from_cents(if negative { -cents } else { cents }))
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money::from_cents(self.cents + other.cents)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.cents += other.cents;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money::from_cents(self.cents - other.cents)
+    }
+}
+
+impl Mul<i64> for Money {
+    type Output = Money;
+
+    fn mul(self, quantity: i64) -> Money {
+        Money::from_cents(self.cents * quantity)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // every whole number of cents below 2^53 survives the round trip through an f64
+        serializer.serialize_f64(self.cents as f64 / 100.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    /// Accept a JSON number of dollars, or a string such as `"12.50"`
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl de::Visitor<'_> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "an amount of money")
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Money, E> {
+                Money::from_f64(value).ok_or_else(|| E::custom("amount out of range"))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+                value
+                    .checked_mul(100)
+                    .map(Money::from_cents)
+                    .ok_or_else(|| E::custom("amount out of range"))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+                i64::try_from(value)
+                    .map_err(|_| E::custom("amount out of range"))
+                    .and_then(|value| self.visit_i64(value))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+                Money::from_str(value).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic_is_exact() {
+        let ten_cents = Money::from_cents(10);
+        let twenty_cents = Money::from_cents(20);
+        assert_eq!(ten_cents + twenty_cents, Money::from_cents(30));
+        assert_eq!(
+            [ten_cents; 10].into_iter().sum::<Money>(),
+            Money::from_dollars(1)
+        );
+        assert_eq!(twenty_cents - ten_cents * 3, Money::from_cents(-10));
+    }
+
+    #[test]
+    fn test_fraction_rounds_to_nearest_cent() {
+        assert_eq!(
+            Money::from_dollars(20).fraction(10, 100),
+            Money::from_dollars(2)
+        );
+        assert_eq!(
+            Money::from_cents(1005).fraction(825, 10_000),
+            Money::from_cents(83)
+        );
+        assert_eq!(Money::from_cents(10).fraction(1, 4), Money::from_cents(3));
+        assert_eq!(Money::from_cents(-10).fraction(1, 4), Money::from_cents(-3));
+        assert_eq!(Money::from_cents(9).fraction(1, 4), Money::from_cents(2));
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        assert_eq!(Money::from_cents(1250).to_string(), "12.50");
+        assert_eq!(Money::from_cents(5).to_string(), "0.05");
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+
+        assert_eq!(Money::from_str("12"), Ok(Money::from_cents(1200)));
+        assert_eq!(Money::from_str("12.5"), Ok(Money::from_cents(1250)));
+        assert_eq!(Money::from_str("-0.05"), Ok(Money::from_cents(-5)));
+        for invalid in ["", ".5", "1.234", "1,00", "abc", "+1", "1.-5"] {
+            assert_eq!(
+                Money::from_str(invalid),
+                Err(ParseMoneyError),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            serde_json::to_string(&Money::from_cents(2050)).unwrap(),
+            "20.5"
+        );
+        assert_eq!(
+            serde_json::to_string(&Money::from_cents(30)).unwrap(),
+            "0.3"
+        );
+
+        let parse = |json: &str| serde_json::from_str::<Money>(json).unwrap();
+        assert_eq!(parse("20.5"), Money::from_cents(2050));
+        assert_eq!(parse("0.30000000000000004"), Money::from_cents(30));
+        assert_eq!(parse("8"), Money::from_cents(800));
+        assert_eq!(parse("\"8.25\""), Money::from_cents(825));
+        assert!(serde_json::from_str::<Money>("\"eight\"").is_err());
+    }
+}
@@ -0,0 +1,884 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    api::IDEMPOTENCY_KEY_HEADER,
+    auth::API_KEY_HEADER,
+    food::{Bun, OrderStatus, Patty, Topping},
+    pricing::ItemKind,
+    store::OrderEventKind,
+};
+
+/// Every error status the API or the proxy in front of it responds with, along with the name of
+/// its entry in `components/responses` and what it means
+const ERROR_RESPONSES: [(u16, &str, &str); 18] = [
+    (400, "BadRequest", "The request or its body is malformed"),
+    (
+        401,
+        "Unauthorized",
+        "No API key was sent, or it isn't known",
+    ),
+    (403, "Forbidden", "The API key isn't allowed to do this"),
+    (404, "NotFound", "The resource doesn't exist"),
+    (
+        405,
+        "MethodNotAllowed",
+        "The path doesn't support this method",
+    ),
+    (408, "RequestTimeout", "The request took too long to send"),
+    (409, "Conflict", "The order can't move to that status"),
+    (
+        412,
+        "PreconditionFailed",
+        "The order has changed since it was fetched",
+    ),
+    (413, "ContentTooLarge", "The request body is too large"),
+    (
+        422,
+        "UnprocessableContent",
+        "The Idempotency-Key was used for a different request",
+    ),
+    (
+        428,
+        "PreconditionRequired",
+        "The request needs an If-Match header",
+    ),
+    (
+        429,
+        "TooManyRequests",
+        "Too many requests were sent through the proxy, or too many event streams are open",
+    ),
+    (
+        431,
+        "RequestHeaderFieldsTooLarge",
+        "The request line and headers are too large",
+    ),
+    (
+        500,
+        "InternalServerError",
+        "Something went wrong on the server",
+    ),
+    (
+        501,
+        "NotImplemented",
+        "The request uses a method or Transfer-Encoding the server doesn't support",
+    ),
+    (
+        502,
+        "BadGateway",
+        "The proxy got an invalid response from the server",
+    ),
+    (
+        503,
+        "ServiceUnavailable",
+        "The server is too busy, or the proxy has no healthy server to send the request to",
+    ),
+    (
+        505,
+        "HTTPVersionNotSupported",
+        "The request uses an HTTP version other than 1.0 or 1.1",
+    ),
+];
+
+/// Error statuses any request can get, whatever it is for, from the server or the proxy
+const ANY_REQUEST_ERRORS: [u16; 9] = [408, 413, 429, 431, 500, 501, 502, 503, 505];
+
+/// The OpenAPI 3 document describing the API, served at `/openapi.json`. The buns, patties,
+/// toppings and statuses listed come from the types themselves, but the rest of the schemas are
+/// written out by hand, and are checked against real values in the tests
+pub fn spec() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Aspirin Eats",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "responses": error_responses(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "apiKey": { "type": "apiKey", "in": "header", "name": API_KEY_HEADER },
+            },
+        },
+        "security": [{ "bearer": [] }, { "apiKey": [] }],
+    })
+}
+
+fn paths() -> Value {
+    let id = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer" },
+    });
+    let etag = json!({ "ETag": { "schema": { "type": "string" } } });
+    let order = json!({
+        "description": "The order",
+        "headers": etag,
+        "content": json_content(schema("Order")),
+    });
+
+    json!({
+        "/": {
+            "get": public(operation(
+                "Say hello",
+                json!({}),
+                json!({ "200": text("A welcome message") }),
+                &[],
+            )),
+        },
+        "/openapi.json": {
+            "get": public(operation(
+                "Get this document",
+                json!({}),
+                json!({
+                    "200": {
+                        "description": "The OpenAPI document",
+                        "content": json_content(json!({ "type": "object" })),
+                    },
+                }),
+                &[],
+            )),
+        },
+        "/menu": {
+            "get": public(operation(
+                "Get the current price list",
+                json!({}),
+                json!({
+                    "200": {
+                        "description": "The price list new orders are priced with",
+                        "content": json_content(schema("PriceList")),
+                    },
+                }),
+                &[],
+            )),
+        },
+        "/menu/{version}": {
+            "get": public(operation(
+                "Get a price list by version",
+                json!({
+                    "parameters": [{
+                        "name": "version",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer" },
+                    }],
+                }),
+                json!({
+                    "200": {
+                        "description": "The price list",
+                        "content": json_content(schema("PriceList")),
+                    },
+                }),
+                &[404],
+            )),
+        },
+        "/orders": {
+            "get": operation(
+                "List orders. Customers only see their own",
+                json!({ "parameters": list_parameters() }),
+                json!({
+                    "200": {
+                        "description": "A page of orders",
+                        "headers": {
+                            "X-Total-Count": {
+                                "description": "Number of orders matching the query",
+                                "schema": { "type": "integer" },
+                            },
+                            "Link": {
+                                "description": "Link to the next page, if there is one",
+                                "schema": { "type": "string" },
+                            },
+                        },
+                        "content": json_content(json!({
+                            "type": "array",
+                            "items": schema("Order"),
+                        })),
+                    },
+                }),
+                &[400, 401, 403],
+            ),
+            "post": operation(
+                "Place an order. Customers can only place orders in their own name",
+                json!({
+                    "parameters": [{
+                        "name": IDEMPOTENCY_KEY_HEADER,
+                        "in": "header",
+                        "description": "Retrying with the same key replays the first response",
+                        "schema": { "type": "string", "maxLength": 255 },
+                    }],
+                    "requestBody": {
+                        "required": true,
+                        "content": json_content(schema("OrderRequest")),
+                    },
+                }),
+                json!({
+                    "201": {
+                        "description": "The order that was placed",
+                        "headers": {
+                            "Location": { "schema": { "type": "string" } },
+                            "ETag": { "schema": { "type": "string" } },
+                            "Idempotency-Replayed": {
+                                "description": "`true` if this is a replayed response",
+                                "schema": { "type": "string" },
+                            },
+                        },
+                        "content": json_content(schema("Order")),
+                    },
+                }),
+                &[400, 401, 403, 422],
+            ),
+            "delete": operation(
+                "Delete every order. Staff only",
+                json!({}),
+                json!({ "200": text("The orders were deleted") }),
+                &[401, 403],
+            ),
+        },
+        "/orders/{id}": {
+            "get": operation(
+                "Get an order",
+                json!({
+                    "parameters": [id, {
+                        "name": "If-None-Match",
+                        "in": "header",
+                        "description": "ETags of versions of the order the client already has, \
+                            or `*`",
+                        "schema": { "type": "string" },
+                    }],
+                }),
+                json!({
+                    "200": order,
+                    "304": {
+                        "description": "The order is still at a version the client has",
+                        "headers": { "ETag": { "schema": { "type": "string" } } },
+                    },
+                }),
+                &[401, 404],
+            ),
+            "patch": operation(
+                "Change an order's status. Customers can only cancel their orders",
+                json!({
+                    "parameters": [id, if_match(true)],
+                    "requestBody": {
+                        "required": true,
+                        "content": json_content(schema("OrderStatusUpdate")),
+                    },
+                }),
+                json!({ "200": order }),
+                &[400, 401, 403, 404, 409, 412, 428],
+            ),
+            "delete": operation(
+                "Delete an order. Staff only",
+                json!({ "parameters": [id, if_match(false)] }),
+                json!({ "200": text("The order was deleted") }),
+                &[401, 403, 404, 412],
+            ),
+        },
+        "/orders/{id}/events": {
+            "get": operation(
+                "Follow an order's status until it is completed, cancelled or deleted",
+                json!({ "parameters": [id] }),
+                json!({
+                    "200": {
+                        "description": "Server-sent events, each holding the order as JSON",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                    },
+                }),
+                &[401, 404],
+            ),
+        },
+        "/orders/{id}/history": {
+            "get": operation(
+                "Get everything that has happened to an order, oldest first",
+                json!({ "parameters": [id] }),
+                json!({
+                    "200": {
+                        "description": "The order's history",
+                        "content": json_content(json!({
+                            "type": "array",
+                            "items": schema("OrderEvent"),
+                        })),
+                    },
+                }),
+                &[401, 404],
+            ),
+        },
+        "/admin/purge": {
+            "post": operation(
+                "Permanently remove every deleted order. Admins only",
+                json!({}),
+                json!({ "200": text("How many orders were purged") }),
+                &[401, 403],
+            ),
+        },
+    })
+}
+
+/// The query string parameters of `GET /orders`
+fn list_parameters() -> Value {
+    let parameter = |name: &str, description: &str, schema: Value| {
+        json!({
+            "name": name,
+            "in": "query",
+            "description": description,
+            "schema": schema,
+        })
+    };
+    json!([
+        parameter(
+            "customer",
+            "Only orders placed by this customer",
+            json!({ "type": "string" })
+        ),
+        parameter(
+            "status",
+            "Only orders with this status",
+            schema("OrderStatus")
+        ),
+        parameter(
+            "min_total",
+            "Only orders costing at least this much",
+            schema("Money")
+        ),
+        parameter(
+            "max_total",
+            "Only orders costing at most this much",
+            schema("Money")
+        ),
+        parameter(
+            "sort",
+            "Field to sort by, prefixed with `-` for descending",
+            json!({ "type": "string", "enum": ["id", "-id", "total", "-total"], "default": "id" }),
+        ),
+        parameter(
+            "limit",
+            "Most orders to return",
+            json!({ "type": "integer", "minimum": 1, "maximum": 100 }),
+        ),
+        parameter(
+            "offset",
+            "Number of orders to skip",
+            json!({ "type": "integer", "minimum": 0, "default": 0 }),
+        ),
+    ])
+}
+
+fn if_match(required: bool) -> Value {
+    json!({
+        "name": "If-Match",
+        "in": "header",
+        "required": required,
+        "description": "ETag of the version of the order the change is based on, or `*`",
+        "schema": { "type": "string" },
+    })
+}
+
+fn schemas() -> Value {
+    json!({
+        "Money": {
+            "type": "number",
+            "description": "An amount in dollars, exact to the cent",
+        },
+        "Order": {
+            "type": "object",
+            "required": [
+                "id", "customer", "food", "status", "total", "breakdown", "price_list", "version",
+            ],
+            "properties": {
+                "id": { "type": "integer" },
+                "customer": { "type": "string" },
+                "food": { "type": "array", "items": schema("MenuItem") },
+                "status": schema("OrderStatus"),
+                "total": schema("Money"),
+                "breakdown": schema("PriceBreakdown"),
+                "price_list": {
+                    "type": "integer",
+                    "description": "Version of the price list the order was priced with",
+                },
+                "version": {
+                    "type": "integer",
+                    "description": "Goes up by one every time the order changes",
+                },
+                "estimate": schema("Estimate"),
+            },
+            "additionalProperties": false,
+        },
+        "OrderRequest": {
+            "type": "object",
+            "required": ["customer", "food"],
+            "properties": {
+                "customer": { "type": "string" },
+                "food": { "type": "array", "items": schema("MenuItem") },
+                "promo_code": { "type": "string" },
+            },
+        },
+        "OrderStatusUpdate": {
+            "type": "object",
+            "required": ["status"],
+            "properties": { "status": schema("OrderStatus") },
+        },
+        "OrderStatus": {
+            "type": "string",
+            "enum": variants(&OrderStatus::ALL),
+        },
+        "MenuItem": {
+            "description": "Fries and drinks are plain strings, while a burger says how it is made",
+            "oneOf": [
+                { "type": "string", "enum": ["Fries", "Drink"] },
+                {
+                    "type": "object",
+                    "required": ["Burger"],
+                    "properties": { "Burger": schema("Burger") },
+                    "additionalProperties": false,
+                },
+            ],
+        },
+        "Burger": {
+            "type": "object",
+            "required": ["bun", "patty", "toppings"],
+            "properties": {
+                "bun": schema("Bun"),
+                "patty": schema("Patty"),
+                "toppings": { "type": "array", "items": schema("Topping") },
+            },
+            "additionalProperties": false,
+        },
+        "Bun": { "type": "string", "enum": variants(&Bun::ALL) },
+        "Patty": { "type": "string", "enum": variants(&Patty::ALL) },
+        "Topping": { "type": "string", "enum": variants(&Topping::ALL) },
+        "PriceBreakdown": {
+            "type": "object",
+            "required": ["items", "subtotal", "discounts", "tax"],
+            "properties": {
+                "items": {
+                    "type": "array",
+                    "items": schema("Money"),
+                    "description": "Price of each item, in the same order as the food",
+                },
+                "subtotal": schema("Money"),
+                "discounts": { "type": "array", "items": schema("Discount") },
+                "tax": schema("Money"),
+            },
+            "additionalProperties": false,
+        },
+        "Discount": {
+            "type": "object",
+            "required": ["description", "amount"],
+            "properties": {
+                "description": { "type": "string" },
+                "amount": schema("Money"),
+            },
+            "additionalProperties": false,
+        },
+        "Estimate": {
+            "type": "object",
+            "description": "When the kitchen expects the order to get there, in Unix seconds",
+            "required": ["ready_at", "delivered_at"],
+            "properties": {
+                "ready_at": { "type": "integer" },
+                "delivered_at": { "type": "integer" },
+            },
+            "additionalProperties": false,
+        },
+        "OrderEvent": {
+            "type": "object",
+            "required": ["order_id", "kind", "status", "actor", "timestamp"],
+            "properties": {
+                "order_id": { "type": "integer" },
+                "kind": {
+                    "type": "string",
+                    "enum": variants(&[
+                        OrderEventKind::Created,
+                        OrderEventKind::StatusChanged,
+                        OrderEventKind::Cancelled,
+                        OrderEventKind::Deleted,
+                        OrderEventKind::Purged,
+                    ]),
+                },
+                "status": {
+                    "allOf": [schema("OrderStatus")],
+                    "nullable": true,
+                },
+                "actor": {
+                    "type": "string",
+                    "description": "Who made the change, such as `staff` or `customer:Amit`",
+                },
+                "timestamp": { "type": "integer" },
+            },
+            "additionalProperties": false,
+        },
+        "PriceList": {
+            "type": "object",
+            "required": [
+                "version", "fries", "drink", "buns", "patties", "toppings", "combos",
+                "promo_codes", "tax_basis_points",
+            ],
+            "properties": {
+                "version": { "type": "integer" },
+                "fries": schema("Money"),
+                "drink": schema("Money"),
+                "buns": { "type": "object", "additionalProperties": schema("Money") },
+                "patties": { "type": "object", "additionalProperties": schema("Money") },
+                "toppings": { "type": "object", "additionalProperties": schema("Money") },
+                "combos": { "type": "array", "items": schema("Combo") },
+                "promo_codes": { "type": "object", "additionalProperties": schema("PromoDiscount") },
+                "tax_basis_points": {
+                    "type": "integer",
+                    "description": "Sales tax in hundredths of a percent, so 825 is 8.25%",
+                },
+            },
+            "additionalProperties": false,
+        },
+        "Combo": {
+            "type": "object",
+            "required": ["name", "items", "discount"],
+            "properties": {
+                "name": { "type": "string" },
+                "items": {
+                    "type": "array",
+                    "items": {
+                        "type": "string",
+                        "enum": variants(&[ItemKind::Burger, ItemKind::Fries, ItemKind::Drink]),
+                    },
+                },
+                "discount": schema("Money"),
+            },
+            "additionalProperties": false,
+        },
+        "PromoDiscount": {
+            "oneOf": [
+                {
+                    "type": "object",
+                    "required": ["Percent"],
+                    "properties": { "Percent": { "type": "integer" } },
+                    "additionalProperties": false,
+                },
+                {
+                    "type": "object",
+                    "required": ["Amount"],
+                    "properties": { "Amount": schema("Money") },
+                    "additionalProperties": false,
+                },
+            ],
+        },
+        "Error": {
+            "type": "string",
+            "description": "What went wrong, followed by `Request ID: <id>` on its own line if \
+                the request had an X-Request-Id",
+        },
+    })
+}
+
+fn error_responses() -> Value {
+    let responses: Map<String, Value> = ERROR_RESPONSES
+        .iter()
+        .map(|(_, name, description)| {
+            let response = json!({
+                "description": description,
+                "content": { "text/plain": { "schema": schema("Error") } },
+            });
+            (name.to_string(), response)
+        })
+        .collect();
+    Value::Object(responses)
+}
+
+/// An operation with the given details and successful responses, which can also fail with any
+/// of the `errors` status codes or `ANY_REQUEST_ERRORS`
+fn operation(summary: &str, details: Value, mut responses: Value, errors: &[u16]) -> Value {
+    for (code, name, _) in ERROR_RESPONSES {
+        if errors.contains(&code) || ANY_REQUEST_ERRORS.contains(&code) {
+            responses[code.to_string()] =
+                json!({ "$ref": format!("#/components/responses/{}", name) });
+        }
+    }
+    let mut operation = details;
+    operation["summary"] = json!(summary);
+    operation["responses"] = responses;
+    operation
+}
+
+/// An operation that needs no API key
+fn public(mut operation: Value) -> Value {
+    operation["security"] = json!([]);
+    operation
+}
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// A successful response with a plain text body
+fn text(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+/// The names the variants of an enum are serialized as
+fn variants<T: Serialize>(values: &[T]) -> Vec<Value> {
+    values
+        .iter()
+        .map(|value| serde_json::to_value(value).unwrap_or_default())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::AspirinEatsError,
+        food::{Burger, Estimate, MenuItem, Order, OrderRequest},
+        http::{HttpResponse, Method},
+        menu::PriceList,
+        store::OrderEvent,
+    };
+
+    /// Find what a `$ref` points at
+    fn resolve<'a>(spec: &'a Value, reference: &str) -> &'a Value {
+        let pointer = reference.strip_prefix('#').unwrap();
+        spec.pointer(pointer)
+            .unwrap_or_else(|| panic!("{} doesn't exist", reference))
+    }
+
+    /// Whether `value` fits `schema`, as far as the parts of JSON Schema used in the spec go
+    fn matches(spec: &Value, schema: &Value, value: &Value) -> bool {
+        if let Some(reference) = schema["$ref"].as_str() {
+            return matches(spec, resolve(spec, reference), value);
+        }
+        if value.is_null() {
+            return schema["nullable"] == true;
+        }
+        if let Some(one_of) = schema["oneOf"].as_array() {
+            return one_of.iter().filter(|s| matches(spec, s, value)).count() == 1;
+        }
+        if let Some(all_of) = schema["allOf"].as_array() {
+            return all_of.iter().all(|s| matches(spec, s, value));
+        }
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                return false;
+            }
+        }
+        match schema["type"].as_str().unwrap_or_default() {
+            "string" => value.is_string(),
+            "integer" => value.is_i64(),
+            "number" => value.is_number(),
+            "array" => value
+                .as_array()
+                .is_some_and(|items| items.iter().all(|v| matches(spec, &schema["items"], v))),
+            "object" => {
+                let Some(object) = value.as_object() else {
+                    return false;
+                };
+                let required = schema["required"].as_array().cloned().unwrap_or_default();
+                required
+                    .iter()
+                    .all(|name| object.contains_key(name.as_str().unwrap()))
+                    && object.iter().all(|(name, v)| {
+                        match (&schema["properties"][name], &schema["additionalProperties"]) {
+                            (Value::Null, Value::Bool(false)) => false,
+                            (Value::Null, Value::Null) => true,
+                            (Value::Null, additional) => matches(spec, additional, v),
+                            (property, _) => matches(spec, property, v),
+                        }
+                    })
+            }
+            other => panic!("unknown type {:?}", other),
+        }
+    }
+
+    fn check<T: Serialize>(spec: &Value, name: &str, value: &T) {
+        let value = serde_json::to_value(value).unwrap();
+        assert!(
+            matches(spec, &schema(name), &value),
+            "{} doesn't match {}",
+            value,
+            name
+        );
+    }
+
+    #[test]
+    fn test_schemas_match_serialized_values() {
+        let spec = spec();
+        let burger = MenuItem::Burger(Burger::new(Bun::Sesame, Patty::Beef, vec![Topping::Bacon]));
+        let request = OrderRequest {
+            customer: "Amit".to_string(),
+            food: vec![burger, MenuItem::Fries, MenuItem::Fries, MenuItem::Drink],
+            promo_code: None,
+        };
+        check(&spec, "OrderRequest", &request);
+
+        let mut order = Order::try_from(request).unwrap();
+        order.id = Some(1);
+        check(&spec, "Order", &order);
+        order.estimate = Some(Estimate {
+            ready_at: 1,
+            delivered_at: 2,
+        });
+        check(&spec, "Order", &order);
+
+        check(&spec, "PriceList", &PriceList::default());
+        for status in [Some(OrderStatus::Pending), None] {
+            let event = OrderEvent {
+                order_id: 1,
+                kind: OrderEventKind::Created,
+                status,
+                actor: "staff".to_string(),
+                timestamp: 1,
+            };
+            check(&spec, "OrderEvent", &event);
+        }
+
+        // a burger missing its patty doesn't fit
+        let value = json!({ "Burger": { "bun": "Plain", "toppings": [] } });
+        assert!(!matches(&spec, &schema("MenuItem"), &value));
+    }
+
+    #[test]
+    fn test_every_reference_exists() {
+        fn references<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+            match value {
+                Value::Object(object) => {
+                    if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                        found.push(reference);
+                    }
+                    object.values().for_each(|v| references(v, found));
+                }
+                Value::Array(items) => items.iter().for_each(|v| references(v, found)),
+                _ => {}
+            }
+        }
+
+        let spec = spec();
+        let mut found = Vec::new();
+        references(&spec, &mut found);
+        assert!(found.len() > 20);
+        for reference in found {
+            resolve(&spec, reference);
+        }
+    }
+
+    /// Where an error comes in the list of variants. Adding a variant fails to compile here
+    /// until it is numbered, and then `test_error_responses` until it is in `every_error`
+    fn variant_index(error: &AspirinEatsError) -> usize {
+        match error {
+            AspirinEatsError::ParseError(_) => 0,
+            AspirinEatsError::Database(_) => 1,
+            AspirinEatsError::CorruptOrder(_) => 2,
+            AspirinEatsError::Io(_) => 3,
+            AspirinEatsError::InvalidRequest => 4,
+            AspirinEatsError::MalformedRequestLine => 5,
+            AspirinEatsError::UnsupportedMethod(_) => 6,
+            AspirinEatsError::UnsupportedVersion(_) => 7,
+            AspirinEatsError::MalformedHeader => 8,
+            AspirinEatsError::InvalidEncoding => 9,
+            AspirinEatsError::InvalidContentLength => 10,
+            AspirinEatsError::UnsupportedTransferEncoding(_) => 11,
+            AspirinEatsError::InvalidChunkedBody => 12,
+            AspirinEatsError::IncompleteRequest => 13,
+            AspirinEatsError::HeadersTooLarge => 14,
+            AspirinEatsError::PayloadTooLarge => 15,
+            AspirinEatsError::RequestTimeout => 16,
+            AspirinEatsError::TooManyRequests { .. } => 17,
+            AspirinEatsError::InvalidResponse => 18,
+            AspirinEatsError::ServerBusy => 19,
+            AspirinEatsError::NoHealthyUpstream => 20,
+            AspirinEatsError::InvalidCertificate(_) => 21,
+            AspirinEatsError::Unauthorized => 22,
+            AspirinEatsError::Forbidden => 23,
+            AspirinEatsError::InvalidTransition { .. } => 24,
+            AspirinEatsError::InvalidHeader(_) => 25,
+            AspirinEatsError::PreconditionFailed => 26,
+            AspirinEatsError::PreconditionRequired => 27,
+            AspirinEatsError::IdempotencyKeyReused => 28,
+            AspirinEatsError::InvalidQuery(_) => 29,
+            AspirinEatsError::InvalidMenu(_) => 30,
+            AspirinEatsError::UnknownPromoCode(_) => 31,
+            AspirinEatsError::NotFound => 32,
+            AspirinEatsError::MethodNotAllowed(_) => 33,
+            AspirinEatsError::DuplicateApiKey => 34,
+        }
+    }
+
+    /// One error of each variant
+    fn every_error() -> Vec<AspirinEatsError> {
+        let text = || "x".to_string();
+        vec![
+            serde_json::from_str::<Value>("").unwrap_err().into(),
+            AspirinEatsError::Database(rusqlite::Error::QueryReturnedNoRows),
+            AspirinEatsError::CorruptOrder(1),
+            std::io::Error::other("x").into(),
+            AspirinEatsError::InvalidRequest,
+            AspirinEatsError::MalformedRequestLine,
+            AspirinEatsError::UnsupportedMethod(text()),
+            AspirinEatsError::UnsupportedVersion(text()),
+            AspirinEatsError::MalformedHeader,
+            AspirinEatsError::InvalidEncoding,
+            AspirinEatsError::InvalidContentLength,
+            AspirinEatsError::UnsupportedTransferEncoding(text()),
+            AspirinEatsError::InvalidChunkedBody,
+            AspirinEatsError::IncompleteRequest,
+            AspirinEatsError::HeadersTooLarge,
+            AspirinEatsError::PayloadTooLarge,
+            AspirinEatsError::RequestTimeout,
+            AspirinEatsError::TooManyRequests { retry_after: 1 },
+            AspirinEatsError::InvalidResponse,
+            AspirinEatsError::ServerBusy,
+            AspirinEatsError::NoHealthyUpstream,
+            AspirinEatsError::InvalidCertificate(text()),
+            AspirinEatsError::Unauthorized,
+            AspirinEatsError::Forbidden,
+            AspirinEatsError::InvalidTransition {
+                from: OrderStatus::Completed,
+                to: OrderStatus::Pending,
+            },
+            AspirinEatsError::InvalidHeader(text()),
+            AspirinEatsError::PreconditionFailed,
+            AspirinEatsError::PreconditionRequired,
+            AspirinEatsError::IdempotencyKeyReused,
+            AspirinEatsError::InvalidQuery(text()),
+            AspirinEatsError::InvalidMenu(text()),
+            AspirinEatsError::UnknownPromoCode(text()),
+            AspirinEatsError::NotFound,
+            AspirinEatsError::MethodNotAllowed(vec![Method::Get]),
+            AspirinEatsError::DuplicateApiKey,
+        ]
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let errors = every_error();
+        let indexes: Vec<usize> = errors.iter().map(variant_index).collect();
+        assert_eq!(indexes, (0..=34).collect::<Vec<_>>());
+
+        // every status an error can be sent as is described, and nothing else is
+        let mut codes: Vec<u16> = errors
+            .into_iter()
+            .map(|error| HttpResponse::from(error).status_code())
+            .collect();
+        codes.sort();
+        codes.dedup();
+        let described: Vec<u16> = ERROR_RESPONSES.iter().map(|(code, _, _)| *code).collect();
+        assert_eq!(codes, described);
+        assert!(ANY_REQUEST_ERRORS.iter().all(|code| codes.contains(code)));
+
+        let spec = spec();
+        let responses = &spec["paths"]["/orders/{id}"]["patch"]["responses"];
+        assert_eq!(
+            responses["428"]["$ref"],
+            "#/components/responses/PreconditionRequired"
+        );
+        assert!(responses["422"].is_null());
+        assert!(responses["500"].is_object());
+        assert!(responses["503"].is_object());
+    }
+}
@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AspirinEatsError, food::MenuItem, menu::PriceList, money::Money};
+
+/// The kind of a menu item, ignoring how a burger is made. Used to describe combos
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ItemKind {
+    Burger,
+    Fries,
+    Drink,
+}
+
+impl From<&MenuItem> for ItemKind {
+    fn from(item: &MenuItem) -> Self {
+        match item {
+            MenuItem::Burger(_) => ItemKind::Burger,
+            MenuItem::Fries => ItemKind::Fries,
+            MenuItem::Drink => ItemKind::Drink,
+        }
+    }
+}
+
+/// A combo deal: ordering all of `items` together takes `discount` off the order. Each item in an
+/// order can only count towards one combo, but a combo can be applied more than once
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Combo {
+    pub name: String,
+    pub items: Vec<ItemKind>,
+    pub discount: Money,
+}
+
+/// What a promo code takes off an order, after any combos have been applied
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PromoDiscount {
+    /// A whole percentage of the order
+    Percent(u32),
+
+    /// A fixed amount
+    Amount(Money),
+}
+
+/// A discount applied to an order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Discount {
+    /// What the discount is for, such as `Combo: Meal deal` or `Promo code: SAVE10`
+    pub description: String,
+
+    /// How much the discount takes off the order
+    pub amount: Money,
+}
+
+/// Itemised breakdown of how the total of an order was calculated
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PriceBreakdown {
+    /// Price of each item, in the same order as the order's food
+    pub items: Vec<Money>,
+
+    /// Sum of the item prices
+    pub subtotal: Money,
+
+    /// Combos and promo codes that were applied, in the order they were applied
+    pub discounts: Vec<Discount>,
+
+    /// Sales tax on the subtotal after discounts
+    pub tax: Money,
+}
+
+impl PriceBreakdown {
+    /// The amount the customer pays
+    pub fn total(&self) -> Money {
+        remaining(&self.discounts, self.subtotal) + self.tax
+    }
+}
+
+/// Price some food with a price list. Combos are applied first, in the order the price list lists
+/// them, then the promo code, then tax. Discounts never take the order below zero. Fails with
+/// `UnknownPromoCode` if the promo code isn't on the price list
+pub fn price_order(
+    food: &[MenuItem],
+    promo_code: Option<&str>,
+    prices: &PriceList,
+) -> Result<PriceBreakdown, AspirinEatsError> {
+    let items: Vec<Money> = food.iter().map(|item| prices.price(item)).collect();
+    let subtotal = items.iter().copied().sum();
+    let mut discounts = Vec::new();
+
+    let mut used = vec![false; food.len()];
+    for combo in prices.combos() {
+        while let Some(matched) = match_combo(combo, food, &used) {
+            for index in matched {
+                used[index] = true;
+            }
+            apply(
+                &mut discounts,
+                subtotal,
+                format!("Combo: {}", combo.name),
+                combo.discount,
+            );
+        }
+    }
+
+    if let Some(code) = promo_code {
+        let promo = prices
+            .promo_code(code)
+            .ok_or_else(|| AspirinEatsError::UnknownPromoCode(code.to_string()))?;
+        let amount = match promo {
+            PromoDiscount::Percent(percent) => {
+                remaining(&discounts, subtotal).fraction(*percent as i64, 100)
+            }
+            PromoDiscount::Amount(amount) => *amount,
+        };
+        apply(
+            &mut discounts,
+            subtotal,
+            format!("Promo code: {}", code),
+            amount,
+        );
+    }
+
+    let tax = remaining(&discounts, subtotal).fraction(prices.tax_basis_points() as i64, 10_000);
+    Ok(PriceBreakdown {
+        items,
+        subtotal,
+        discounts,
+        tax,
+    })
+}
+
+/// How much of the subtotal is left to pay after the discounts
+fn remaining(discounts: &[Discount], subtotal: Money) -> Money {
+    subtotal - discounts.iter().map(|discount| discount.amount).sum()
+}
+
+/// Add a discount, capped so the order doesn't go below zero
+fn apply(discounts: &mut Vec<Discount>, subtotal: Money, description: String, amount: Money) {
+    let amount = amount.min(remaining(discounts, subtotal));
+    discounts.push(Discount {
+        description,
+        amount,
+    });
+}
+
+/// Find unused items that make up a combo, returning their indices
+fn match_combo(combo: &Combo, food: &[MenuItem], used: &[bool]) -> Option<Vec<usize>> {
+    let mut matched: Vec<usize> = Vec::new();
+    for kind in &combo.items {
+        let index = food.iter().enumerate().position(|(index, item)| {
+            !used[index] && !matched.contains(&index) && ItemKind::from(item) == *kind
+        })?;
+        matched.push(index);
+    }
+    Some(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::food::{Bun, Burger, Patty, Topping};
+
+    fn burger() -> MenuItem {
+        MenuItem::Burger(Burger::new(Bun::Sesame, Patty::Beef, vec![Topping::Cheese]))
+    }
+
+    fn prices_with(rules: serde_json::Value) -> PriceList {
+        let mut menu = serde_json::to_value(PriceList::default()).unwrap();
+        for (key, value) in rules.as_object().unwrap() {
+            menu[key] = value.clone();
+        }
+        PriceList::from_str(&menu.to_string()).unwrap()
+    }
+
+    fn meal_deal() -> PriceList {
+        prices_with(serde_json::json!({
+            "combos": [
+                {"name": "Meal deal", "items": ["Burger", "Fries", "Drink"], "discount": 3},
+                {"name": "Snack", "items": ["Fries", "Drink"], "discount": 1}
+            ],
+            "promo_codes": {"SAVE10": {"Percent": 10}, "FREEBIE": {"Amount": 1000}},
+            "tax_basis_points": 825
+        }))
+    }
+
+    #[test]
+    fn test_default_menu_has_no_rules() {
+        let breakdown =
+            price_order(&[burger(), MenuItem::Fries], None, &PriceList::default()).unwrap();
+        assert_eq!(
+            breakdown,
+            PriceBreakdown {
+                items: vec![Money::from_dollars(10), Money::from_dollars(5)],
+                subtotal: Money::from_dollars(15),
+                discounts: vec![],
+                tax: Money::ZERO,
+            }
+        );
+        assert_eq!(breakdown.total(), Money::from_dollars(15));
+    }
+
+    #[test]
+    fn test_combos_promo_codes_and_tax() {
+        let food = [
+            MenuItem::Drink,
+            burger(),
+            MenuItem::Fries,
+            MenuItem::Fries,
+            MenuItem::Drink,
+            MenuItem::Drink,
+        ];
+        let breakdown = price_order(&food, Some("SAVE10"), &meal_deal()).unwrap();
+        assert_eq!(breakdown.subtotal, Money::from_dollars(29));
+        let discounts: Vec<_> = breakdown
+            .discounts
+            .iter()
+            .map(|discount| (discount.description.as_str(), discount.amount))
+            .collect();
+        assert_eq!(
+            discounts,
+            vec![
+                ("Combo: Meal deal", Money::from_dollars(3)),
+                ("Combo: Snack", Money::from_dollars(1)),
+                ("Promo code: SAVE10", Money::from_cents(250)),
+            ]
+        );
+        // 8.25% of 22.50, rounded to the nearest cent
+        assert_eq!(breakdown.tax, Money::from_cents(186));
+        assert_eq!(breakdown.total(), Money::from_cents(2436));
+    }
+
+    #[test]
+    fn test_discounts_never_go_below_zero() {
+        let breakdown = price_order(&[MenuItem::Drink], Some("FREEBIE"), &meal_deal()).unwrap();
+        assert_eq!(breakdown.discounts[0].amount, Money::from_dollars(3));
+        assert_eq!(breakdown.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_unknown_promo_code() {
+        assert!(matches!(
+            price_order(&[MenuItem::Drink], Some("save10"), &meal_deal()),
+            Err(AspirinEatsError::UnknownPromoCode(code)) if code == "save10"
+        ));
+    }
+}
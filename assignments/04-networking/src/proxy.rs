@@ -0,0 +1,709 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    net::{SocketAddr, TcpStream},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api::IDEMPOTENCY_KEY_HEADER,
+    error::AspirinEatsError,
+    http::{BodyStream, Headers, HttpRequest, HttpResponse, Method, StreamChunk},
+};
+
+use cache::{cache_control, Lookup, ResponseCache};
+use rate_limit::RateLimiter;
+
+mod cache;
+mod rate_limit;
+
+/// Headers that only apply to a single connection, so are never forwarded
+const HOP_BY_HOP_HEADERS: [&str; 7] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// How the proxy chooses which upstream server handles each request
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Balancing {
+    /// Take turns between the upstream servers
+    #[default]
+    RoundRobin,
+
+    /// Send each request to the upstream server with the fewest requests in flight
+    LeastConnections,
+}
+
+/// Error when a string isn't the name of a balancing strategy
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("Unknown balancing strategy, expected round-robin or least-connections")]
+pub struct ParseBalancingError;
+
+impl FromStr for Balancing {
+    type Err = ParseBalancingError;
+
+    /// Parse `round-robin` or `least-connections`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Balancing::RoundRobin),
+            "least-connections" => Ok(Balancing::LeastConnections),
+            _ => Err(ParseBalancingError),
+        }
+    }
+}
+
+/// Settings controlling how the proxy talks to its upstream servers
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// How to choose an upstream server for each request
+    pub balancing: Balancing,
+
+    /// How long to wait when opening a connection to an upstream server
+    pub connect_timeout: Duration,
+
+    /// How long to wait for an upstream server to respond
+    pub response_timeout: Duration,
+
+    /// Maximum number of idle connections kept open to each upstream server
+    pub max_idle_connections: usize,
+
+    /// How often to check whether each upstream server is healthy
+    pub health_check_interval: Duration,
+
+    /// Maximum total size of cached responses, in bytes. Zero turns caching off
+    pub cache_size: usize,
+
+    /// How long to cache responses for the menu that don't set a `max-age` themselves. Other
+    /// responses are only cached if they set one
+    pub cache_ttl: Duration,
+
+    /// Requests per second each client IP may keep up. Zero turns rate limiting off
+    pub rate_limit: f64,
+
+    /// Number of requests a client IP may send at once before being rate limited
+    pub rate_limit_burst: u32,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            balancing: Balancing::default(),
+            connect_timeout: Duration::from_secs(2),
+            response_timeout: Duration::from_secs(30),
+            max_idle_connections: 8,
+            health_check_interval: Duration::from_secs(5),
+            cache_size: 1 << 20,
+            cache_ttl: Duration::from_secs(1),
+            rate_limit: 10.0,
+            rate_limit_burst: 20,
+        }
+    }
+}
+
+/// A reverse proxy that balances requests across several upstream servers, reusing connections
+/// to them and skipping any that fail their health checks. Responses to GET and HEAD requests are
+/// cached when their Cache-Control allows it, until they expire or a request that changes the
+/// same collection passes through. A response to a request with credentials is only reused for
+/// the same credentials, and is revalidated with the upstream server first unless it says it can
+/// be shared. Each client IP is rate limited before anything is forwarded
+pub struct Proxy {
+    upstreams: Vec<Upstream>,
+    config: ProxyConfig,
+    cache: Option<ResponseCache>,
+    rate_limiter: Option<RateLimiter>,
+
+    /// Where the next search for an upstream server starts, so they take turns
+    next: AtomicUsize,
+}
+
+/// An upstream server, along with its idle connections
+struct Upstream {
+    addr: SocketAddr,
+    healthy: AtomicBool,
+
+    /// Number of requests currently being handled by this upstream server
+    active: AtomicUsize,
+    idle: Mutex<Vec<UpstreamConnection>>,
+}
+
+/// An open connection to an upstream server
+struct UpstreamConnection {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+/// Why sending a request to an upstream server failed
+enum SendError {
+    /// Couldn't connect at all, so the request can safely be sent somewhere else
+    Connect(io::Error),
+
+    /// The request may have reached the upstream server, but no valid response came back
+    Exchange(AspirinEatsError),
+}
+
+impl Proxy {
+    /// Create a proxy for the given upstream servers, which are assumed to be healthy until a
+    /// health check says otherwise
+    pub fn new(upstreams: Vec<SocketAddr>, config: ProxyConfig) -> Self {
+        Proxy {
+            upstreams: upstreams
+                .into_iter()
+                .map(|addr| Upstream {
+                    addr,
+                    healthy: AtomicBool::new(true),
+                    active: AtomicUsize::new(0),
+                    idle: Mutex::new(Vec::new()),
+                })
+                .collect(),
+            cache: (config.cache_size > 0)
+                .then(|| ResponseCache::new(config.cache_size, config.cache_ttl)),
+            rate_limiter: (config.rate_limit > 0.0)
+                .then(|| RateLimiter::new(config.rate_limit, config.rate_limit_burst)),
+            config,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Forward a request upstream, turning any failure into an error response
+    pub fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        self.forward(request)
+            .unwrap_or_else(|error| HttpResponse::from_error(error, request.request_id()))
+    }
+
+    /// Answer a request from the cache, or forward it upstream. Cached responses carry an `Age`
+    /// header, and every response to a GET or HEAD says in `X-Cache` whether it was a `HIT`,
+    /// `MISS` or `REVALIDATED` with the upstream server. Fails with `TooManyRequests` if the
+    /// client is over its rate limit
+    pub fn forward(&self, request: &HttpRequest) -> Result<HttpResponse, AspirinEatsError> {
+        if let (Some(limiter), Some(client)) = (&self.rate_limiter, request.remote_addr) {
+            limiter
+                .acquire(client.ip(), Instant::now())
+                .map_err(|wait| AspirinEatsError::TooManyRequests {
+                    retry_after: wait.as_secs_f64().ceil().max(1.0) as u64,
+                })?;
+        }
+
+        let Some(cache) = &self.cache else {
+            return self.forward_upstream(request);
+        };
+        if !matches!(request.method, Method::Get | Method::Head) {
+            let response = self.forward_upstream(request);
+            if request.method != Method::Options {
+                cache.invalidate(collection(&request.path));
+            }
+            return response;
+        }
+
+        let directives = cache_control(&request.headers);
+        if directives.iter().any(|directive| directive == "no-store") {
+            return self.forward_upstream(request);
+        }
+        let revalidate = directives
+            .iter()
+            .any(|directive| directive == "no-cache" || directive == "max-age=0");
+
+        match cache.lookup(request, revalidate, Instant::now()) {
+            Lookup::Fresh(response, age) => Ok(from_cache(request, response, age, "HIT")),
+            Lookup::Stale { etag } => {
+                let mut conditional = request.clone();
+                conditional.headers.insert("If-None-Match", &etag);
+                let response = self.forward_upstream(&conditional)?;
+                if response.status_code() == 304 {
+                    if let Some(cached) = cache.revalidated(request, &response, Instant::now()) {
+                        return Ok(from_cache(request, cached, 0, "REVALIDATED"));
+                    }
+                    // evicted in the meantime, so the client needs the full response after all
+                    let response = self.forward_upstream(request)?;
+                    cache.store(request, &response, Instant::now());
+                    return Ok(response.with_header("X-Cache", "MISS"));
+                }
+                cache.store(request, &response, Instant::now());
+                Ok(response.with_header("X-Cache", "MISS"))
+            }
+            Lookup::Miss => {
+                let response = self.forward_upstream(request)?;
+                cache.store(request, &response, Instant::now());
+                Ok(response.with_header("X-Cache", "MISS"))
+            }
+        }
+    }
+
+    /// Forward a request to one of the healthy upstream servers and return its response. If an
+    /// upstream server can't be connected to, it is ejected and the next one is tried
+    fn forward_upstream(&self, request: &HttpRequest) -> Result<HttpResponse, AspirinEatsError> {
+        let mut request = without_hop_by_hop_headers(request);
+        add_forwarding_headers(&mut request);
+        let mut tried = Vec::new();
+        loop {
+            let index = self
+                .choose(&tried)
+                .ok_or(AspirinEatsError::NoHealthyUpstream)?;
+            tried.push(index);
+
+            let upstream = &self.upstreams[index];
+            match self.send(upstream, &request) {
+                Ok(mut response) => {
+                    remove_hop_by_hop_headers(response.headers_mut());
+                    return Ok(response);
+                }
+                Err(SendError::Connect(e)) => {
+                    eprintln!("Failed to connect to upstream {}: {}", upstream.addr, e);
+                    self.set_healthy(upstream, false);
+                }
+                Err(SendError::Exchange(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Addresses of the upstream servers that are currently receiving requests
+    pub fn healthy_upstreams(&self) -> Vec<SocketAddr> {
+        self.upstreams
+            .iter()
+            .filter(|upstream| upstream.healthy.load(Ordering::Relaxed))
+            .map(|upstream| upstream.addr)
+            .collect()
+    }
+
+    /// Send `GET /` to every upstream server, ejecting the ones that don't respond with a 2xx
+    /// status and restoring the ones that do
+    pub fn check_health(&self) {
+        for upstream in &self.upstreams {
+            let healthy = self.probe(upstream.addr);
+            self.set_healthy(upstream, healthy);
+        }
+    }
+
+    /// Run health checks in the background every `health_check_interval`, until the proxy is
+    /// dropped
+    pub fn spawn_health_checks(self: &Arc<Self>) -> thread::JoinHandle<()> {
+        let proxy = Arc::downgrade(self);
+        let interval = self.config.health_check_interval;
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match proxy.upgrade() {
+                Some(proxy) => proxy.check_health(),
+                None => return,
+            }
+        })
+    }
+
+    /// Pick a healthy upstream server that hasn't already been tried, returning its index
+    fn choose(&self, tried: &[usize]) -> Option<usize> {
+        let count = self.upstreams.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut candidates = (0..count)
+            .map(|offset| (start + offset) % count)
+            .filter(|index| {
+                !tried.contains(index) && self.upstreams[*index].healthy.load(Ordering::Relaxed)
+            });
+        match self.config.balancing {
+            Balancing::RoundRobin => candidates.next(),
+            // ties go to whichever comes first in round-robin order
+            Balancing::LeastConnections => {
+                candidates.min_by_key(|index| self.upstreams[*index].active.load(Ordering::Relaxed))
+            }
+        }
+    }
+
+    /// Send a request to an upstream server, preferring an idle connection over a new one. A
+    /// request that isn't safe to send twice always gets a new connection, since an idle one may
+    /// turn out to be closed after the upstream server has already acted on the request
+    fn send(&self, upstream: &Upstream, request: &HttpRequest) -> Result<HttpResponse, SendError> {
+        upstream.active.fetch_add(1, Ordering::Relaxed);
+        let _active = ActiveGuard(&upstream.active);
+
+        // the upstream server may have closed an idle connection, in which case nothing comes
+        // back and the request can be retried on another connection
+        while let Some(mut connection) = is_replayable(request)
+            .then(|| upstream.take_idle())
+            .flatten()
+        {
+            if let Some(result) = exchange(&mut connection, request) {
+                return self.finish(upstream, connection, request, result);
+            }
+        }
+
+        let mut connection = self.connect(upstream.addr).map_err(SendError::Connect)?;
+        let result =
+            exchange(&mut connection, request).unwrap_or(Err(AspirinEatsError::InvalidResponse));
+        self.finish(upstream, connection, request, result)
+    }
+
+    /// Return a connection to the pool if it can carry another request. If the response is an
+    /// event stream, the connection is handed to the response to pass the stream on
+    fn finish(
+        &self,
+        upstream: &Upstream,
+        connection: UpstreamConnection,
+        request: &HttpRequest,
+        result: Result<HttpResponse, AspirinEatsError>,
+    ) -> Result<HttpResponse, SendError> {
+        let response = result.map_err(SendError::Exchange)?;
+        if is_streamed(&response, &request.method) {
+            return Ok(response.with_stream(upstream_stream(connection)));
+        }
+        if is_reusable(&response) {
+            let mut idle = upstream.idle.lock().unwrap();
+            if idle.len() < self.config.max_idle_connections {
+                idle.push(connection);
+            }
+        }
+        Ok(response)
+    }
+
+    fn connect(&self, addr: SocketAddr) -> io::Result<UpstreamConnection> {
+        let stream = TcpStream::connect_timeout(&addr, self.config.connect_timeout)?;
+        stream.set_read_timeout(Some(self.config.response_timeout))?;
+        stream.set_nodelay(true)?;
+        Ok(UpstreamConnection {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+        })
+    }
+
+    /// Whether an upstream server answers `GET /` with a 2xx status
+    fn probe(&self, addr: SocketAddr) -> bool {
+        let Ok(mut connection) = self.connect(addr) else {
+            return false;
+        };
+        let request = HttpRequest::from_str(&format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            addr
+        ))
+        .expect("health check request should be valid");
+        matches!(
+            exchange(&mut connection, &request),
+            Some(Ok(response)) if (200..300).contains(&response.status_code())
+        )
+    }
+
+    fn set_healthy(&self, upstream: &Upstream, healthy: bool) {
+        if upstream.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            let state = if healthy { "healthy" } else { "unhealthy" };
+            eprintln!("Upstream {} is {}", upstream.addr, state);
+        }
+        if !healthy {
+            upstream.idle.lock().unwrap().clear();
+        }
+    }
+}
+
+impl Upstream {
+    fn take_idle(&self) -> Option<UpstreamConnection> {
+        self.idle.lock().unwrap().pop()
+    }
+}
+
+/// Decrements an upstream server's count of active requests when dropped
+struct ActiveGuard<'a>(&'a AtomicUsize);
+
+impl Drop for ActiveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Send a request over a connection and read the response. Returns None if the connection was
+/// closed before any of the response arrived
+fn exchange(
+    connection: &mut UpstreamConnection,
+    request: &HttpRequest,
+) -> Option<Result<HttpResponse, AspirinEatsError>> {
+    if request.write_to(&mut connection.stream).is_err() {
+        return None;
+    }
+    match connection.reader.fill_buf() {
+        Ok([]) => return None,
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::ConnectionReset => return None,
+        Err(_) => return Some(Err(AspirinEatsError::InvalidResponse)),
+    }
+    let response = HttpResponse::read_head(&mut connection.reader).and_then(|response| {
+        if is_streamed(&response, &request.method) {
+            Ok(response)
+        } else {
+            response.read_body(&mut connection.reader, &request.method)
+        }
+    });
+    Some(response.map_err(|_| AspirinEatsError::InvalidResponse))
+}
+
+/// Whether a request can be sent again if no response came back the first time. Idempotent
+/// methods can, as can anything with an `Idempotency-Key`, which the origin uses to avoid acting
+/// on it twice
+fn is_replayable(request: &HttpRequest) -> bool {
+    matches!(
+        request.method,
+        Method::Get | Method::Head | Method::Options | Method::Put | Method::Delete
+    ) || request.headers.contains(IDEMPOTENCY_KEY_HEADER)
+}
+
+/// Whether a response is an event stream whose body should be passed on as it arrives rather
+/// than read in full, which would never finish
+fn is_streamed(response: &HttpResponse, method: &Method) -> bool {
+    *method != Method::Head
+        && response.status_code() == 200
+        && response.is_event_stream()
+        && !response.headers().contains("Content-Length")
+        && !response.headers().contains("Transfer-Encoding")
+}
+
+/// Pass on the body of a response from an upstream connection as it arrives. The body only ends
+/// when the upstream server closes the connection, so it can't be reused. Dropping the stream
+/// closes the connection, so the upstream server stops sending once the client has gone away
+fn upstream_stream(connection: UpstreamConnection) -> BodyStream {
+    let UpstreamConnection { stream, mut reader } = connection;
+    BodyStream::new(move |timeout| {
+        let timeout = timeout.max(Duration::from_millis(1));
+        if stream.set_read_timeout(Some(timeout)).is_err() {
+            return StreamChunk::End;
+        }
+        let mut buf = [0; 4096];
+        match reader.read(&mut buf) {
+            Ok(0) => StreamChunk::End,
+            Ok(read) => StreamChunk::Data(buf[..read].to_vec()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                StreamChunk::Idle
+            }
+            Err(_) => StreamChunk::End,
+        }
+    })
+}
+
+/// Whether the connection a response came in on can be used for another request. A response
+/// without a Content-Length may have been delimited by the upstream server closing the connection
+fn is_reusable(response: &HttpResponse) -> bool {
+    let closing = response
+        .headers()
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("close"));
+    let framed = response.headers().contains("Content-Length")
+        || matches!(response.status_code(), 100..=199 | 204 | 304);
+    !closing && framed
+}
+
+/// Build the response to a request from a cached response, answering `304 Not Modified` if the
+/// client already has it
+fn from_cache(request: &HttpRequest, cached: HttpResponse, age: u64, status: &str) -> HttpResponse {
+    let etag = cached.headers().get("ETag").map(str::to_string);
+    let client_has_it = etag.as_deref().is_some_and(|etag| {
+        request
+            .headers
+            .get_all("If-None-Match")
+            .flat_map(|value| value.split(','))
+            .any(|candidate| matches!(candidate.trim(), "*") || candidate.trim() == etag)
+    });
+
+    let response = if client_has_it {
+        let mut not_modified = HttpResponse::new(304, "Not Modified", "");
+        for name in ["ETag", "Cache-Control"] {
+            if let Some(value) = cached.headers().get(name) {
+                not_modified.headers_mut().insert(name, value);
+            }
+        }
+        not_modified
+    } else {
+        cached
+    };
+    response
+        .with_header("Age", &age.to_string())
+        .with_header("X-Cache", status)
+}
+
+/// The top-level collection a path belongs to, such as `/orders` for `/orders/1`. A change to
+/// anything in a collection invalidates every cached response for it
+fn collection(path: &str) -> &str {
+    match path[1..].find('/') {
+        Some(end) => &path[..end + 1],
+        None => path,
+    }
+}
+
+/// Copy of a request without the headers that only applied to the client's connection
+fn without_hop_by_hop_headers(request: &HttpRequest) -> HttpRequest {
+    let mut request = request.clone();
+    remove_hop_by_hop_headers(&mut request.headers);
+    request
+}
+
+/// Tell the upstream server who the request came from: `X-Forwarded-For` is set to the address
+/// the request came from, `X-Forwarded-Proto` says whether the client used TLS, and the request
+/// is tagged with an `X-Request-Id` if it doesn't have one yet. Any `X-Forwarded-For` the client
+/// sent is replaced, since the proxy faces clients directly and anyone could claim to be anyone
+fn add_forwarding_headers(request: &mut HttpRequest) {
+    match request.remote_addr {
+        Some(client) => request
+            .headers
+            .insert("X-Forwarded-For", &client.ip().to_string()),
+        None => request.headers.remove("X-Forwarded-For"),
+    }
+    let proto = if request.tls { "https" } else { "http" };
+    request.headers.insert("X-Forwarded-Proto", proto);
+    request.ensure_request_id();
+}
+
+/// Remove the standard hop-by-hop headers, along with any listed in the Connection header
+fn remove_hop_by_hop_headers(headers: &mut Headers) {
+    let listed: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect();
+    for name in HOP_BY_HOP_HEADERS
+        .iter()
+        .copied()
+        .chain(listed.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(count: u16, balancing: Balancing) -> Proxy {
+        let upstreams = (1..=count)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect();
+        Proxy::new(
+            upstreams,
+            ProxyConfig {
+                balancing,
+                ..ProxyConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_round_robin_skips_unhealthy_upstreams() {
+        let proxy = proxy(3, Balancing::RoundRobin);
+        let chosen: Vec<_> = (0..4).map(|_| proxy.choose(&[]).unwrap()).collect();
+        assert_eq!(chosen, vec![0, 1, 2, 0]);
+
+        proxy.upstreams[2].healthy.store(false, Ordering::Relaxed);
+        let chosen: Vec<_> = (0..4).map(|_| proxy.choose(&[]).unwrap()).collect();
+        assert_eq!(chosen, vec![1, 0, 0, 1]);
+        assert_eq!(proxy.choose(&[0, 1]), None);
+        assert_eq!(proxy.healthy_upstreams().len(), 2);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let proxy = proxy(3, Balancing::LeastConnections);
+        proxy.upstreams[0].active.store(2, Ordering::Relaxed);
+        proxy.upstreams[1].active.store(1, Ordering::Relaxed);
+        proxy.upstreams[2].active.store(3, Ordering::Relaxed);
+        assert_eq!(proxy.choose(&[]), Some(1));
+        assert_eq!(proxy.choose(&[1]), Some(0));
+
+        // ties are broken in round-robin order
+        proxy.upstreams[0].active.store(1, Ordering::Relaxed);
+        let chosen: Vec<_> = (0..3).map(|_| proxy.choose(&[]).unwrap()).collect();
+        assert_eq!(chosen, vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn test_hop_by_hop_headers_are_removed() {
+        let request = HttpRequest::from_str(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive, X-Secret\r\n\
+            X-Secret: 1\r\nKeep-Alive: timeout=5\r\nX-Other: 2\r\n\r\n",
+        )
+        .unwrap();
+        let forwarded = without_hop_by_hop_headers(&request);
+        let names: Vec<_> = forwarded.headers.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["Host", "X-Other"]);
+    }
+
+    #[test]
+    fn test_forwarding_headers() {
+        let mut request = HttpRequest::from_str(
+            "GET / HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1\r\nX-Request-Id: abc-123\r\n\r\n",
+        )
+        .unwrap();
+        request.remote_addr = Some(SocketAddr::from(([192, 168, 1, 2], 4000)));
+        add_forwarding_headers(&mut request);
+        assert_eq!(request.headers.get("X-Forwarded-For"), Some("192.168.1.2"));
+        assert_eq!(request.headers.get("X-Forwarded-Proto"), Some("http"));
+        assert_eq!(request.request_id(), Some("abc-123"));
+
+        let mut request =
+            HttpRequest::from_str("GET / HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1\r\n\r\n").unwrap();
+        add_forwarding_headers(&mut request);
+        assert_eq!(request.headers.get("X-Forwarded-For"), None);
+        let id = request.request_id().unwrap();
+        assert!(uuid::Uuid::parse_str(id).is_ok(), "{}", id);
+    }
+
+    #[test]
+    fn test_replayable_requests() {
+        let replayable = |request: &str| is_replayable(&HttpRequest::from_str(request).unwrap());
+        assert!(replayable("GET /orders HTTP/1.1\r\n\r\n"));
+        assert!(replayable("DELETE /orders/1 HTTP/1.1\r\n\r\n"));
+        assert!(!replayable("POST /orders HTTP/1.1\r\n\r\n"));
+        assert!(!replayable("PATCH /orders/1 HTTP/1.1\r\n\r\n"));
+        assert!(replayable(
+            "POST /orders HTTP/1.1\r\nIdempotency-Key: abc\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_collection() {
+        assert_eq!(collection("/orders/1"), "/orders");
+        assert_eq!(collection("/orders"), "/orders");
+        assert_eq!(collection("/"), "/");
+    }
+
+    #[test]
+    fn test_cached_response_is_not_modified_for_matching_etag() {
+        let cached = HttpResponse::new(200, "OK", "order").with_header("ETag", "\"v2\"");
+        let request = |tags: &str| {
+            HttpRequest::from_str(&format!(
+                "GET /orders/1 HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n",
+                tags
+            ))
+            .unwrap()
+        };
+
+        let response = from_cache(&request("\"v1\", \"v2\""), cached.clone(), 3, "HIT");
+        assert_eq!(response.status_code(), 304);
+        assert_eq!(response.body(), "");
+        assert_eq!(response.headers().get("ETag"), Some("\"v2\""));
+        assert_eq!(response.headers().get("Age"), Some("3"));
+
+        let response = from_cache(&request("\"v1\""), cached, 3, "HIT");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), "order");
+        assert_eq!(response.headers().get("X-Cache"), Some("HIT"));
+    }
+
+    #[test]
+    fn test_parse_balancing() {
+        assert_eq!(
+            Balancing::from_str("round-robin"),
+            Ok(Balancing::RoundRobin)
+        );
+        assert_eq!(
+            Balancing::from_str("least-connections"),
+            Ok(Balancing::LeastConnections)
+        );
+        assert_eq!(Balancing::from_str("random"), Err(ParseBalancingError));
+    }
+}
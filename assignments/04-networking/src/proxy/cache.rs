@@ -0,0 +1,585 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auth::{self, API_KEY_HEADER},
+    http::{Headers, HttpRequest, HttpResponse, Method},
+};
+
+/// The only path, along with everything beneath it, whose responses are cached for the default
+/// TTL when they don't say how long they may be cached for. Anything else, such as a list of
+/// orders, is only cached if its Cache-Control allows it
+const DEFAULT_CACHED_PATH: &str = "/menu";
+
+/// The method and the request target, including any query string, that responses are cached for,
+/// along with a hash of the credentials the request was sent with, if any. A response to a request
+/// with credentials is only ever given back to requests with the same ones
+type Resource = (Method, String, Option<String>);
+
+/// Identifies a cached response: the resource, and the values the request had for each header
+/// the response's `Vary` names
+type CacheKey = (Resource, Vec<Option<String>>);
+
+/// An in-memory cache of upstream responses, evicting the least recently used responses once the
+/// cached bodies and headers grow past a size limit
+pub(super) struct ResponseCache {
+    max_size: usize,
+    default_ttl: Duration,
+    inner: Mutex<CacheInner>,
+}
+
+struct CacheInner {
+    entries: HashMap<CacheKey, CacheEntry>,
+
+    /// Keys ordered by when they were last used, oldest first
+    recency: BTreeMap<u64, CacheKey>,
+    next_use: u64,
+    size: usize,
+
+    /// The request headers that the cached responses for each resource vary on, along with how
+    /// many responses are cached for it
+    vary: HashMap<Resource, (Vec<String>, usize)>,
+}
+
+struct CacheEntry {
+    response: HttpResponse,
+    stored_at: Instant,
+    ttl: Duration,
+    size: usize,
+    last_used: u64,
+}
+
+/// The result of looking a request up in the cache
+pub(super) enum Lookup {
+    /// A response that can be served as-is, along with how many seconds old it is
+    Fresh(HttpResponse, u64),
+
+    /// An expired response that can still be used if the upstream server confirms its ETag
+    Stale {
+        etag: String,
+    },
+
+    Miss,
+}
+
+impl ResponseCache {
+    pub(super) fn new(max_size: usize, default_ttl: Duration) -> Self {
+        ResponseCache {
+            max_size,
+            default_ttl,
+            inner: Mutex::new(CacheInner {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_use: 0,
+                size: 0,
+                vary: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Look up the response cached for a request. If `revalidate` is set, a fresh response is
+    /// treated as stale, so the upstream server is asked whether it is still current. So are
+    /// responses to requests with credentials, unless they are marked `public` or have an
+    /// `s-maxage`, so the upstream server still gets to check the credentials (RFC 9111 §3.5)
+    pub(super) fn lookup(&self, request: &HttpRequest, revalidate: bool, now: Instant) -> Lookup {
+        let mut inner = self.inner.lock().unwrap();
+        let key = inner.key(request);
+        let Some(entry) = inner.entries.get(&key) else {
+            return Lookup::Miss;
+        };
+
+        let age = now.saturating_duration_since(entry.stored_at);
+        let shareable = key.0 .2.is_none() || is_shareable(entry.response.headers());
+        let lookup = if age < entry.ttl && !revalidate && shareable {
+            Lookup::Fresh(entry.response.clone(), age.as_secs())
+        } else if let Some(etag) = entry.response.headers().get("ETag") {
+            Lookup::Stale {
+                etag: etag.to_string(),
+            }
+        } else {
+            inner.remove(&key);
+            return Lookup::Miss;
+        };
+        inner.touch(&key);
+        lookup
+    }
+
+    /// Cache an upstream response to a request, if its status and Cache-Control allow it. Streamed
+    /// responses are never cached, since their body isn't known yet, and neither are responses
+    /// that vary on everything
+    pub(super) fn store(&self, request: &HttpRequest, response: &HttpResponse, now: Instant) {
+        if response.status_code() != 200 || response.stream().is_some() {
+            return;
+        }
+        let default_ttl = is_default_cached(&request.path).then_some(self.default_ttl);
+        let Some(ttl) = cache_ttl(response.headers(), default_ttl) else {
+            return;
+        };
+        let Some(vary) = vary(response.headers()) else {
+            return;
+        };
+        let size = response_size(response);
+        if size > self.max_size {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let resource = resource(request);
+        if inner
+            .vary
+            .get(&resource)
+            .is_some_and(|(names, _)| *names != vary)
+        {
+            // responses cached under the old header names could never be found again
+            inner.remove_resource(&resource);
+        }
+        let key = (resource.clone(), vary_values(&vary, request));
+        inner.remove(&key);
+        inner.vary.entry(resource).or_insert((vary, 0)).1 += 1;
+        while inner.size + size > self.max_size {
+            inner.evict_oldest();
+        }
+        inner.size += size;
+        inner.entries.insert(
+            key.clone(),
+            CacheEntry {
+                response: response.clone(),
+                stored_at: now,
+                ttl,
+                size,
+                last_used: 0,
+            },
+        );
+        inner.touch(&key);
+    }
+
+    /// Handle a `304 Not Modified` from the upstream server by restarting the cached response's
+    /// TTL, returning the cached response
+    pub(super) fn revalidated(
+        &self,
+        request: &HttpRequest,
+        not_modified: &HttpResponse,
+        now: Instant,
+    ) -> Option<HttpResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        let key = inner.key(request);
+        let entry = inner.entries.get_mut(&key)?;
+        entry.stored_at = now;
+        let default_ttl = is_default_cached(&request.path).then_some(self.default_ttl);
+        if let Some(ttl) = cache_ttl(not_modified.headers(), default_ttl) {
+            entry.ttl = ttl;
+        }
+        Some(entry.response.clone())
+    }
+
+    /// Remove every cached response for `path`, or for anything beneath it, such as when an
+    /// order is created or changed
+    pub(super) fn invalidate(&self, path: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let stale: Vec<CacheKey> = inner
+            .entries
+            .keys()
+            .filter(|((_, target, _), _)| {
+                let target_path = target.split('?').next().unwrap_or_default();
+                target_path == path
+                    || target_path
+                        .strip_prefix(path)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .cloned()
+            .collect();
+        for key in stale {
+            inner.remove(&key);
+        }
+    }
+}
+
+impl CacheInner {
+    /// The key a request's response is cached under, given the headers cached responses to it
+    /// vary on
+    fn key(&self, request: &HttpRequest) -> CacheKey {
+        let resource = resource(request);
+        let values = match self.vary.get(&resource) {
+            Some((names, _)) => vary_values(names, request),
+            None => Vec::new(),
+        };
+        (resource, values)
+    }
+
+    /// Mark an entry as the most recently used
+    fn touch(&mut self, key: &CacheKey) {
+        let use_count = self.next_use;
+        self.next_use += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.last_used);
+            entry.last_used = use_count;
+            self.recency.insert(use_count, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.size -= entry.size;
+            self.forget_variant(&key.0);
+        }
+    }
+
+    /// Remove every response cached for a resource, whatever its headers
+    fn remove_resource(&mut self, resource: &Resource) {
+        let keys: Vec<CacheKey> = self
+            .entries
+            .keys()
+            .filter(|(cached, _)| cached == resource)
+            .cloned()
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.size -= entry.size;
+                self.forget_variant(&key.0);
+            }
+        }
+    }
+
+    /// Note that one fewer response is cached for a resource, forgetting what it varies on once
+    /// none are left
+    fn forget_variant(&mut self, resource: &Resource) {
+        if let Some((_, count)) = self.vary.get_mut(resource) {
+            *count -= 1;
+            if *count == 0 {
+                self.vary.remove(resource);
+            }
+        }
+    }
+}
+
+/// The resource a request's response is cached under
+fn resource(request: &HttpRequest) -> Resource {
+    let credentials: Vec<&str> = ["Authorization", API_KEY_HEADER]
+        .iter()
+        .flat_map(|name| request.headers.get_all(name))
+        .collect();
+    let credentials =
+        (!credentials.is_empty()).then(|| auth::sha256_hex(credentials.join("\n").as_bytes()));
+    (request.method, request.target.clone(), credentials)
+}
+
+/// Whether a response to a request with credentials may be reused without asking the upstream
+/// server, because it says it can be shared
+fn is_shareable(headers: &Headers) -> bool {
+    cache_control(headers).iter().any(|directive| {
+        directive == "public"
+            || directive
+                .split_once('=')
+                .is_some_and(|(name, _)| name == "s-maxage")
+    })
+}
+
+/// Whether responses for a path are cached for the default TTL when they don't set their own
+fn is_default_cached(path: &str) -> bool {
+    path.strip_prefix(DEFAULT_CACHED_PATH)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The lowercased names of the request headers a response varies on, or None if it varies on
+/// something other than headers, so it can't be reused at all
+fn vary(headers: &Headers) -> Option<Vec<String>> {
+    let mut names: Vec<String> = headers
+        .get_all("Vary")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    (!names.iter().any(|name| name == "*")).then_some(names)
+}
+
+/// The values a request has for each of the named headers
+fn vary_values(names: &[String], request: &HttpRequest) -> Vec<Option<String>> {
+    names
+        .iter()
+        .map(|name| {
+            let values: Vec<&str> = request.headers.get_all(name).collect();
+            (!values.is_empty()).then(|| values.join(", "))
+        })
+        .collect()
+}
+
+/// How long a response may be cached for, or None if it must not be stored. `s-maxage` wins
+/// over `max-age`, and a response with neither is cached for `default_ttl`, if there is one
+fn cache_ttl(headers: &Headers, default_ttl: Option<Duration>) -> Option<Duration> {
+    let mut max_age = None;
+    let mut shared_max_age = None;
+    for directive in cache_control(headers) {
+        let (name, value) = directive.split_once('=').unwrap_or((&directive, ""));
+        let seconds = || value.trim_matches('"').parse::<u64>().ok();
+        match name {
+            "no-store" | "private" => return None,
+            "no-cache" => max_age = Some(0),
+            "max-age" => max_age = max_age.or(seconds()),
+            "s-maxage" => shared_max_age = seconds(),
+            _ => {}
+        }
+    }
+    let ttl = shared_max_age
+        .or(max_age)
+        .map(Duration::from_secs)
+        .or(default_ttl)?;
+    (!ttl.is_zero() || headers.contains("ETag")).then_some(ttl)
+}
+
+/// The lowercased directives of the Cache-Control headers
+pub(super) fn cache_control(headers: &Headers) -> Vec<String> {
+    headers
+        .get_all("Cache-Control")
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .filter(|directive| !directive.is_empty())
+        .collect()
+}
+
+/// Roughly how much memory a cached response takes up
+fn response_size(response: &HttpResponse) -> usize {
+    let headers: usize = response
+        .headers()
+        .iter()
+        .map(|(name, value)| name.len() + value.len())
+        .sum();
+    headers + response.body().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    fn response(body: &str) -> HttpResponse {
+        HttpResponse::new(200, "OK", body)
+    }
+
+    fn request(method: &str, target: &str) -> HttpRequest {
+        HttpRequest::from_str(&format!("{} {} HTTP/1.1\r\n\r\n", method, target)).unwrap()
+    }
+
+    fn get_request(target: &str) -> HttpRequest {
+        request("GET", target)
+    }
+
+    fn get(cache: &ResponseCache, target: &str, now: Instant) -> Option<String> {
+        match cache.lookup(&get_request(target), false, now) {
+            Lookup::Fresh(response, _) => Some(response.body().to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_responses_expire_after_their_ttl() {
+        let cache = ResponseCache::new(1000, TTL);
+        let start = Instant::now();
+        cache.store(&get_request("/menu/1"), &response("one"), start);
+        cache.store(
+            &get_request("/orders/2"),
+            &response("two").with_header("Cache-Control", "public, max-age=2"),
+            start,
+        );
+
+        let later = start + Duration::from_secs(5);
+        assert_eq!(get(&cache, "/menu/1", later), Some("one".to_string()));
+        assert_eq!(get(&cache, "/orders/2", start), Some("two".to_string()));
+        assert_eq!(get(&cache, "/orders/2", later), None);
+        assert_eq!(get(&cache, "/menu/1", start + TTL), None);
+        assert!(matches!(
+            cache.lookup(&request("HEAD", "/menu/1"), false, start),
+            Lookup::Miss
+        ));
+    }
+
+    #[test]
+    fn test_uncacheable_responses_are_not_stored() {
+        let cache = ResponseCache::new(1000, TTL);
+        let now = Instant::now();
+        for (target, response) in [
+            ("/a", response("a").with_header("Cache-Control", "no-store")),
+            ("/b", response("b").with_header("Cache-Control", "private")),
+            (
+                "/c",
+                response("c").with_header("Cache-Control", "max-age=0"),
+            ),
+            ("/d", HttpResponse::new(404, "Not Found", "d")),
+            // only the menu is cached without saying how long for
+            ("/orders", response("e")),
+            ("/menus", response("f")),
+            (
+                "/menu",
+                response("g")
+                    .with_header("Cache-Control", "max-age=60")
+                    .with_header("Vary", "*"),
+            ),
+        ] {
+            cache.store(&get_request(target), &response, now);
+            assert_eq!(get(&cache, target, now), None, "{}", target);
+        }
+    }
+
+    #[test]
+    fn test_stale_responses_with_etags_are_revalidated() {
+        let cache = ResponseCache::new(1000, TTL);
+        let start = Instant::now();
+        let tagged = response("one")
+            .with_header("ETag", "\"v1\"")
+            .with_header("Cache-Control", "no-cache");
+        cache.store(&get_request("/orders/1"), &tagged, start);
+
+        assert!(matches!(
+            cache.lookup(&get_request("/orders/1"), false, start),
+            Lookup::Stale { etag } if etag == "\"v1\""
+        ));
+        let not_modified =
+            HttpResponse::new(304, "Not Modified", "").with_header("Cache-Control", "max-age=5");
+        let revalidated = cache.revalidated(&get_request("/orders/1"), &not_modified, start);
+        assert_eq!(revalidated, Some(tagged));
+        assert_eq!(get(&cache, "/orders/1", start), Some("one".to_string()));
+
+        // clients can ask for a fresh response to be checked too
+        assert!(matches!(
+            cache.lookup(&get_request("/orders/1"), true, start),
+            Lookup::Stale { .. }
+        ));
+    }
+
+    #[test]
+    fn test_responses_with_credentials_are_kept_apart() {
+        let cache = ResponseCache::new(1000, TTL);
+        let now = Instant::now();
+        let with_token = |target: &str, token: &str| {
+            let mut request = get_request(target);
+            request
+                .headers
+                .insert("Authorization", &format!("Bearer {}", token));
+            request
+        };
+        let tagged = |body: &str| {
+            response(body)
+                .with_header("ETag", "\"1\"")
+                .with_header("Cache-Control", "max-age=60")
+        };
+        cache.store(&with_token("/orders/1", "amit"), &tagged("amit"), now);
+
+        // the upstream server still checks the credentials before the response is reused
+        assert!(matches!(
+            cache.lookup(&with_token("/orders/1", "amit"), false, now),
+            Lookup::Stale { etag } if etag == "\"1\""
+        ));
+        for other in [with_token("/orders/1", "bea"), get_request("/orders/1")] {
+            assert!(matches!(cache.lookup(&other, false, now), Lookup::Miss));
+        }
+
+        // unless the response says it can be shared
+        let shared = tagged("menu").with_header("Cache-Control", "public");
+        cache.store(&with_token("/menu", "amit"), &shared, now);
+        assert!(matches!(
+            cache.lookup(&with_token("/menu", "amit"), false, now),
+            Lookup::Fresh(..)
+        ));
+        cache.invalidate("/orders");
+        assert!(matches!(
+            cache.lookup(&with_token("/orders/1", "amit"), false, now),
+            Lookup::Miss
+        ));
+    }
+
+    #[test]
+    fn test_least_recently_used_responses_are_evicted() {
+        let body = "x".repeat(100);
+        let cache = ResponseCache::new(250, TTL);
+        let now = Instant::now();
+        cache.store(&get_request("/menu/1"), &response(&body), now);
+        cache.store(&get_request("/menu/2"), &response(&body), now);
+        get(&cache, "/menu/1", now);
+        cache.store(&get_request("/menu/3"), &response(&body), now);
+
+        assert!(get(&cache, "/menu/1", now).is_some());
+        assert!(get(&cache, "/menu/2", now).is_none());
+        assert!(get(&cache, "/menu/3", now).is_some());
+
+        // too big to ever fit
+        cache.store(&get_request("/big"), &response(&"x".repeat(300)), now);
+        assert!(get(&cache, "/big", now).is_none());
+        assert!(get(&cache, "/menu/3", now).is_some());
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = ResponseCache::new(1000, TTL);
+        let now = Instant::now();
+        for target in [
+            "/orders",
+            "/orders?status=Pending",
+            "/orders/1",
+            "/ordersx",
+            "/menu",
+        ] {
+            let response = response(target).with_header("Cache-Control", "max-age=60");
+            cache.store(&get_request(target), &response, now);
+        }
+        cache.invalidate("/orders");
+        let cached: Vec<_> = [
+            "/orders",
+            "/orders?status=Pending",
+            "/orders/1",
+            "/ordersx",
+            "/menu",
+        ]
+        .into_iter()
+        .filter(|target| get(&cache, target, now).is_some())
+        .collect();
+        assert_eq!(cached, vec!["/ordersx", "/menu"]);
+    }
+
+    #[test]
+    fn test_responses_are_cached_for_each_value_of_the_headers_they_vary_on() {
+        let cache = ResponseCache::new(1000, TTL);
+        let now = Instant::now();
+        let with_language = |language: Option<&str>| {
+            let mut request = get_request("/menu");
+            if let Some(language) = language {
+                request.headers.insert("Accept-Language", language);
+            }
+            request
+        };
+        let body = |request: &HttpRequest| match cache.lookup(request, false, now) {
+            Lookup::Fresh(response, _) => Some(response.body().to_string()),
+            _ => None,
+        };
+        for language in ["en", "fr"] {
+            let response = response(language).with_header("Vary", "Accept-Language");
+            cache.store(&with_language(Some(language)), &response, now);
+        }
+
+        assert_eq!(body(&with_language(Some("en"))), Some("en".to_string()));
+        assert_eq!(body(&with_language(Some("fr"))), Some("fr".to_string()));
+        assert_eq!(body(&with_language(Some("de"))), None);
+        assert_eq!(body(&with_language(None)), None);
+
+        // a response that varies on something else replaces the others
+        let response = response("any").with_header("Vary", "Accept-Encoding");
+        cache.store(&with_language(Some("en")), &response, now);
+        assert_eq!(body(&with_language(Some("fr"))), Some("any".to_string()));
+        cache.invalidate("/menu");
+        assert_eq!(body(&with_language(Some("fr"))), None);
+        assert!(cache.inner.lock().unwrap().vary.is_empty());
+    }
+}
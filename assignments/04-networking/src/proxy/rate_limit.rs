@@ -0,0 +1,177 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Most clients tracked at once. Past this, the client that sent a request longest ago is
+/// forgotten to make room for a new one
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Limits how often each client IP can send requests, using a token bucket per client. Each
+/// request takes a token, and tokens are added back at a steady rate up to a maximum, so clients
+/// can send short bursts but not keep up a higher rate. IPv6 clients are limited per /64, since
+/// a single host is usually given a whole /64 and could otherwise pick a new address each time
+pub(super) struct RateLimiter {
+    /// Tokens added back to each bucket per second
+    rate: f64,
+
+    /// Most tokens a bucket can hold, which is the largest burst a client can send
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+
+    /// Clients ordered by when they last sent a request, oldest first
+    recency: BTreeMap<u64, IpAddr>,
+    next_use: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    last_used: u64,
+}
+
+impl RateLimiter {
+    pub(super) fn new(rate: f64, burst: u32) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_use: 0,
+            }),
+        }
+    }
+
+    /// Take a token for a request from `client`. If its bucket is empty, returns how long until
+    /// the next token is added
+    pub(super) fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let client = client_key(client);
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets {
+            clients,
+            recency,
+            next_use,
+        } = &mut *buckets;
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&client) {
+            if let Some((_, oldest)) = recency.pop_first() {
+                clients.remove(&oldest);
+            }
+        }
+
+        let bucket = clients.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+            last_used: 0,
+        });
+        recency.remove(&bucket.last_used);
+        bucket.last_used = *next_use;
+        recency.insert(*next_use, client);
+        *next_use += 1;
+
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// How many tokens a bucket holds at `now`
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// The address a client's requests are counted against: its IPv4 address, or the /64 its IPv6
+/// address is in
+fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(v6.to_bits() & !u128::from(u64::MAX))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bursts_are_limited_then_refilled() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let client = IpAddr::from([10, 0, 0, 1]);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(client, start), Ok(()));
+        }
+        assert_eq!(
+            limiter.acquire(client, start),
+            Err(Duration::from_millis(500))
+        );
+
+        // other clients have their own buckets
+        assert_eq!(limiter.acquire(IpAddr::from([10, 0, 0, 2]), start), Ok(()));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.acquire(client, later), Ok(()));
+        assert!(limiter.acquire(client, later).is_err());
+
+        // a long wait only refills up to the burst size
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(client, much_later), Ok(()));
+        }
+        assert!(limiter.acquire(client, much_later).is_err());
+    }
+
+    #[test]
+    fn test_ipv6_clients_are_limited_per_64() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let now = Instant::now();
+        let client = |address: &str| address.parse::<IpAddr>().unwrap();
+
+        assert_eq!(limiter.acquire(client("2001:db8:1:2::1"), now), Ok(()));
+        assert!(limiter
+            .acquire(client("2001:db8:1:2:ffff::9"), now)
+            .is_err());
+        assert_eq!(limiter.acquire(client("2001:db8:1:3::1"), now), Ok(()));
+
+        // a mapped IPv4 address is the same client as the IPv4 address itself
+        assert_eq!(limiter.acquire(client("::ffff:10.0.0.1"), now), Ok(()));
+        assert!(limiter.acquire(client("10.0.0.1"), now).is_err());
+    }
+
+    #[test]
+    fn test_least_recently_seen_clients_are_forgotten() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let now = Instant::now();
+        let first = IpAddr::from([10, 0, 0, 1]);
+        assert_eq!(limiter.acquire(first, now), Ok(()));
+        for i in 1..MAX_TRACKED_CLIENTS as u32 {
+            let client = IpAddr::from((10 << 24 | 1 << 16 | i).to_be_bytes());
+            assert_eq!(limiter.acquire(client, now), Ok(()));
+        }
+        let buckets = || limiter.buckets.lock().unwrap().clients.len();
+        assert_eq!(buckets(), MAX_TRACKED_CLIENTS);
+
+        // seen again, so the next oldest client is forgotten instead
+        assert!(limiter.acquire(first, now).is_err());
+        assert_eq!(limiter.acquire(IpAddr::from([10, 2, 0, 0]), now), Ok(()));
+        assert_eq!(buckets(), MAX_TRACKED_CLIENTS);
+        assert!(limiter.acquire(first, now).is_err());
+        assert_eq!(limiter.acquire(IpAddr::from([10, 1, 0, 1]), now), Ok(()));
+    }
+}
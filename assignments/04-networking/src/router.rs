@@ -0,0 +1,262 @@
+use std::str::FromStr;
+
+use crate::{
+    error::AspirinEatsError,
+    http::{HttpRequest, HttpResponse, Method},
+};
+
+/// A function that handles a request to a route, given some shared state (such as the database)
+/// and the values captured from the path
+pub type Handler<S> =
+    Box<dyn Fn(&S, &HttpRequest, &Params) -> Result<HttpResponse, AspirinEatsError> + Send + Sync>;
+
+/// Routes requests to handlers based on their method and path
+///
+/// Patterns are made up of `/`-separated segments, where a segment is either matched literally
+/// or is a `{name}` capture. A capture can be restricted to integers with `{name:int}`, so a
+/// path like `/orders/abc` does not match `/orders/{id:int}` at all
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+}
+
+struct Route<S> {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Handler<S>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Capture { name: String, int_only: bool },
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    /// Register a handler for requests with the given method and path pattern
+    ///
+    /// Panics if the pattern contains an unknown capture type, since that is a programming error
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&S, &HttpRequest, &Params) -> Result<HttpResponse, AspirinEatsError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Handle a request, converting any error into an error response that quotes the request id
+    pub fn handle(&self, state: &S, request: &HttpRequest) -> HttpResponse {
+        self.dispatch(state, request)
+            .unwrap_or_else(|error| HttpResponse::from_error(error, request.request_id()))
+    }
+
+    /// Find the handler for a request and call it. If no pattern matches the path this fails
+    /// with `NotFound`, and if a pattern matches but not for this method it fails with
+    /// `MethodNotAllowed` listing the methods that are allowed
+    pub fn dispatch(
+        &self,
+        state: &S,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let segments: Vec<&str> = split_path(&request.path).collect();
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = match_pattern(&route.pattern, &segments) else {
+                continue;
+            };
+            if route.method == request.method {
+                return (route.handler)(state, request, &params);
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            Err(AspirinEatsError::NotFound)
+        } else {
+            Err(AspirinEatsError::MethodNotAllowed(allowed))
+        }
+    }
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+/// Values captured from the `{name}` segments of a matched route
+#[derive(Debug, Default, PartialEq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Get the raw value captured for `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get the value captured for `name` converted to `T`. A missing or unparseable value
+    /// means the path doesn't name a real resource, so this fails with `NotFound`
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, AspirinEatsError> {
+        self.get(name)
+            .and_then(|value| value.parse().ok())
+            .ok_or(AspirinEatsError::NotFound)
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    split_path(pattern)
+        .map(|segment| {
+            match segment
+                .strip_prefix('{')
+                .and_then(|capture| capture.strip_suffix('}'))
+            {
+                Some(capture) => {
+                    let (name, kind) = capture.split_once(':').unwrap_or((capture, ""));
+                    let int_only = match kind {
+                        "" => false,
+                        "int" => true,
+                        other => panic!("Unknown capture type `{}` in route {}", other, pattern),
+                    };
+                    Segment::Capture {
+                        name: name.to_string(),
+                        int_only,
+                    }
+                }
+                None => Segment::Literal(segment.to_string()),
+            }
+        })
+        .collect()
+}
+
+fn match_pattern(pattern: &[Segment], segments: &[&str]) -> Option<Params> {
+    if pattern.len() != segments.len() {
+        return None;
+    }
+
+    let mut params = Params::default();
+    for (expected, segment) in pattern.iter().zip(segments) {
+        match expected {
+            Segment::Literal(literal) if literal == segment => {}
+            Segment::Capture { name, int_only } if !int_only || segment.parse::<i64>().is_ok() => {
+                params.values.push((name.clone(), segment.to_string()));
+            }
+            _ => return None,
+        }
+    }
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> HttpRequest {
+        HttpRequest::from_str(&format!("{} {} HTTP/1.1\r\n\r\n", method, path)).unwrap()
+    }
+
+    fn echo(
+        _: &(),
+        request: &HttpRequest,
+        params: &Params,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let id = params.get("id").unwrap_or("-");
+        Ok(HttpResponse::new(
+            200,
+            "OK",
+            &format!("{} {}", request.method, id),
+        ))
+    }
+
+    fn test_router() -> Router<()> {
+        Router::new()
+            .route(Method::Get, "/", echo)
+            .route(Method::Get, "/orders", echo)
+            .route(Method::Post, "/orders", echo)
+            .route(Method::Get, "/orders/{id:int}", echo)
+            .route(Method::Delete, "/orders/{id:int}", echo)
+            .route(Method::Get, "/customers/{name}", |_, _, params| {
+                Ok(HttpResponse::new(200, "OK", params.get("name").unwrap()))
+            })
+    }
+
+    #[test]
+    fn test_matches_routes() {
+        let router = test_router();
+        let body = |method, path| {
+            router
+                .handle(&(), &request(method, path))
+                .body()
+                .to_string()
+        };
+
+        assert_eq!(body("GET", "/"), "GET -");
+        assert_eq!(body("GET", "/orders"), "GET -");
+        assert_eq!(body("GET", "/orders/"), "GET -");
+        assert_eq!(body("POST", "/orders"), "POST -");
+        assert_eq!(body("GET", "/orders/42"), "GET 42");
+        assert_eq!(body("DELETE", "/orders/7"), "DELETE 7");
+        assert_eq!(body("GET", "/customers/Amit"), "Amit");
+    }
+
+    #[test]
+    fn test_not_found() {
+        let router = test_router();
+        for path in ["/menu", "/orders/abc", "/orders/1/items", "/customers"] {
+            assert!(matches!(
+                router.dispatch(&(), &request("GET", path)),
+                Err(AspirinEatsError::NotFound)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let router = test_router();
+        assert!(matches!(
+            router.dispatch(&(), &request("PATCH", "/orders")),
+            Err(AspirinEatsError::MethodNotAllowed(allowed)) if allowed == vec![Method::Get, Method::Post]
+        ));
+
+        let response = router.handle(&(), &request("POST", "/orders/1"));
+        assert_eq!(response.status_code(), 405);
+        assert_eq!(response.headers().get("Allow"), Some("GET, DELETE"));
+    }
+
+    #[test]
+    fn test_params_parse() {
+        let params = match_pattern(&parse_pattern("/orders/{id}"), &["orders", "12"]).unwrap();
+        assert_eq!(params.parse::<i64>("id").unwrap(), 12);
+        assert!(matches!(
+            params.parse::<i64>("missing"),
+            Err(AspirinEatsError::NotFound)
+        ));
+
+        let params = match_pattern(&parse_pattern("/orders/{id}"), &["orders", "x"]).unwrap();
+        assert!(matches!(
+            params.parse::<i64>("id"),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,866 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, TrySendError},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    error::AspirinEatsError,
+    http::{BodyStream, HttpRequest, HttpResponse, RequestLimits, StreamChunk, REQUEST_ID_HEADER},
+    tls::{TlsCertificates, TlsStream},
+};
+
+/// Settings controlling how long client connections are kept open and how much a client may send
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How long to wait for the next request on a connection before closing it
+    pub idle_timeout: Duration,
+
+    /// How long a client has to send the whole of a request once it has started, so a client
+    /// trickling in a byte at a time can't hold a connection open forever
+    pub request_timeout: Duration,
+
+    /// Maximum number of requests served on a single connection before it is closed
+    pub max_requests_per_connection: usize,
+
+    /// Most connections served at once by a server that gives each its own thread. Any more are
+    /// answered with `503 Service Unavailable` and closed
+    pub max_connections: usize,
+
+    /// Most connections left waiting for a free worker by a server with a fixed pool of threads.
+    /// Any more are answered with `503 Service Unavailable` and closed
+    pub max_queued_connections: usize,
+
+    /// Most streamed responses, such as order events, sent at once to a single client, which is
+    /// told to wait with `429 Too Many Requests` if it asks for more. Clients are told apart by
+    /// their API key, or by their IP address if they don't send one
+    pub max_streams_per_client: usize,
+
+    /// Most streamed responses sent at once to all clients together. Any more are answered with
+    /// `503 Service Unavailable`
+    pub max_streams: usize,
+
+    /// Largest request that will be read
+    pub limits: RequestLimits,
+
+    /// Whether to write a line of JSON to stdout for every response, with its status, size and
+    /// how long it took
+    pub access_log: bool,
+
+    /// Stops the server when triggered
+    pub shutdown: Shutdown,
+
+    /// The streamed responses being sent, shared by every clone of the config
+    pub streams: OpenStreams,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            idle_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            max_requests_per_connection: 100,
+            max_connections: 1024,
+            max_queued_connections: 64,
+            max_streams_per_client: 4,
+            max_streams: 256,
+            limits: RequestLimits::default(),
+            access_log: false,
+            shutdown: Shutdown::new(),
+            streams: OpenStreams::default(),
+        }
+    }
+}
+
+/// Accept connections until the server is shut down, serving each one with `handler` until it
+/// is closed
+pub fn serve<F>(listener: &TcpListener, config: &ServerConfig, mut handler: F)
+where
+    F: FnMut(&HttpRequest) -> HttpResponse,
+{
+    accept_until_shutdown(listener, &config.shutdown, |stream| {
+        if let Err(e) = handle_connection(stream, config, &mut handler) {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+    config.streams.wait_until_closed(config.request_timeout);
+}
+
+/// Accept connections until the server is shut down, serving them on a fixed pool of `workers`
+/// threads. Connections wait in a queue while every worker is busy, and any more than
+/// `max_queued_connections` are turned away. While connections are waiting, a worker closes its
+/// connection rather than wait for the client to send another request. Once shut down, this
+/// returns after every connection that was already accepted has been served
+pub fn serve_pool<F>(listener: &TcpListener, config: &ServerConfig, workers: usize, handler: F)
+where
+    F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(config.max_queued_connections);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let queued = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<JoinHandle<()>> = (0..workers.max(1))
+        .map(|_| {
+            let (config, handler, receiver, queued) = (
+                config.clone(),
+                Arc::clone(&handler),
+                Arc::clone(&receiver),
+                Arc::clone(&queued),
+            );
+            thread::spawn(move || loop {
+                let stream = receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+                let Ok(stream) = stream else {
+                    return;
+                };
+                queued.fetch_sub(1, Ordering::SeqCst);
+                // a panicking handler loses its connection, but not the worker
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    serve_connection(stream, &config, Some(&queued), &mut |request| {
+                        handler(request)
+                    })
+                }));
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => eprintln!("Connection error: {}", e),
+                    Err(_) => eprintln!("Connection error: handler panicked"),
+                }
+            })
+        })
+        .collect();
+
+    accept_until_shutdown(listener, &config.shutdown, |stream| {
+        queued.fetch_add(1, Ordering::SeqCst);
+        if let Err(TrySendError::Full(stream) | TrySendError::Disconnected(stream)) =
+            sender.try_send(stream)
+        {
+            queued.fetch_sub(1, Ordering::SeqCst);
+            reject_busy(stream);
+        }
+    });
+    drop(sender);
+    for worker in workers {
+        let _ = worker.join();
+    }
+    config.streams.wait_until_closed(config.request_timeout);
+}
+
+/// Accept connections until the server is shut down, serving each one on its own thread so a
+/// slow client doesn't hold up the others. Connections beyond `max_connections` are turned away.
+/// Once shut down, this returns after every open connection has been served
+pub fn serve_concurrently<F>(listener: &TcpListener, config: &ServerConfig, handler: F)
+where
+    F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+{
+    spawn_connections(listener, config, handler, Ok, reject_busy);
+}
+
+/// Like [`serve_concurrently`], but decrypting every connection with the current TLS certificate
+pub fn serve_tls_concurrently<F>(
+    listener: &TcpListener,
+    config: &ServerConfig,
+    certificates: Arc<TlsCertificates>,
+    handler: F,
+) where
+    F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+{
+    // a plain text response would mean nothing to a client expecting a TLS handshake
+    spawn_connections(
+        listener,
+        config,
+        handler,
+        move |stream| certificates.accept(stream),
+        drop,
+    );
+}
+
+/// Accept connections until the server is shut down, setting each one up with `accept` and
+/// serving it on its own thread. Once `max_connections` are open, new connections are passed to
+/// `reject` instead
+fn spawn_connections<F, A, S, R>(
+    listener: &TcpListener,
+    config: &ServerConfig,
+    handler: F,
+    accept: A,
+    reject: R,
+) where
+    F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    A: Fn(TcpStream) -> Result<S, AspirinEatsError> + Send + Sync + 'static,
+    S: Connection,
+    R: Fn(TcpStream),
+{
+    let (handler, accept) = (Arc::new(handler), Arc::new(accept));
+    let mut connections: Vec<JoinHandle<()>> = Vec::new();
+    accept_until_shutdown(listener, &config.shutdown, |stream| {
+        connections.retain(|connection| !connection.is_finished());
+        if connections.len() >= config.max_connections {
+            reject(stream);
+            return;
+        }
+        let (config, handler, accept) = (config.clone(), Arc::clone(&handler), Arc::clone(&accept));
+        connections.push(thread::spawn(move || {
+            let result = accept(stream).map_err(|e| io::Error::other(e.to_string()));
+            let result = result.and_then(|stream| {
+                handle_connection(stream, &config, &mut |request| handler(request))
+            });
+            if let Err(e) = result {
+                eprintln!("Connection error: {}", e);
+            }
+        }));
+    });
+    for connection in connections {
+        let _ = connection.join();
+    }
+    config.streams.wait_until_closed(config.request_timeout);
+}
+
+/// Tell a client the server is too busy to serve its connection, then close it. The response is
+/// small enough to fit in the socket's buffer, but the write still times out in case it doesn't
+fn reject_busy(mut stream: TcpStream) {
+    eprintln!("Connection error: too many connections, turning one away");
+    let response =
+        HttpResponse::from(AspirinEatsError::ServerBusy).with_header("Connection", "close");
+    let _ = stream
+        .set_write_timeout(Some(SHUTDOWN_POLL_INTERVAL))
+        .and_then(|_| response.write_to(&mut stream));
+}
+
+/// Pass each connection made to `listener` to `serve` until `shutdown` is triggered
+fn accept_until_shutdown<F: FnMut(TcpStream)>(
+    listener: &TcpListener,
+    shutdown: &Shutdown,
+    mut serve: F,
+) {
+    if let Ok(addr) = listener.local_addr() {
+        shutdown.wake_on_trigger(addr);
+    }
+    for stream in listener.incoming() {
+        if shutdown.is_triggered() {
+            return;
+        }
+        match stream {
+            Ok(stream) => serve(stream),
+            Err(e) => eprintln!("Connection error: {}", e),
+        }
+    }
+}
+
+/// Tells a server to stop accepting connections. Requests that are being read or handled are
+/// still answered, then every connection is closed instead of waiting for another request
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<ShutdownInner>,
+}
+
+#[derive(Debug, Default)]
+struct ShutdownInner {
+    triggered: AtomicBool,
+
+    /// Addresses of the listeners accepting connections, which are connected to on shutdown to
+    /// wake them up
+    listeners: Mutex<Vec<SocketAddr>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    pub fn trigger(&self) {
+        self.inner.triggered.store(true, Ordering::SeqCst);
+        let listeners = self
+            .inner
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for addr in listeners.iter() {
+            let _ = TcpStream::connect_timeout(addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Trigger the shutdown when the process receives SIGINT or SIGTERM. A second signal exits
+    /// straight away, without waiting for requests to finish
+    pub fn trigger_on_signals(&self) -> io::Result<()> {
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let shutdown = self.clone();
+        thread::spawn(move || {
+            let mut signals = signals.forever();
+            if signals.next().is_some() {
+                eprintln!("Shutting down once open requests are answered");
+                shutdown.trigger();
+            }
+            if signals.next().is_some() {
+                std::process::exit(1);
+            }
+        });
+        Ok(())
+    }
+
+    /// Remember a listener to connect to when the shutdown is triggered, so a thread blocked
+    /// accepting connections on it notices
+    fn wake_on_trigger(&self, mut addr: SocketAddr) {
+        if addr.ip().is_unspecified() {
+            match addr {
+                SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        self.inner
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(addr);
+    }
+}
+
+/// Counts the streamed responses being sent, for each client and in total, so a few clients
+/// can't tie up every thread with streams that never end
+#[derive(Debug, Clone, Default)]
+pub struct OpenStreams {
+    inner: Arc<OpenStreamsInner>,
+}
+
+#[derive(Debug, Default)]
+struct OpenStreamsInner {
+    counts: Mutex<StreamCounts>,
+
+    /// Notified whenever a stream ends
+    closed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct StreamCounts {
+    total: usize,
+    clients: HashMap<String, usize>,
+}
+
+impl OpenStreams {
+    /// Number of streamed responses being sent
+    pub fn len(&self) -> usize {
+        self.counts().total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Count a new stream for `client`, which stops being counted once the returned slot is
+    /// dropped. Fails with `TooManyRequests` if the client already has as many streams as it may,
+    /// or `ServerBusy` if every client together does
+    fn open(&self, client: String, config: &ServerConfig) -> Result<StreamSlot, AspirinEatsError> {
+        let mut counts = self.counts();
+        if counts.total >= config.max_streams {
+            return Err(AspirinEatsError::ServerBusy);
+        }
+        let count = counts.clients.entry(client.clone()).or_default();
+        if *count >= config.max_streams_per_client {
+            return Err(AspirinEatsError::TooManyRequests { retry_after: 1 });
+        }
+        *count += 1;
+        counts.total += 1;
+        Ok(StreamSlot {
+            streams: self.clone(),
+            client,
+        })
+    }
+
+    /// Wait up to `timeout` for every stream to end, such as once the server has shut down
+    fn wait_until_closed(&self, timeout: Duration) {
+        let counts = self.counts();
+        let _ = self
+            .inner
+            .closed
+            .wait_timeout_while(counts, timeout, |counts| counts.total > 0);
+    }
+
+    fn counts(&self) -> std::sync::MutexGuard<'_, StreamCounts> {
+        self.inner
+            .counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A stream counted by [`OpenStreams`], until this is dropped
+struct StreamSlot {
+    streams: OpenStreams,
+    client: String,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let mut counts = self.streams.counts();
+        counts.total -= 1;
+        if let Some(count) = counts.clients.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                counts.clients.remove(&self.client);
+            }
+        }
+        self.streams.inner.closed.notify_all();
+    }
+}
+
+/// Who a streamed response is counted against: the API key the request was sent with, or else
+/// the address it came from
+fn stream_client(request: &HttpRequest) -> String {
+    match (auth::request_token(request), request.remote_addr) {
+        (Some(token), _) => format!("key {}", token),
+        (None, Some(addr)) => format!("ip {}", addr.ip()),
+        (None, None) => String::new(),
+    }
+}
+
+/// A client connection the server can read requests from and write responses to
+pub trait Connection: Read + Write + Send + 'static {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Set how long a read may block before failing with a timeout
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Whether the connection is encrypted with TLS
+    fn is_tls(&self) -> bool;
+
+    /// Tell the client the server has finished with the connection
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn is_tls(&self) -> bool {
+        false
+    }
+}
+
+impl Connection for TlsStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn is_tls(&self) -> bool {
+        true
+    }
+
+    /// Send a `close_notify` alert, so the client knows the response wasn't cut short
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serve requests from a single connection until the client closes it, it sits idle for longer
+/// than the idle timeout, it reaches the maximum number of requests, or the server shuts down.
+/// Pipelined requests are answered in the order they were sent. A request that takes longer than
+/// the request timeout to arrive, or goes over the size limits, gets an error response and the
+/// connection is closed. Every response echoes the request's `X-Request-Id`, which is generated if
+/// the client didn't send one. A response with a streamed body is the last on its connection, and
+/// is sent on a thread of its own, so this returns as soon as the stream has started
+pub fn handle_connection<S, F>(stream: S, config: &ServerConfig, handler: &mut F) -> io::Result<()>
+where
+    S: Connection,
+    F: FnMut(&HttpRequest) -> HttpResponse,
+{
+    serve_connection(stream, config, None, handler)
+}
+
+/// Serve requests from a connection like [`handle_connection`]. If `queued` is given, it counts
+/// the connections waiting for this one's thread, and waiting for the next request stops as soon
+/// as there are any
+fn serve_connection<S, F>(
+    stream: S,
+    config: &ServerConfig,
+    queued: Option<&Arc<AtomicUsize>>,
+    handler: &mut F,
+) -> io::Result<()>
+where
+    S: Connection,
+    F: FnMut(&HttpRequest) -> HttpResponse,
+{
+    let mut reader = BufReader::new(DeadlineStream {
+        stream,
+        idle_timeout: config.idle_timeout,
+        deadline: None,
+        shutdown: config.shutdown.clone(),
+        queued: queued.cloned(),
+    });
+    let streaming = match serve_requests(&mut reader, config, handler) {
+        Ok(Some(streaming)) => streaming,
+        result => {
+            let _ = reader.get_mut().stream.close();
+            return result.map(|_| ());
+        }
+    };
+
+    // a streamed body can go on for as long as the client listens, so it is sent on a thread of
+    // its own, leaving this one free to serve other connections
+    let mut stream = reader.into_inner().stream;
+    let config = config.clone();
+    thread::spawn(move || {
+        if let Err(e) = streaming.send(&mut stream, &config) {
+            eprintln!("Connection error: {}", e);
+        }
+        let _ = stream.close();
+    });
+    Ok(())
+}
+
+/// Answer requests from a connection until it should be closed. If the last response has a
+/// streamed body, it is returned to be sent instead
+fn serve_requests<S, F>(
+    reader: &mut BufReader<DeadlineStream<S>>,
+    config: &ServerConfig,
+    handler: &mut F,
+) -> io::Result<Option<Streaming>>
+where
+    S: Connection,
+    F: FnMut(&HttpRequest) -> HttpResponse,
+{
+    let remote_addr = reader.get_ref().stream.peer_addr().ok();
+    let tls = reader.get_ref().stream.is_tls();
+    for served in 1..=config.max_requests_per_connection {
+        // wait for the next request, quietly closing if the client hung up or went idle
+        match reader.fill_buf() {
+            Ok([]) => return Ok(None),
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let started = Instant::now();
+        reader.get_mut().deadline = Some(started + config.request_timeout);
+        let result = HttpRequest::read_with_limits(reader, &config.limits);
+        reader.get_mut().deadline = None;
+        let mut request = match result {
+            Ok(request) => request,
+            Err(AspirinEatsError::Io(e)) if !is_timeout(&e) => return Err(e),
+            Err(e) => {
+                let error = match e {
+                    AspirinEatsError::Io(_) => AspirinEatsError::RequestTimeout,
+                    e => e,
+                };
+                // we can't tell where the next request would start, so close the connection
+                let request_id = Uuid::new_v4().to_string();
+                let response = HttpResponse::from_error(error, Some(&request_id))
+                    .with_header("Connection", "close");
+                let exchange = Exchange {
+                    request_id: &request_id,
+                    remote_addr,
+                    request: None,
+                    started,
+                };
+                return respond(reader.get_mut(), config, &exchange, response).map(|_| None);
+            }
+        };
+
+        request.remote_addr = remote_addr;
+        request.tls = tls;
+        let request_id = request.ensure_request_id();
+
+        let mut response = handler(&request);
+        // a streamed body runs until the connection closes, so nothing can follow it. It is sent
+        // once the connection has been handed to a thread of its own
+        if response.stream().is_some() {
+            match config.streams.open(stream_client(&request), config) {
+                Ok(slot) => {
+                    return Ok(Some(Streaming {
+                        request,
+                        request_id,
+                        started,
+                        response: response.with_header("Connection", "close"),
+                        _slot: slot,
+                    }));
+                }
+                Err(e) => response = HttpResponse::from_error(e, Some(&request_id)),
+            }
+        }
+
+        let keep_alive = request.keep_alive()
+            && served < config.max_requests_per_connection
+            && !config.shutdown.is_triggered();
+        let response = response.with_header(
+            "Connection",
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+        let exchange = Exchange {
+            request_id: &request_id,
+            remote_addr,
+            request: Some(&request),
+            started,
+        };
+        respond(reader.get_mut(), config, &exchange, response)?;
+        if !keep_alive {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// A response with a streamed body, to be sent once the connection is handed to a thread of its
+/// own
+struct Streaming {
+    request: HttpRequest,
+    request_id: String,
+
+    /// When the request started arriving
+    started: Instant,
+    response: HttpResponse,
+
+    /// Keeps the stream counted until it has been sent
+    _slot: StreamSlot,
+}
+
+impl Streaming {
+    fn send<W: Write>(self, writer: W, config: &ServerConfig) -> io::Result<()> {
+        let exchange = Exchange {
+            request_id: &self.request_id,
+            remote_addr: self.request.remote_addr,
+            request: Some(&self.request),
+            started: self.started,
+        };
+        respond(writer, config, &exchange, self.response)
+    }
+}
+
+/// A request being answered, or the attempt at one if it couldn't be read
+struct Exchange<'a> {
+    request_id: &'a str,
+    remote_addr: Option<SocketAddr>,
+    request: Option<&'a HttpRequest>,
+
+    /// When the request started arriving
+    started: Instant,
+}
+
+/// One line of the access log
+#[derive(Serialize)]
+struct AccessLogEntry<'a> {
+    /// When the response was sent, in milliseconds since the Unix epoch
+    timestamp_ms: u64,
+    request_id: &'a str,
+    remote_addr: Option<SocketAddr>,
+    method: Option<String>,
+    target: Option<&'a str>,
+    status: u16,
+
+    /// Size of the response body, including any of it that was streamed
+    bytes: usize,
+
+    /// Time from the request starting to arrive until the response was sent
+    latency_ms: f64,
+}
+
+impl<'a> AccessLogEntry<'a> {
+    fn new(exchange: &Exchange<'a>, response: &HttpResponse, bytes: usize) -> Self {
+        AccessLogEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            request_id: exchange.request_id,
+            remote_addr: exchange.remote_addr,
+            method: exchange.request.map(|request| request.method.to_string()),
+            target: exchange.request.map(|request| request.target.as_str()),
+            status: response.status_code(),
+            bytes,
+            latency_ms: exchange.started.elapsed().as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Send a response tagged with the request's id, then log it if access logging is on. A streamed
+/// body is sent until it ends, the client goes away or the server shuts down
+fn respond<W: Write>(
+    writer: W,
+    config: &ServerConfig,
+    exchange: &Exchange,
+    response: HttpResponse,
+) -> io::Result<()> {
+    let response = response.with_header(REQUEST_ID_HEADER, exchange.request_id);
+    let mut writer = BufWriter::new(writer);
+    response.write_to(&mut writer)?;
+    let mut bytes = response.body().len();
+    if let Some(stream) = response.stream() {
+        bytes += send_stream(&mut writer, stream, &config.shutdown)?;
+    }
+    if config.access_log {
+        if let Ok(line) = serde_json::to_string(&AccessLogEntry::new(exchange, &response, bytes)) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Write each piece of a streamed body as soon as it is produced, returning how many bytes were
+/// sent. Fails if the client has gone away
+fn send_stream<W: Write>(
+    writer: &mut W,
+    stream: &BodyStream,
+    shutdown: &Shutdown,
+) -> io::Result<usize> {
+    let mut sent = 0;
+    while !shutdown.is_triggered() {
+        match stream.next(SHUTDOWN_POLL_INTERVAL) {
+            StreamChunk::Data(data) => {
+                writer.write_all(&data)?;
+                writer.flush()?;
+                sent += data.len();
+            }
+            StreamChunk::Idle => {}
+            StreamChunk::End => break,
+        }
+    }
+    Ok(sent)
+}
+
+/// How often a connection waiting for its next request checks whether the server is shutting down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A connection that times out reads after the idle timeout, or at a fixed deadline while a
+/// request is being read. Waiting for the next request also stops once the server shuts down, or
+/// once other connections are queued up for the thread serving this one
+struct DeadlineStream<S> {
+    stream: S,
+    idle_timeout: Duration,
+    deadline: Option<Instant>,
+    shutdown: Shutdown,
+    queued: Option<Arc<AtomicUsize>>,
+}
+
+impl<S: Connection> DeadlineStream<S> {
+    /// Whether another connection needs the thread more than an idle client does
+    fn is_needed_elsewhere(&self) -> bool {
+        self.queued
+            .as_ref()
+            .is_some_and(|queued| queued.load(Ordering::SeqCst) > 0)
+    }
+
+    /// Wait up to the idle timeout for the client to send something
+    fn read_idle(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let idle_deadline = Instant::now() + self.idle_timeout;
+        loop {
+            let timeout = idle_deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream
+                .set_read_timeout(Some(timeout.min(SHUTDOWN_POLL_INTERVAL)))?;
+            match self.stream.read(buf) {
+                Err(e)
+                    if is_timeout(&e)
+                        && !self.shutdown.is_triggered()
+                        && !self.is_needed_elsewhere() =>
+                {
+                    continue
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<S: Connection> Read for DeadlineStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(deadline) = self.deadline else {
+            return self.read_idle(buf);
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Connection> Write for DeadlineStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Read timeouts are reported as WouldBlock on Unix and TimedOut on Windows
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_access_log_entry() {
+        let request = HttpRequest::from_str("GET /orders?status=Pending HTTP/1.1\r\n\r\n").unwrap();
+        let exchange = Exchange {
+            request_id: "abc",
+            remote_addr: Some(SocketAddr::from(([127, 0, 0, 1], 5000))),
+            request: Some(&request),
+            started: Instant::now(),
+        };
+        let response = HttpResponse::new(404, "Not Found", "missing");
+        let entry = serde_json::to_value(AccessLogEntry::new(&exchange, &response, 7)).unwrap();
+
+        assert_eq!(entry["request_id"], "abc");
+        assert_eq!(entry["remote_addr"], "127.0.0.1:5000");
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["target"], "/orders?status=Pending");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["bytes"], 7);
+        assert!(entry["latency_ms"].as_f64().unwrap() >= 0.0);
+        assert!(entry["timestamp_ms"].as_u64().unwrap() > 0);
+
+        // a request that couldn't be read is logged without a method or target
+        let exchange = Exchange {
+            request: None,
+            ..exchange
+        };
+        let entry = serde_json::to_value(AccessLogEntry::new(&exchange, &response, 7)).unwrap();
+        assert!(entry["method"].is_null());
+        assert!(entry["target"].is_null());
+    }
+}
@@ -0,0 +1,494 @@
+use std::{sync::mpsc::Receiver, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Role,
+    error::AspirinEatsError,
+    food::{Estimate, Order, OrderStatus},
+    menu::PriceList,
+    money::Money,
+};
+
+mod memory;
+
+pub use memory::MemoryStore;
+
+/// How long the response to a request made with an `Idempotency-Key` is kept for replaying
+pub(crate) const IDEMPOTENCY_KEY_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where the API keeps orders and everything else it needs between requests. The SQLite
+/// [`AspirinEatsDb`](crate::db::AspirinEatsDb) is used in production, and [`MemoryStore`] keeps
+/// everything in memory
+pub trait OrderStore {
+    /// Store a new order, recording that `actor` created it, and return its ID
+    fn add_order(&self, order: Order, actor: &str) -> Result<i64, AspirinEatsError>;
+
+    /// Get an order by ID. Deleted orders aren't returned
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError>;
+
+    /// Get one page of the orders matching a query, along with how many orders match in total.
+    /// Deleted orders never match
+    fn query_orders(&self, query: &OrderQuery) -> Result<OrderPage, AspirinEatsError>;
+
+    /// Get every order with an ID above `after` that has yet to be completed or cancelled, oldest
+    /// first. Deleted orders aren't returned
+    fn unfinished_orders(&self, after: i64) -> Result<Vec<Order>, AspirinEatsError>;
+
+    /// Move an order to a new status on behalf of `actor`, returning the updated order with its
+    /// version bumped. If `expected_version` is given, the order is only changed if it is still at
+    /// that version. Fails with `NotFound` if there is no such order, `PreconditionFailed` if it is
+    /// at a different version, or `InvalidTransition` if the order can't move to that status
+    fn update_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+        expected_version: Option<u32>,
+        actor: &str,
+    ) -> Result<Order, AspirinEatsError>;
+
+    /// Set or clear when an order is expected to be ready and delivered. This isn't a change the
+    /// customer made or needs to agree to, so it doesn't bump the order's version or appear in its
+    /// history, though the order's ETag does include it. Does nothing if there is no such order
+    fn set_estimate(&self, id: i64, estimate: Option<Estimate>) -> Result<(), AspirinEatsError>;
+
+    /// Delete an order on behalf of `actor`. The order is only marked as deleted, so it no longer
+    /// appears anywhere but its history is kept, and it is stored until it is purged
+    fn remove_order(&self, id: i64, actor: &str) -> Result<(), AspirinEatsError>;
+
+    /// Delete every order on behalf of `actor`, in the same way as `remove_order`. Order IDs
+    /// carry on from where they were, so an ID always refers to the same order in the history
+    fn reset_orders(&self, actor: &str) -> Result<(), AspirinEatsError>;
+
+    /// Permanently remove every deleted order on behalf of `actor`, returning how many were
+    /// purged. Their history is kept, with a final `Purged` event
+    fn purge_deleted_orders(&self, actor: &str) -> Result<usize, AspirinEatsError>;
+
+    /// Get everything that has happened to an order, oldest first. This includes orders that have
+    /// been deleted or purged
+    fn order_history(&self, id: i64) -> Result<Vec<OrderEvent>, AspirinEatsError>;
+
+    /// Receive every change to an order's status from now on, until it reaches a final status or
+    /// is deleted
+    fn subscribe(&self, id: i64) -> Receiver<Order>;
+
+    /// Make a price list available for new orders. A version that is already stored must have
+    /// exactly the same prices, since orders may already have been priced with it, and a version
+    /// older than the current one can't be installed
+    fn install_price_list(&self, prices: &PriceList) -> Result<(), AspirinEatsError>;
+
+    /// Get the price list new orders should be priced with, which is the newest one installed
+    fn current_price_list(&self) -> Result<PriceList, AspirinEatsError>;
+
+    /// Get a price list by version
+    fn get_price_list(&self, version: u32) -> Result<Option<PriceList>, AspirinEatsError>;
+
+    /// Store an API key for `token` with the given role. Only a hash of the token is kept, so it
+    /// has to be handed to its holder now and can't be recovered later. Fails with
+    /// `DuplicateApiKey` if there is already a key for `token`
+    fn add_api_key(&self, token: &str, role: &Role) -> Result<(), AspirinEatsError>;
+
+    /// Get the role of the API key for `token`, if there is one
+    fn find_api_key(&self, token: &str) -> Result<Option<Role>, AspirinEatsError>;
+
+    /// Get the response saved for a request made with an `Idempotency-Key`, if it hasn't expired.
+    /// Keys are separate for each `scope`, so one client can't replay another's responses
+    fn find_idempotent_response(
+        &self,
+        scope: &str,
+        key: &str,
+    ) -> Result<Option<SavedResponse>, AspirinEatsError>;
+
+    /// Store a new order like `add_order`, and save the response to the request that placed it
+    /// with an `Idempotency-Key` in the same step, so a retry finds either both or neither.
+    /// `respond` builds the response from the new order's ID. It runs while the store is locked
+    /// or in the middle of a transaction, so it must not call back into the store. Responses that
+    /// have expired are forgotten. Fails with `IdempotencyKeyReused`, storing nothing, if a
+    /// response is already saved under the key
+    fn add_order_idempotently(
+        &self,
+        order: Order,
+        actor: &str,
+        scope: &str,
+        key: &str,
+        respond: &dyn Fn(i64) -> Result<SavedResponse, AspirinEatsError>,
+    ) -> Result<i64, AspirinEatsError>;
+}
+
+/// Check that an order at `order`'s status and version can be moved to `status` by
+/// [`OrderStore::update_status`]
+pub(crate) fn check_status_change(
+    order: &Order,
+    status: &OrderStatus,
+    expected_version: Option<u32>,
+) -> Result<(), AspirinEatsError> {
+    if expected_version.is_some_and(|version| version != order.version) {
+        return Err(AspirinEatsError::PreconditionFailed);
+    }
+    if !order.status.can_transition_to(status) {
+        return Err(AspirinEatsError::InvalidTransition {
+            from: order.status.clone(),
+            to: status.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Check that `prices` can be installed by [`OrderStore::install_price_list`], given the price
+/// list already stored with its version and the current one. Returns false if exactly this price
+/// list is already installed, so there is nothing to do
+pub(crate) fn check_new_price_list(
+    prices: &PriceList,
+    existing: Option<PriceList>,
+    current: &PriceList,
+) -> Result<bool, AspirinEatsError> {
+    if let Some(existing) = existing {
+        return if existing == *prices {
+            Ok(false)
+        } else {
+            Err(AspirinEatsError::InvalidMenu(format!(
+                "price list version {} already exists with different prices",
+                prices.version()
+            )))
+        };
+    }
+    if prices.version() < current.version() {
+        return Err(AspirinEatsError::InvalidMenu(format!(
+            "price list version {} is older than the current version {}",
+            prices.version(),
+            current.version()
+        )));
+    }
+    Ok(true)
+}
+
+/// The response to a request made with an `Idempotency-Key`, saved so that a retry of the same
+/// request gets the same answer without doing the work again
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedResponse {
+    /// Hash of the request body, to tell whether a retry really is the same request
+    pub request_hash: String,
+    pub status_code: u16,
+    pub body: String,
+
+    /// The Location header, pointing at whatever the request created
+    pub location: Option<String>,
+
+    /// The ETag header, for the version of whatever the request created
+    pub etag: Option<String>,
+}
+
+/// What happened to an order in an [`OrderEvent`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderEventKind {
+    Created,
+    StatusChanged,
+    Cancelled,
+    Deleted,
+    Purged,
+}
+
+/// One change to an order, as recorded in its history. History is only ever added to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub order_id: i64,
+    pub kind: OrderEventKind,
+
+    /// The status the order was created with or moved to, for events that set one
+    pub status: Option<OrderStatus>,
+
+    /// Who made the change, such as `staff` or `customer:Amit`
+    pub actor: String,
+
+    /// When the change was made, in seconds since the Unix epoch
+    pub timestamp: i64,
+}
+
+/// Column to sort orders by when querying
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OrderSort {
+    #[default]
+    Id,
+    Total,
+}
+
+/// Direction to sort orders in when querying
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortDirection {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Filters, ordering and pagination for listing orders. The default query returns every order
+/// sorted by ID
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderQuery {
+    /// Only include orders placed by this customer
+    pub customer: Option<String>,
+
+    /// Only include orders with this status
+    pub status: Option<OrderStatus>,
+
+    /// Only include orders with a total of at least this much
+    pub min_total: Option<Money>,
+
+    /// Only include orders with a total of at most this much
+    pub max_total: Option<Money>,
+
+    /// Column to sort by. Ties are broken by ID
+    pub sort: OrderSort,
+
+    /// Direction to sort in
+    pub direction: SortDirection,
+
+    /// Maximum number of orders to return, or None for no limit
+    pub limit: Option<usize>,
+
+    /// Number of matching orders to skip before the first one returned
+    pub offset: usize,
+}
+
+/// A page of orders returned from [`OrderStore::query_orders`]
+#[derive(Debug, PartialEq)]
+pub struct OrderPage {
+    /// The orders on this page
+    pub orders: Vec<Order>,
+
+    /// Number of orders matching the query across all pages
+    pub total_count: usize,
+
+    /// Offset of the next page, or None if this is the last page
+    pub next_offset: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::AspirinEatsDb,
+        food::{MenuItem, OrderRequest},
+    };
+
+    /// Run the same checks against every kind of store, so the backends can't drift apart
+    fn for_each_store(check: impl Fn(&dyn OrderStore)) {
+        check(&AspirinEatsDb::in_memory().unwrap());
+        check(&MemoryStore::new());
+    }
+
+    fn test_order(customer: &str, total_cents: i64) -> Order {
+        let mut order = Order::try_from(OrderRequest {
+            customer: customer.to_string(),
+            food: vec![MenuItem::Fries],
+            promo_code: None,
+        })
+        .unwrap();
+        order.total = Money::from_cents(total_cents);
+        order
+    }
+
+    fn ids(page: &OrderPage) -> Vec<i64> {
+        page.orders.iter().map(|order| order.id.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_orders() {
+        for_each_store(|store| {
+            for (customer, total) in [("Amit", 800), ("Bea", 2000), ("Amit", 300), ("Cy", 800)] {
+                store
+                    .add_order(test_order(customer, total), "staff")
+                    .unwrap();
+            }
+            let mut expected = test_order("Bea", 2000);
+            expected.id = Some(2);
+            assert_eq!(store.get_order(2).unwrap(), Some(expected.clone()));
+            assert_eq!(store.get_order(5).unwrap(), None);
+
+            // estimates don't count as changes to the order
+            let estimate = Estimate {
+                ready_at: 100,
+                delivered_at: 200,
+            };
+            store.set_estimate(2, Some(estimate)).unwrap();
+            expected.estimate = Some(estimate);
+            assert_eq!(store.get_order(2).unwrap(), Some(expected));
+            assert_eq!(store.order_history(2).unwrap().len(), 1);
+            store.set_estimate(2, None).unwrap();
+
+            let query = OrderQuery {
+                sort: OrderSort::Total,
+                direction: SortDirection::Descending,
+                limit: Some(3),
+                ..OrderQuery::default()
+            };
+            let page = store.query_orders(&query).unwrap();
+            assert_eq!(ids(&page), vec![2, 4, 1]);
+            assert_eq!((page.total_count, page.next_offset), (4, Some(3)));
+            let page = store
+                .query_orders(&OrderQuery {
+                    customer: Some("Amit".to_string()),
+                    max_total: Some(Money::from_dollars(5)),
+                    ..OrderQuery::default()
+                })
+                .unwrap();
+            assert_eq!(ids(&page), vec![3]);
+
+            let updated = store
+                .update_status(1, OrderStatus::Preparing, Some(1), "staff")
+                .unwrap();
+            assert_eq!(
+                (updated.status, updated.version),
+                (OrderStatus::Preparing, 2)
+            );
+            assert!(matches!(
+                store.update_status(1, OrderStatus::Cancelled, Some(1), "staff"),
+                Err(AspirinEatsError::PreconditionFailed)
+            ));
+            assert!(matches!(
+                store.update_status(1, OrderStatus::Pending, None, "staff"),
+                Err(AspirinEatsError::InvalidTransition { .. })
+            ));
+            let page = store
+                .query_orders(&OrderQuery {
+                    status: Some(OrderStatus::Pending),
+                    ..OrderQuery::default()
+                })
+                .unwrap();
+            assert_eq!(ids(&page), vec![2, 3, 4]);
+
+            store
+                .update_status(3, OrderStatus::Cancelled, None, "staff")
+                .unwrap();
+            let unfinished = |after| {
+                let orders = store.unfinished_orders(after).unwrap();
+                orders
+                    .iter()
+                    .map(|order| order.id.unwrap())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(unfinished(0), vec![1, 2, 4]);
+            assert_eq!(unfinished(1), vec![2, 4]);
+            assert_eq!(
+                store.unfinished_orders(0).unwrap()[1].food,
+                vec![MenuItem::Fries]
+            );
+
+            store.remove_order(2, "staff").unwrap();
+            assert_eq!(store.get_order(2).unwrap(), None);
+            assert_eq!(unfinished(0), vec![1, 4]);
+            assert!(matches!(
+                store.update_status(2, OrderStatus::Preparing, None, "staff"),
+                Err(AspirinEatsError::NotFound)
+            ));
+            store.reset_orders("staff").unwrap();
+            assert_eq!(store.query_orders(&query).unwrap().total_count, 0);
+            assert_eq!(
+                store.add_order(test_order("Amit", 100), "staff").unwrap(),
+                5
+            );
+            assert_eq!(store.purge_deleted_orders("admin").unwrap(), 4);
+
+            let history: Vec<_> = store
+                .order_history(1)
+                .unwrap()
+                .into_iter()
+                .map(|event| (event.kind, event.status, event.actor))
+                .collect();
+            assert_eq!(
+                history,
+                vec![
+                    (
+                        OrderEventKind::Created,
+                        Some(OrderStatus::Pending),
+                        "staff".to_string()
+                    ),
+                    (
+                        OrderEventKind::StatusChanged,
+                        Some(OrderStatus::Preparing),
+                        "staff".to_string()
+                    ),
+                    (OrderEventKind::Deleted, None, "staff".to_string()),
+                    (OrderEventKind::Purged, None, "admin".to_string()),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_everything_else() {
+        for_each_store(|store| {
+            assert_eq!(store.current_price_list().unwrap(), PriceList::default());
+            let mut menu = serde_json::to_value(PriceList::default()).unwrap();
+            menu["fries"] = 6.into();
+            let changed: PriceList = serde_json::from_value(menu.clone()).unwrap();
+            assert!(store.install_price_list(&changed).is_err());
+            menu["version"] = 2.into();
+            let v2: PriceList = serde_json::from_value(menu).unwrap();
+            store.install_price_list(&v2).unwrap();
+            store.install_price_list(&PriceList::default()).unwrap();
+            assert_eq!(store.current_price_list().unwrap(), v2);
+            assert_eq!(store.get_price_list(3).unwrap(), None);
+
+            store.add_api_key("admin-token", &Role::Admin).unwrap();
+            assert_eq!(
+                store.find_api_key("admin-token").unwrap(),
+                Some(Role::Admin)
+            );
+            assert_eq!(store.find_api_key("other-token").unwrap(), None);
+            assert!(matches!(
+                store.add_api_key("admin-token", &Role::Staff),
+                Err(AspirinEatsError::DuplicateApiKey)
+            ));
+            assert_eq!(
+                store.find_api_key("admin-token").unwrap(),
+                Some(Role::Admin)
+            );
+
+            let respond = |id: i64| {
+                Ok(SavedResponse {
+                    request_hash: "hash".to_string(),
+                    status_code: 201,
+                    body: "{}".to_string(),
+                    location: Some(format!("/orders/{}", id)),
+                    etag: Some("\"1\"".to_string()),
+                })
+            };
+            let order = test_order("Amit", 100);
+            let id = store
+                .add_order_idempotently(order.clone(), "staff", "staff", "abc", &respond)
+                .unwrap();
+            assert_eq!(store.get_order(id).unwrap().unwrap().customer, "Amit");
+            assert_eq!(
+                store.find_idempotent_response("staff", "abc").unwrap(),
+                Some(respond(id).unwrap())
+            );
+            assert_eq!(
+                store.find_idempotent_response("admin", "abc").unwrap(),
+                None
+            );
+
+            // a key that is already used stores nothing, and neither does a failed response
+            let total = |store: &dyn OrderStore| {
+                store
+                    .query_orders(&OrderQuery::default())
+                    .unwrap()
+                    .total_count
+            };
+            let before = total(store);
+            assert!(matches!(
+                store.add_order_idempotently(order.clone(), "staff", "staff", "abc", &respond),
+                Err(AspirinEatsError::IdempotencyKeyReused)
+            ));
+            assert!(store
+                .add_order_idempotently(order, "staff", "staff", "def", &|_| {
+                    Err(AspirinEatsError::InvalidRequest)
+                })
+                .is_err());
+            assert_eq!(total(store), before);
+            assert_eq!(
+                store.find_idempotent_response("staff", "def").unwrap(),
+                None
+            );
+        });
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    sync::{mpsc::Receiver, Mutex, MutexGuard, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    check_new_price_list, check_status_change, OrderEvent, OrderEventKind, OrderPage, OrderQuery,
+    OrderSort, OrderStore, SavedResponse, SortDirection, IDEMPOTENCY_KEY_LIFETIME,
+};
+use crate::{
+    auth::{hash_token, Role},
+    error::AspirinEatsError,
+    events::OrderEvents,
+    food::{Estimate, Order, OrderStatus},
+    menu::PriceList,
+};
+
+/// An OrderStore that keeps everything in a few maps in memory, so it needs no database and
+/// forgets everything when it is dropped. It starts with the default price list installed, like
+/// a new SQLite database
+#[derive(Debug)]
+pub struct MemoryStore {
+    state: Mutex<State>,
+
+    /// Where changes to orders are published, for clients watching them
+    events: OrderEvents,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    orders: HashMap<i64, Order>,
+
+    /// Orders that have been deleted but not yet purged
+    deleted: HashMap<i64, Order>,
+
+    /// The ID given to the last order added. IDs are never reused, even after a reset
+    last_id: i64,
+
+    history: Vec<OrderEvent>,
+    price_lists: BTreeMap<u32, PriceList>,
+
+    /// Roles, by the hash of their token
+    api_keys: HashMap<String, Role>,
+
+    /// Saved responses and when they were saved, by scope and key
+    idempotent_responses: HashMap<(String, String), (SavedResponse, i64)>,
+}
+
+impl State {
+    /// The price list with the highest version
+    fn current_price_list(&self) -> Result<PriceList, AspirinEatsError> {
+        self.price_lists
+            .values()
+            .next_back()
+            .cloned()
+            .ok_or(AspirinEatsError::NotFound)
+    }
+
+    /// Store a new order, returning its ID
+    fn insert_order(&mut self, mut order: Order, actor: &str) -> i64 {
+        self.last_id += 1;
+        let id = self.last_id;
+        order.id = Some(id);
+        order.version = 1;
+        order.estimate = None;
+        self.record_event(
+            id,
+            OrderEventKind::Created,
+            Some(order.status.clone()),
+            actor,
+        );
+        self.orders.insert(id, order);
+        id
+    }
+
+    fn record_event(
+        &mut self,
+        order_id: i64,
+        kind: OrderEventKind,
+        status: Option<OrderStatus>,
+        actor: &str,
+    ) {
+        self.history.push(OrderEvent {
+            order_id,
+            kind,
+            status,
+            actor: actor.to_string(),
+            timestamp: unix_time(),
+        });
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        let prices = PriceList::default();
+        let state = State {
+            price_lists: BTreeMap::from([(prices.version(), prices)]),
+            ..State::default()
+        };
+        MemoryStore {
+            state: Mutex::new(state),
+            events: OrderEvents::new(),
+        }
+    }
+}
+
+impl OrderStore for MemoryStore {
+    fn add_order(&self, order: Order, actor: &str) -> Result<i64, AspirinEatsError> {
+        Ok(self.lock().insert_order(order, actor))
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(self.lock().orders.get(&id).cloned())
+    }
+
+    fn query_orders(&self, query: &OrderQuery) -> Result<OrderPage, AspirinEatsError> {
+        let state = self.lock();
+        let mut orders: Vec<&Order> = state
+            .orders
+            .values()
+            .filter(|order| matches_query(order, query))
+            .collect();
+        orders.sort_by(|a, b| {
+            let ordering = match query.sort {
+                OrderSort::Id => a.id.cmp(&b.id),
+                OrderSort::Total => a.total.cmp(&b.total).then(a.id.cmp(&b.id)),
+            };
+            match query.direction {
+                SortDirection::Ascending => ordering,
+                SortDirection::Descending => ordering.reverse(),
+            }
+        });
+
+        let total_count = orders.len();
+        let next_offset = query
+            .limit
+            .map(|limit| query.offset + limit)
+            .filter(|next| *next < total_count);
+        Ok(OrderPage {
+            orders: orders
+                .into_iter()
+                .skip(query.offset)
+                .take(query.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect(),
+            total_count,
+            next_offset,
+        })
+    }
+
+    fn update_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+        expected_version: Option<u32>,
+        actor: &str,
+    ) -> Result<Order, AspirinEatsError> {
+        let mut state = self.lock();
+        let order = state
+            .orders
+            .get_mut(&id)
+            .ok_or(AspirinEatsError::NotFound)?;
+        check_status_change(order, &status, expected_version)?;
+        order.status = status.clone();
+        order.version += 1;
+        let order = order.clone();
+
+        let kind = match status {
+            OrderStatus::Cancelled => OrderEventKind::Cancelled,
+            _ => OrderEventKind::StatusChanged,
+        };
+        state.record_event(id, kind, Some(status), actor);
+        self.events.publish(&order);
+        Ok(order)
+    }
+
+    fn unfinished_orders(&self, after: i64) -> Result<Vec<Order>, AspirinEatsError> {
+        let mut orders: Vec<Order> = self
+            .lock()
+            .orders
+            .values()
+            .filter(|order| order.id > Some(after) && !order.status.is_final())
+            .cloned()
+            .collect();
+        orders.sort_by_key(|order| order.id);
+        Ok(orders)
+    }
+
+    fn set_estimate(&self, id: i64, estimate: Option<Estimate>) -> Result<(), AspirinEatsError> {
+        if let Some(order) = self.lock().orders.get_mut(&id) {
+            order.estimate = estimate;
+        }
+        Ok(())
+    }
+
+    fn remove_order(&self, id: i64, actor: &str) -> Result<(), AspirinEatsError> {
+        let mut state = self.lock();
+        if let Some(order) = state.orders.remove(&id) {
+            state.deleted.insert(id, order);
+            state.record_event(id, OrderEventKind::Deleted, None, actor);
+        }
+        self.events.close(id);
+        Ok(())
+    }
+
+    fn reset_orders(&self, actor: &str) -> Result<(), AspirinEatsError> {
+        let mut state = self.lock();
+        let mut orders: Vec<_> = state.orders.drain().collect();
+        orders.sort_by_key(|(id, _)| *id);
+        for (id, order) in orders {
+            state.record_event(id, OrderEventKind::Deleted, None, actor);
+            state.deleted.insert(id, order);
+        }
+        self.events.close_all();
+        Ok(())
+    }
+
+    fn purge_deleted_orders(&self, actor: &str) -> Result<usize, AspirinEatsError> {
+        let mut state = self.lock();
+        let mut ids: Vec<_> = state.deleted.drain().map(|(id, _)| id).collect();
+        ids.sort();
+        for id in &ids {
+            state.record_event(*id, OrderEventKind::Purged, None, actor);
+        }
+        Ok(ids.len())
+    }
+
+    fn order_history(&self, id: i64) -> Result<Vec<OrderEvent>, AspirinEatsError> {
+        Ok(self
+            .lock()
+            .history
+            .iter()
+            .filter(|event| event.order_id == id)
+            .cloned()
+            .collect())
+    }
+
+    fn subscribe(&self, id: i64) -> Receiver<Order> {
+        self.events.subscribe(id)
+    }
+
+    fn install_price_list(&self, prices: &PriceList) -> Result<(), AspirinEatsError> {
+        // checked and installed under one lock, so two installs can't both pass the check
+        let mut state = self.lock();
+        let existing = state.price_lists.get(&prices.version()).cloned();
+        if check_new_price_list(prices, existing, &state.current_price_list()?)? {
+            state.price_lists.insert(prices.version(), prices.clone());
+        }
+        Ok(())
+    }
+
+    fn current_price_list(&self) -> Result<PriceList, AspirinEatsError> {
+        self.lock().current_price_list()
+    }
+
+    fn get_price_list(&self, version: u32) -> Result<Option<PriceList>, AspirinEatsError> {
+        Ok(self.lock().price_lists.get(&version).cloned())
+    }
+
+    fn add_api_key(&self, token: &str, role: &Role) -> Result<(), AspirinEatsError> {
+        match self.lock().api_keys.entry(hash_token(token)) {
+            Entry::Occupied(_) => Err(AspirinEatsError::DuplicateApiKey),
+            Entry::Vacant(entry) => {
+                entry.insert(role.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn find_api_key(&self, token: &str) -> Result<Option<Role>, AspirinEatsError> {
+        Ok(self.lock().api_keys.get(&hash_token(token)).cloned())
+    }
+
+    fn find_idempotent_response(
+        &self,
+        scope: &str,
+        key: &str,
+    ) -> Result<Option<SavedResponse>, AspirinEatsError> {
+        let state = self.lock();
+        let saved = state
+            .idempotent_responses
+            .get(&(scope.to_string(), key.to_string()));
+        Ok(saved
+            .filter(|(_, created_at)| !has_expired(*created_at))
+            .map(|(response, _)| response.clone()))
+    }
+
+    fn add_order_idempotently(
+        &self,
+        order: Order,
+        actor: &str,
+        scope: &str,
+        key: &str,
+        respond: &dyn Fn(i64) -> Result<SavedResponse, AspirinEatsError>,
+    ) -> Result<i64, AspirinEatsError> {
+        let mut state = self.lock();
+        state
+            .idempotent_responses
+            .retain(|_, (_, created_at)| !has_expired(*created_at));
+        let saved_key = (scope.to_string(), key.to_string());
+        if state.idempotent_responses.contains_key(&saved_key) {
+            return Err(AspirinEatsError::IdempotencyKeyReused);
+        }
+        // the response is built first, so nothing is stored if that fails. `respond` can't use
+        // the store, since it is still locked
+        let response = respond(state.last_id + 1)?;
+        let id = state.insert_order(order, actor);
+        state
+            .idempotent_responses
+            .insert(saved_key, (response, unix_time()));
+        Ok(id)
+    }
+}
+
+/// Whether an order passes the filters of a query. Sorting and pagination are left to the caller
+fn matches_query(order: &Order, query: &OrderQuery) -> bool {
+    query
+        .customer
+        .as_ref()
+        .is_none_or(|customer| order.customer == *customer)
+        && query
+            .status
+            .as_ref()
+            .is_none_or(|status| order.status == *status)
+        && query.min_total.is_none_or(|min| order.total >= min)
+        && query.max_total.is_none_or(|max| order.total <= max)
+}
+
+/// Whether a response saved at `created_at` is too old to replay
+fn has_expired(created_at: i64) -> bool {
+    created_at <= unix_time() - IDEMPOTENCY_KEY_LIFETIME.as_secs() as i64
+}
+
+/// The current time in seconds since the Unix epoch
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
@@ -0,0 +1,129 @@
+use std::{
+    fs,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::{auth, error::AspirinEatsError};
+
+/// A connection from a client that is being decrypted
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// A TLS certificate chain and private key loaded from PEM files, which can be reloaded while the
+/// server is running. New connections use the latest certificate, while connections that are
+/// already open carry on with the one they were set up with
+pub struct TlsCertificates {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    config: RwLock<Arc<ServerConfig>>,
+
+    /// What the certificate and key files held the last time they were loaded
+    loaded: Mutex<(Fingerprint, Fingerprint)>,
+}
+
+/// Identifies what a file holds: a hash of its contents, or when it was last modified if it can't
+/// be read. Hashing catches a file rewritten within the filesystem's timestamp granularity, which
+/// would keep the same modification time
+#[derive(Debug, PartialEq)]
+enum Fingerprint {
+    Contents(String),
+    Modified(Option<SystemTime>),
+}
+
+impl TlsCertificates {
+    /// Load a certificate chain and the private key that goes with it
+    pub fn from_pem_files<P: AsRef<Path>>(
+        cert_path: P,
+        key_path: P,
+    ) -> Result<Self, AspirinEatsError> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        let loaded = (fingerprint(cert_path), fingerprint(key_path));
+        Ok(TlsCertificates {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            config: RwLock::new(Arc::new(load_server_config(cert_path, key_path)?)),
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    /// Load the certificate and key again. If they can't be loaded, the current ones are kept
+    pub fn reload(&self) -> Result<(), AspirinEatsError> {
+        let mut last_loaded = self.loaded.lock().unwrap();
+        let loaded = (fingerprint(&self.cert_path), fingerprint(&self.key_path));
+        let config = load_server_config(&self.cert_path, &self.key_path)?;
+        *self.config.write().unwrap() = Arc::new(config);
+        *last_loaded = loaded;
+        Ok(())
+    }
+
+    /// Reload the certificate and key if either file has changed since they were last loaded,
+    /// returning whether they were reloaded
+    pub fn reload_if_changed(&self) -> Result<bool, AspirinEatsError> {
+        let current = (fingerprint(&self.cert_path), fingerprint(&self.key_path));
+        if *self.loaded.lock().unwrap() == current {
+            return Ok(false);
+        }
+        self.reload().map(|_| true)
+    }
+
+    /// Check for a new certificate every `interval` on a background thread, until the
+    /// certificates are dropped
+    pub fn spawn_reloader(self: &Arc<Self>, interval: Duration) {
+        let certificates = Arc::downgrade(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let Some(certificates) = Weak::upgrade(&certificates) else {
+                return;
+            };
+            match certificates.reload_if_changed() {
+                Ok(true) => eprintln!("Reloaded TLS certificate"),
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to reload TLS certificate: {}", e),
+            }
+        });
+    }
+
+    /// Start decrypting a connection with the current certificate. The handshake happens the
+    /// first time the stream is read from or written to
+    pub fn accept(&self, stream: TcpStream) -> Result<TlsStream, AspirinEatsError> {
+        let config = Arc::clone(&self.config.read().unwrap());
+        let connection = ServerConnection::new(config)
+            .map_err(|e| AspirinEatsError::InvalidCertificate(e.to_string()))?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+/// Build the TLS settings for a certificate chain and key, checking that they match
+fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, AspirinEatsError> {
+    let invalid = |path: &Path, e: &dyn std::fmt::Display| {
+        AspirinEatsError::InvalidCertificate(format!("{}: {}", path.display(), e))
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert_path, &e))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_path, &"no certificates found"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid(key_path, &e))?;
+
+    ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| invalid(cert_path, &e))
+}
+
+fn fingerprint(path: &Path) -> Fingerprint {
+    match fs::read(path) {
+        Ok(contents) => Fingerprint::Contents(auth::sha256_hex(&contents)),
+        Err(_) => Fingerprint::Modified(fs::metadata(path).and_then(|meta| meta.modified()).ok()),
+    }
+}
@@ -0,0 +1,536 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::Mutex,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use aspirin_eats::{
+    api,
+    auth::Role,
+    client::Client,
+    db::AspirinEatsDb,
+    food::{MenuItem, Order, OrderRequest, OrderStatus},
+    http::{HttpResponse, Method, RequestLimits},
+    server::{self, ServerConfig, Shutdown},
+    store::{OrderQuery, OrderStore},
+};
+
+/// Token of the staff API key every test origin server is started with
+const STAFF_TOKEN: &str = "staff-token";
+
+/// Start an origin server with an in-memory database on a free loopback port
+fn spawn_origin(config: ServerConfig) -> SocketAddr {
+    spawn_pooled_origin(config, 1).0
+}
+
+/// Start an origin server that serves `workers` connections at once, returning its address and
+/// the thread it runs on, which finishes once the server has shut down
+fn spawn_pooled_origin(config: ServerConfig, workers: usize) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.add_api_key(STAFF_TOKEN, &Role::Staff).unwrap();
+        let db = Mutex::new(db);
+        let router = api::router();
+        server::serve_pool(&listener, &config, workers, move |request| {
+            router.handle(&*db.lock().unwrap(), request)
+        });
+    });
+    (addr, server)
+}
+
+fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+/// Returns true once the server has closed its end of the connection
+fn is_closed(reader: &mut BufReader<TcpStream>) -> bool {
+    let mut buf = [0; 1];
+    matches!(reader.read(&mut buf), Ok(0))
+}
+
+#[test]
+fn test_keep_alive_serves_multiple_requests() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+
+    let order = r#"{"customer":"Amit","food":["Fries"]}"#;
+    write!(
+        stream,
+        "POST /orders HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+        STAFF_TOKEN,
+        order.len(),
+        order
+    )
+    .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 201);
+    assert_eq!(response.headers().get("Connection"), Some("keep-alive"));
+
+    write!(
+        stream,
+        "GET /orders/1 HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+        STAFF_TOKEN
+    )
+    .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 200);
+    assert!(response.body().contains("Amit"));
+}
+
+#[test]
+fn test_pipelined_requests_are_answered_in_order() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\r\n\
+              GET /missing HTTP/1.1\r\n\r\n\
+              PUT /orders HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}\
+              GET /orders HTTP/1.1\r\nX-Api-Key: staff-token\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+
+    let statuses: Vec<u16> = (0..4)
+        .map(|_| HttpResponse::read_from(&mut reader).unwrap().status_code())
+        .collect();
+    assert_eq!(statuses, vec![200, 404, 405, 200]);
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_connection_close_is_honoured() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.headers().get("Connection"), Some("close"));
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_http_1_0_closes_by_default() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    HttpResponse::read_from(&mut reader).unwrap();
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_max_requests_per_connection() {
+    let addr = spawn_origin(ServerConfig {
+        max_requests_per_connection: 2,
+        ..ServerConfig::default()
+    });
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let first = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(first.headers().get("Connection"), Some("keep-alive"));
+    let second = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(second.headers().get("Connection"), Some("close"));
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_idle_connections_time_out() {
+    let addr = spawn_origin(ServerConfig {
+        idle_timeout: Duration::from_millis(100),
+        ..ServerConfig::default()
+    });
+    let (mut stream, mut reader) = connect(addr);
+
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    HttpResponse::read_from(&mut reader).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert!(is_closed(&mut reader));
+
+    // the server moves on to the next client once the idle connection is closed
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(
+        HttpResponse::read_from(&mut reader).unwrap().status_code(),
+        200
+    );
+}
+
+#[test]
+fn test_malformed_request_closes_connection() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+
+    stream.write_all(b"NONSENSE\r\n\r\n").unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 400);
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_slow_requests_time_out() {
+    let addr = spawn_origin(ServerConfig {
+        request_timeout: Duration::from_millis(200),
+        ..ServerConfig::default()
+    });
+    let (mut stream, mut reader) = connect(addr);
+
+    // trickling in a header every so often doesn't keep the request alive
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        let _ = stream.write_all(b"X-Slow: yes\r\n");
+    }
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 408);
+    assert_eq!(response.headers().get("Connection"), Some("close"));
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_oversized_requests_are_rejected() {
+    let addr = spawn_origin(ServerConfig {
+        limits: RequestLimits {
+            max_header_size: 256,
+            max_body_size: 16,
+        },
+        ..ServerConfig::default()
+    });
+
+    let (mut stream, mut reader) = connect(addr);
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+        "a".repeat(256)
+    )
+    .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 431);
+    assert!(is_closed(&mut reader));
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"POST /orders HTTP/1.1\r\nContent-Length: 17\r\n\r\n")
+        .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 413);
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn test_request_ids_are_echoed() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"GET /missing HTTP/1.1\r\nX-Request-Id: abc-123\r\n\r\n")
+        .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 404);
+    assert_eq!(response.headers().get("X-Request-Id"), Some("abc-123"));
+    assert!(response.body().contains("abc-123"), "{}", response.body());
+
+    // requests without an id are given one
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    let id = response.headers().get("X-Request-Id").unwrap();
+    assert!(uuid::Uuid::parse_str(id).is_ok(), "{}", id);
+}
+
+#[test]
+fn test_slow_clients_do_not_block_others() {
+    let workers = 2;
+    let (addr, _server) = spawn_pooled_origin(ServerConfig::default(), workers);
+
+    // every worker is taken by a client that is idle between requests
+    let mut idle: Vec<_> = (0..workers)
+        .map(|_| {
+            let (mut stream, mut reader) = connect(addr);
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            assert_eq!(
+                HttpResponse::read_from(&mut reader).unwrap().status_code(),
+                200
+            );
+            (stream, reader)
+        })
+        .collect();
+    // and one more is trickling in a request
+    let (mut slow, _slow_reader) = connect(addr);
+    slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+    let (mut stream, mut reader) = connect(addr);
+    let started = Instant::now();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 200);
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // the idle clients were closed to make room
+    for (_, reader) in &mut idle {
+        assert!(is_closed(reader));
+    }
+}
+
+#[test]
+fn test_pool_queue_is_bounded() {
+    let config = ServerConfig {
+        max_queued_connections: 1,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_pooled_origin(config, 1);
+
+    // one client is being served and another is waiting for it, so a third is turned away
+    let (mut slow, _slow_reader) = connect(addr);
+    slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let (_queued, _queued_reader) = connect(addr);
+    thread::sleep(Duration::from_millis(100));
+    let (_, mut reader) = connect(addr);
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 503);
+    assert_eq!(response.headers().get("Connection"), Some("close"));
+}
+
+#[test]
+fn test_shutdown_drains_in_flight_requests() {
+    let shutdown = Shutdown::new();
+    let (addr, server) = spawn_pooled_origin(
+        ServerConfig {
+            shutdown: shutdown.clone(),
+            ..ServerConfig::default()
+        },
+        4,
+    );
+
+    // one client is idle between requests, and another is halfway through sending one
+    let (mut idle, mut idle_reader) = connect(addr);
+    idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    HttpResponse::read_from(&mut idle_reader).unwrap();
+    let order = r#"{"customer":"Amit","food":["Fries"]}"#;
+    let (mut busy, mut busy_reader) = connect(addr);
+    write!(
+        busy,
+        "POST /orders HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n",
+        STAFF_TOKEN,
+        order.len()
+    )
+    .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    shutdown.trigger();
+    assert!(is_closed(&mut idle_reader));
+    busy.write_all(order.as_bytes()).unwrap();
+    let response = HttpResponse::read_from(&mut busy_reader).unwrap();
+    assert_eq!(response.status_code(), 201);
+    assert_eq!(response.headers().get("Connection"), Some("close"));
+
+    server.join().unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+/// Read the next server-sent event from a stream, returning its data
+fn read_event(reader: &mut BufReader<TcpStream>) -> String {
+    let mut data = String::new();
+    loop {
+        let mut line = String::new();
+        assert!(reader.read_line(&mut line).unwrap() > 0, "stream ended");
+        match line.trim_end() {
+            "" if !data.is_empty() => return data,
+            line => {
+                if let Some(value) = line.strip_prefix("data: ") {
+                    data.push_str(value);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_order_status_changes_are_streamed() {
+    let (addr, _server) = spawn_pooled_origin(ServerConfig::default(), 2);
+    let client = Client::new(addr).with_token(STAFF_TOKEN);
+    let order = client.create_order(&fries_for("Amit"), None).unwrap();
+
+    let (mut stream, mut reader) = connect(addr);
+    write!(
+        stream,
+        "GET /orders/1/events HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+        STAFF_TOKEN
+    )
+    .unwrap();
+    let response = HttpResponse::read_head(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type"),
+        Some("text/event-stream")
+    );
+    assert_eq!(response.headers().get("Content-Length"), None);
+    let status = |data: String| Order::from_str(&data).unwrap().status;
+    assert_eq!(status(read_event(&mut reader)), OrderStatus::Pending);
+
+    let order = client
+        .update_status(1, OrderStatus::Preparing, order.version)
+        .unwrap();
+    assert_eq!(status(read_event(&mut reader)), OrderStatus::Preparing);
+    client
+        .update_status(1, OrderStatus::Cancelled, order.version)
+        .unwrap();
+    assert_eq!(status(read_event(&mut reader)), OrderStatus::Cancelled);
+    assert!(is_closed(&mut reader));
+}
+
+/// Start streaming the events of order 1, returning the connection and the response head
+fn open_event_stream(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>, HttpResponse) {
+    let (mut stream, mut reader) = connect(addr);
+    write!(
+        stream,
+        "GET /orders/1/events HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+        STAFF_TOKEN
+    )
+    .unwrap();
+    let response = HttpResponse::read_head(&mut reader).unwrap();
+    (stream, reader, response)
+}
+
+#[test]
+fn test_event_streams_do_not_hold_workers() {
+    let workers = 2;
+    let (addr, _server) = spawn_pooled_origin(ServerConfig::default(), workers);
+    let client = Client::new(addr).with_token(STAFF_TOKEN);
+    client.create_order(&fries_for("Amit"), None).unwrap();
+
+    // more streams than there are workers, each still open
+    let streams: Vec<_> = (0..workers * 2)
+        .map(|_| {
+            let (stream, mut reader, response) = open_event_stream(addr);
+            assert_eq!(response.status_code(), 200);
+            read_event(&mut reader);
+            (stream, reader)
+        })
+        .collect();
+
+    let (mut stream, mut reader) = connect(addr);
+    let started = Instant::now();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 200);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    drop(streams);
+}
+
+#[test]
+fn test_event_streams_are_capped_per_client() {
+    let config = ServerConfig {
+        max_streams_per_client: 2,
+        ..ServerConfig::default()
+    };
+    let streams = config.streams.clone();
+    let (addr, _server) = spawn_pooled_origin(config, 1);
+    let client = Client::new(addr).with_token(STAFF_TOKEN);
+    client.create_order(&fries_for("Amit"), None).unwrap();
+
+    let open: Vec<_> = (0..2).map(|_| open_event_stream(addr)).collect();
+    assert!(open
+        .iter()
+        .all(|(_, _, response)| response.status_code() == 200));
+    assert_eq!(streams.len(), 2);
+
+    let (_stream, mut reader, response) = open_event_stream(addr);
+    assert_eq!(response.status_code(), 429);
+    assert_eq!(response.headers().get("Retry-After"), Some("1"));
+    assert!(response.read_body(&mut reader, &Method::Get).is_ok());
+    assert_eq!(streams.len(), 2);
+}
+
+fn fries_for(customer: &str) -> OrderRequest {
+    OrderRequest {
+        customer: customer.to_string(),
+        food: vec![MenuItem::Fries],
+        promo_code: None,
+    }
+}
+
+#[test]
+fn test_client() {
+    let (addr, _server) = spawn_pooled_origin(ServerConfig::default(), 2);
+    let client = Client::new(addr).with_token(STAFF_TOKEN);
+
+    for customer in ["Amit", "Bea", "Amit"] {
+        client.create_order(&fries_for(customer), None).unwrap();
+    }
+    let retried = client
+        .create_order(&fries_for("Bea"), Some("key-1"))
+        .unwrap();
+    let replayed = client
+        .create_order(&fries_for("Bea"), Some("key-1"))
+        .unwrap();
+    assert_eq!(replayed, retried);
+
+    let order = client.get_order(2).unwrap();
+    assert_eq!(order.customer, "Bea");
+    assert_eq!(order.status, OrderStatus::Pending);
+    let order = client
+        .update_status(2, OrderStatus::Preparing, order.version)
+        .unwrap();
+    assert_eq!(order.status, OrderStatus::Preparing);
+
+    let query = OrderQuery {
+        customer: Some("Amit".to_string()),
+        limit: Some(1),
+        ..OrderQuery::default()
+    };
+    let page = client.list_orders(&query).unwrap();
+    assert_eq!(page.orders.len(), 1);
+    assert_eq!(page.orders[0].id, Some(1));
+    assert_eq!(page.total_count, 2);
+    assert_eq!(page.next_offset, Some(1));
+
+    // errors carry the status the server sent
+    let stale = client
+        .update_status(2, OrderStatus::Cancelled, 1)
+        .unwrap_err();
+    assert_eq!(stale.status(), Some(412));
+    assert_eq!(client.get_order(99).unwrap_err().status(), Some(404));
+    let anonymous = Client::new(addr).get_order(1).unwrap_err();
+    assert_eq!(anonymous.status(), Some(401));
+}
+
+#[test]
+fn test_openapi_document_is_served() {
+    let addr = spawn_origin(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"GET /openapi.json HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let response = HttpResponse::read_from(&mut reader).unwrap();
+    assert_eq!(response.status_code(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type"),
+        Some("application/json")
+    );
+    let spec: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+    assert_eq!(spec["openapi"], "3.0.3");
+    for schema in ["Order", "OrderRequest", "MenuItem", "Burger", "Error"] {
+        assert!(
+            spec["components"]["schemas"][schema].is_object(),
+            "{}",
+            schema
+        );
+    }
+    assert!(spec["paths"]["/orders/{id}"]["get"].is_object());
+}
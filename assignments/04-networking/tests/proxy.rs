@@ -0,0 +1,582 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use aspirin_eats::{
+    api,
+    auth::Role,
+    db::AspirinEatsDb,
+    http::{BodyStream, HttpRequest, HttpResponse, StreamChunk},
+    proxy::{Balancing, Proxy, ProxyConfig},
+    server::{self, ServerConfig},
+    store::OrderStore,
+    tls::TlsCertificates,
+};
+use rustls::{
+    crypto::ring,
+    pki_types::{CertificateDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+
+/// A stand-in origin server that answers every request with its name
+struct FakeOrigin {
+    addr: SocketAddr,
+
+    /// Number of connections the origin has accepted
+    connections: Arc<AtomicUsize>,
+
+    /// Number of requests the origin has answered
+    requests: Arc<AtomicUsize>,
+
+    /// Whether `GET /` succeeds, so health checks can be made to fail
+    healthy: Arc<AtomicBool>,
+}
+
+/// Start a fake origin. Requests for `/slow` take half a second to answer, requests for
+/// `/headers` are answered with the forwarding headers the proxy added, and requests for
+/// `/events` get an event stream with two events a quarter of a second apart
+fn spawn_fake_origin(name: &'static str) -> FakeOrigin {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let requests = Arc::new(AtomicUsize::new(0));
+    let healthy = Arc::new(AtomicBool::new(true));
+
+    let (connection_count, request_count, is_healthy) = (
+        Arc::clone(&connections),
+        Arc::clone(&requests),
+        Arc::clone(&healthy),
+    );
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            connection_count.fetch_add(1, Ordering::SeqCst);
+            let (request_count, is_healthy) = (Arc::clone(&request_count), Arc::clone(&is_healthy));
+            thread::spawn(move || {
+                let mut handler = |request: &HttpRequest| {
+                    request_count.fetch_add(1, Ordering::SeqCst);
+                    respond(name, request, &is_healthy)
+                };
+                let _ = server::handle_connection(
+                    stream.unwrap(),
+                    &ServerConfig::default(),
+                    &mut handler,
+                );
+            });
+        }
+    });
+
+    FakeOrigin {
+        addr,
+        connections,
+        requests,
+        healthy,
+    }
+}
+
+fn respond(name: &str, request: &HttpRequest, healthy: &AtomicBool) -> HttpResponse {
+    match request.path.as_str() {
+        "/" if !healthy.load(Ordering::SeqCst) => {
+            HttpResponse::new(500, "Internal Server Error", name)
+        }
+        "/headers" => {
+            let headers = ["X-Forwarded-For", "X-Forwarded-Proto", "X-Request-Id"]
+                .map(|name| request.headers.get(name).unwrap_or_default());
+            HttpResponse::new(200, "OK", &headers.join("\n"))
+        }
+        "/slow" => {
+            thread::sleep(Duration::from_millis(500));
+            HttpResponse::new(200, "OK", name)
+        }
+        "/events" => {
+            let mut events = vec!["data: 2\n\n", "data: 1\n\n"];
+            let started = Instant::now();
+            let stream = BodyStream::new(move |timeout| {
+                if events.len() == 1 && started.elapsed() < Duration::from_millis(250) {
+                    thread::sleep(timeout);
+                    return StreamChunk::Idle;
+                }
+                match events.pop() {
+                    Some(event) => StreamChunk::Data(event.as_bytes().to_vec()),
+                    None => StreamChunk::End,
+                }
+            });
+            HttpResponse::new(200, "OK", "")
+                .with_header("Content-Type", "text/event-stream")
+                .with_stream(stream)
+        }
+        // a single order may be cached for a while, but other responses don't say
+        path if path.starts_with("/orders/") => {
+            HttpResponse::new(200, "OK", name).with_header("Cache-Control", "max-age=60")
+        }
+        _ => HttpResponse::new(200, "OK", name),
+    }
+}
+
+/// Proxy settings with caching turned off, so every request reaches an origin
+fn uncached(balancing: Balancing) -> ProxyConfig {
+    ProxyConfig {
+        balancing,
+        cache_size: 0,
+        ..ProxyConfig::default()
+    }
+}
+
+/// Start a real origin server with an in-memory database, where `staff-token` is a staff API
+/// key and `amit-token` belongs to the customer Amit
+fn spawn_api_origin() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.add_api_key("staff-token", &Role::Staff).unwrap();
+        db.add_api_key("amit-token", &Role::Customer("Amit".to_string()))
+            .unwrap();
+        let db = Mutex::new(db);
+        let router = api::router();
+        server::serve_concurrently(&listener, &ServerConfig::default(), move |request| {
+            router.handle(&*db.lock().unwrap(), request)
+        });
+    });
+    addr
+}
+
+/// Start a proxy in front of the given origins on a free loopback port
+fn spawn_proxy(origins: &[&FakeOrigin], config: ProxyConfig) -> (SocketAddr, Arc<Proxy>) {
+    spawn_proxy_to(origins.iter().map(|origin| origin.addr).collect(), config)
+}
+
+/// Start a proxy in front of the origins at the given addresses
+fn spawn_proxy_to(upstreams: Vec<SocketAddr>, config: ProxyConfig) -> (SocketAddr, Arc<Proxy>) {
+    let proxy = Arc::new(Proxy::new(upstreams, config));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::clone(&proxy);
+    thread::spawn(move || {
+        server::serve_concurrently(&listener, &ServerConfig::default(), move |request| {
+            handler.handle(request)
+        });
+    });
+    (addr, proxy)
+}
+
+/// Start a proxy that accepts TLS connections in front of the given origins
+fn spawn_tls_proxy(origins: &[&FakeOrigin], certificates: Arc<TlsCertificates>) -> SocketAddr {
+    let proxy = Proxy::new(
+        origins.iter().map(|origin| origin.addr).collect(),
+        uncached(Balancing::RoundRobin),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        server::serve_tls_concurrently(
+            &listener,
+            &ServerConfig::default(),
+            certificates,
+            move |request| proxy.handle(request),
+        );
+    });
+    addr
+}
+
+/// Write a new self-signed certificate for `localhost` and its key to `dir`, returning the
+/// certificate so clients can trust it
+fn write_self_signed_cert(dir: &Path) -> CertificateDer<'static> {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+    fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem()).unwrap();
+    certified.cert.der().clone()
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aspirin-eats-tls-{}", uuid::Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+    dir
+}
+
+type TlsClient = StreamOwned<ClientConnection, TcpStream>;
+
+/// Open a TLS connection to `localhost` that only trusts `cert`
+fn connect_tls(addr: SocketAddr, cert: &CertificateDer<'static>) -> TlsClient {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    StreamOwned::new(connection, stream)
+}
+
+/// Send a GET request over a TLS connection, keeping the connection open
+fn get_tls(client: &mut TlsClient, path: &str) -> Result<HttpResponse, String> {
+    write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)
+        .map_err(|e| e.to_string())?;
+    HttpResponse::read_from(&mut BufReader::new(client)).map_err(|e| e.to_string())
+}
+
+/// Send a single request through its own connection
+fn send(addr: SocketAddr, method: &str, path: &str) -> HttpResponse {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path
+    )
+    .unwrap();
+    HttpResponse::read_from(&mut BufReader::new(stream)).unwrap()
+}
+
+fn get(addr: SocketAddr, path: &str) -> HttpResponse {
+    send(addr, "GET", path)
+}
+
+/// An address that refuses connections, since nothing can listen on port 0
+fn dead_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 0))
+}
+
+#[test]
+fn test_round_robin_across_origins() {
+    let (a, b) = (spawn_fake_origin("a"), spawn_fake_origin("b"));
+    let (addr, _proxy) = spawn_proxy(&[&a, &b], uncached(Balancing::RoundRobin));
+
+    let bodies: Vec<String> = (0..4)
+        .map(|_| get(addr, "/orders").body().to_string())
+        .collect();
+    assert_eq!(bodies, vec!["a", "b", "a", "b"]);
+}
+
+#[test]
+fn test_upstream_connections_are_reused() {
+    let origin = spawn_fake_origin("a");
+    let (addr, _proxy) = spawn_proxy(&[&origin], uncached(Balancing::RoundRobin));
+
+    for _ in 0..5 {
+        let response = get(addr, "/orders");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.headers().get("Connection"), Some("close"));
+    }
+    assert_eq!(origin.connections.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_requests_that_change_things_get_a_new_connection() {
+    let origin = spawn_fake_origin("a");
+    let (addr, _proxy) = spawn_proxy(&[&origin], uncached(Balancing::RoundRobin));
+
+    // an idle connection may be closed by the time it is used, and a POST that was already acted
+    // on can't be sent again on another one, so it is never given an idle connection
+    get(addr, "/orders");
+    assert_eq!(send(addr, "POST", "/orders").status_code(), 200);
+    assert_eq!(origin.connections.load(Ordering::SeqCst), 2);
+    get(addr, "/orders");
+    assert_eq!(origin.connections.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_connections_are_capped() {
+    let origin = spawn_fake_origin("a");
+    let proxy = Proxy::new(vec![origin.addr], uncached(Balancing::RoundRobin));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let config = ServerConfig {
+            max_connections: 1,
+            ..ServerConfig::default()
+        };
+        server::serve_concurrently(&listener, &config, move |request| proxy.handle(request));
+    });
+
+    let _idle = TcpStream::connect(addr).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // the connection is turned away without its request being read, so none is sent
+    let stream = TcpStream::connect(addr).unwrap();
+    let response = HttpResponse::read_from(&mut BufReader::new(stream)).unwrap();
+    assert_eq!(response.status_code(), 503);
+    assert_eq!(response.headers().get("Connection"), Some("close"));
+    assert_eq!(origin.requests.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_least_connections_avoids_busy_origin() {
+    let (a, b) = (spawn_fake_origin("a"), spawn_fake_origin("b"));
+    let (addr, _proxy) = spawn_proxy(&[&a, &b], uncached(Balancing::LeastConnections));
+
+    let slow = thread::spawn(move || get(addr, "/slow").body().to_string());
+    thread::sleep(Duration::from_millis(100));
+    let bodies: Vec<String> = (0..3)
+        .map(|_| get(addr, "/orders").body().to_string())
+        .collect();
+    let busy = slow.join().unwrap();
+    assert!(bodies.iter().all(|body| *body != busy), "{:?}", bodies);
+}
+
+#[test]
+fn test_unhealthy_origins_are_ejected_and_restored() {
+    let (a, b) = (spawn_fake_origin("a"), spawn_fake_origin("b"));
+    let (addr, proxy) = spawn_proxy(&[&a, &b], uncached(Balancing::RoundRobin));
+
+    a.healthy.store(false, Ordering::SeqCst);
+    proxy.check_health();
+    assert_eq!(proxy.healthy_upstreams(), vec![b.addr]);
+    for _ in 0..3 {
+        assert_eq!(get(addr, "/orders").body(), "b");
+    }
+
+    a.healthy.store(true, Ordering::SeqCst);
+    proxy.check_health();
+    assert_eq!(proxy.healthy_upstreams(), vec![a.addr, b.addr]);
+}
+
+#[test]
+fn test_unreachable_origins_are_skipped() {
+    let origin = spawn_fake_origin("a");
+    let proxy = Proxy::new(
+        vec![dead_addr(), origin.addr],
+        uncached(Balancing::RoundRobin),
+    );
+    let request = HttpRequest::read_from(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+
+    for _ in 0..3 {
+        assert_eq!(proxy.handle(&request).body(), "a");
+    }
+    assert_eq!(proxy.healthy_upstreams(), vec![origin.addr]);
+
+    let proxy = Proxy::new(vec![dead_addr()], ProxyConfig::default());
+    assert_eq!(proxy.handle(&request).status_code(), 503);
+}
+
+#[test]
+fn test_responses_are_cached_until_orders_change() {
+    let origin = spawn_fake_origin("a");
+    let (addr, _proxy) = spawn_proxy(&[&origin], ProxyConfig::default());
+
+    let response = get(addr, "/orders/1");
+    assert_eq!(response.headers().get("X-Cache"), Some("MISS"));
+    let response = get(addr, "/orders/1");
+    assert_eq!(response.headers().get("X-Cache"), Some("HIT"));
+    assert_eq!(response.headers().get("Age"), Some("0"));
+    assert_eq!(response.body(), "a");
+    get(addr, "/menu");
+    assert_eq!(origin.requests.load(Ordering::SeqCst), 2);
+
+    // lists of orders change too often to cache without being told to
+    for _ in 0..2 {
+        let response = get(addr, "/orders");
+        assert_eq!(response.headers().get("X-Cache"), Some("MISS"));
+    }
+    assert_eq!(origin.requests.load(Ordering::SeqCst), 4);
+
+    let response = send(addr, "PATCH", "/orders/2");
+    assert_eq!(response.headers().get("X-Cache"), None);
+    assert_eq!(
+        get(addr, "/orders/1").headers().get("X-Cache"),
+        Some("MISS")
+    );
+    assert_eq!(get(addr, "/menu").headers().get("X-Cache"), Some("HIT"));
+    assert_eq!(origin.requests.load(Ordering::SeqCst), 6);
+}
+
+#[test]
+fn test_orders_are_revalidated_for_each_client() {
+    let (addr, _proxy) = spawn_proxy_to(vec![spawn_api_origin()], ProxyConfig::default());
+    let send_as = |token: &str, head: &str, body: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{}\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            head,
+            token,
+            body.len(),
+            body
+        )
+        .unwrap();
+        HttpResponse::read_from(&mut BufReader::new(stream)).unwrap()
+    };
+    let get_as = |token: &str| send_as(token, "GET /orders/1 HTTP/1.1", "");
+    let order = r#"{"customer":"Amit","food":["Fries"]}"#;
+    let response = send_as("amit-token", "POST /orders HTTP/1.1", order);
+    assert_eq!(response.status_code(), 201);
+
+    let first = get_as("amit-token");
+    assert_eq!(first.headers().get("X-Cache"), Some("MISS"));
+    let again = get_as("amit-token");
+    assert_eq!(again.status_code(), 200);
+    assert_eq!(again.headers().get("X-Cache"), Some("REVALIDATED"));
+    assert_eq!(again.body(), first.body());
+    let etag = again.headers().get("ETag").unwrap();
+    let head = format!("GET /orders/1 HTTP/1.1\r\nIf-None-Match: {}", etag);
+    assert_eq!(send_as("amit-token", &head, "").status_code(), 304);
+
+    // the origin still checks who is asking, and each client has its own copy
+    assert_eq!(get_as("unknown-token").status_code(), 401);
+    assert_eq!(get_as("staff-token").headers().get("X-Cache"), Some("MISS"));
+
+    // a change is seen straight away
+    let head = "PATCH /orders/1 HTTP/1.1\r\nIf-Match: *";
+    let response = send_as("staff-token", head, r#"{"status":"Preparing"}"#);
+    assert_eq!(response.status_code(), 200);
+    let response = get_as("amit-token");
+    assert_eq!(response.headers().get("X-Cache"), Some("MISS"));
+    assert!(response.body().contains("Preparing"));
+}
+
+#[test]
+fn test_event_streams_are_passed_through() {
+    let origin = spawn_fake_origin("a");
+    let (addr, _proxy) = spawn_proxy(&[&origin], ProxyConfig::default());
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let started = Instant::now();
+    stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    let response = HttpResponse::read_head(&mut reader).unwrap();
+    assert_eq!(
+        response.headers().get("Content-Type"),
+        Some("text/event-stream")
+    );
+    assert_eq!(response.headers().get("Connection"), Some("close"));
+
+    // the first event arrives before the origin has sent the second
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "data: 1\n");
+    assert!(started.elapsed() < Duration::from_millis(250));
+
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "\ndata: 2\n\n");
+    assert!(started.elapsed() >= Duration::from_millis(250));
+}
+
+#[test]
+fn test_clients_are_rate_limited() {
+    let origin = spawn_fake_origin("a");
+    let (addr, _proxy) = spawn_proxy(
+        &[&origin],
+        ProxyConfig {
+            rate_limit: 0.5,
+            rate_limit_burst: 2,
+            ..uncached(Balancing::RoundRobin)
+        },
+    );
+
+    assert_eq!(get(addr, "/orders").status_code(), 200);
+    assert_eq!(get(addr, "/orders").status_code(), 200);
+    let response = get(addr, "/orders");
+    assert_eq!(response.status_code(), 429);
+    assert_eq!(response.headers().get("Retry-After"), Some("2"));
+    assert_eq!(origin.requests.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_forwarding_headers_and_request_ids() {
+    let origin = spawn_fake_origin("a");
+    let (addr, _proxy) = spawn_proxy(&[&origin], uncached(Balancing::RoundRobin));
+
+    let response = get(addr, "/headers");
+    let forwarded: Vec<&str> = response.body().lines().collect();
+    let id = response.headers().get("X-Request-Id").unwrap();
+    assert_eq!(forwarded, vec!["127.0.0.1", "http", id]);
+    assert!(uuid::Uuid::parse_str(id).is_ok(), "{}", id);
+
+    // a client can't pretend to be somewhere else
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"GET /headers HTTP/1.1\r\nX-Forwarded-For: 10.9.9.9\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let response = HttpResponse::read_from(&mut BufReader::new(stream)).unwrap();
+    assert_eq!(response.body().lines().next(), Some("127.0.0.1"));
+
+    // errors from the proxy itself quote the id too
+    let proxy = Proxy::new(vec![dead_addr()], ProxyConfig::default());
+    let request =
+        HttpRequest::read_from(&mut "GET / HTTP/1.1\r\nX-Request-Id: abc-123\r\n\r\n".as_bytes())
+            .unwrap();
+    let response = proxy.handle(&request);
+    assert_eq!(response.status_code(), 503);
+    assert!(response.body().ends_with("Request ID: abc-123"));
+}
+
+#[test]
+fn test_tls_is_terminated_at_the_proxy() {
+    let dir = temp_dir();
+    let cert = write_self_signed_cert(&dir);
+    let certificates =
+        TlsCertificates::from_pem_files(dir.join("cert.pem"), dir.join("key.pem")).unwrap();
+    let origin = spawn_fake_origin("a");
+    let addr = spawn_tls_proxy(&[&origin], Arc::new(certificates));
+
+    let response = get_tls(&mut connect_tls(addr, &cert), "/headers").unwrap();
+    assert_eq!(response.status_code(), 200);
+    let forwarded: Vec<&str> = response.body().lines().collect();
+    assert_eq!(forwarded[..2], ["127.0.0.1", "https"]);
+
+    // plain HTTP isn't understood
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert!(HttpResponse::read_from(&mut BufReader::new(stream)).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_certificate_reload_keeps_open_connections() {
+    let dir = temp_dir();
+    let old_cert = write_self_signed_cert(&dir);
+    let certificates = Arc::new(
+        TlsCertificates::from_pem_files(dir.join("cert.pem"), dir.join("key.pem")).unwrap(),
+    );
+    let origin = spawn_fake_origin("a");
+    let addr = spawn_tls_proxy(&[&origin], Arc::clone(&certificates));
+
+    let mut open = connect_tls(addr, &old_cert);
+    assert_eq!(get_tls(&mut open, "/orders").unwrap().body(), "a");
+
+    // a rewrite within the filesystem's timestamp granularity leaves the modification times as
+    // they were, which is made certain here by putting them back
+    let modified = |name: &str| fs::metadata(dir.join(name)).unwrap().modified().unwrap();
+    let before = ["cert.pem", "key.pem"].map(|name| (name, modified(name)));
+    let new_cert = write_self_signed_cert(&dir);
+    for (name, time) in before {
+        let file = File::options().write(true).open(dir.join(name)).unwrap();
+        file.set_modified(time).unwrap();
+        assert_eq!(modified(name), time);
+    }
+    assert!(certificates.reload_if_changed().unwrap());
+    assert!(!certificates.reload_if_changed().unwrap());
+
+    assert_eq!(get_tls(&mut open, "/orders").unwrap().body(), "a");
+    assert!(get_tls(&mut connect_tls(addr, &old_cert), "/orders").is_err());
+    assert_eq!(
+        get_tls(&mut connect_tls(addr, &new_cert), "/orders")
+            .unwrap()
+            .body(),
+        "a"
+    );
+
+    // a broken certificate is rejected and the working one kept
+    fs::write(dir.join("key.pem"), "not a key").unwrap();
+    assert!(certificates.reload().is_err());
+    assert!(get_tls(&mut connect_tls(addr, &new_cert), "/orders").is_ok());
+    fs::remove_dir_all(dir).unwrap();
+}